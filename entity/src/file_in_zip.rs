@@ -16,6 +16,15 @@ pub struct Model {
     pub file_type: String,
     pub extension: String,
     pub takeout_zip_id: i32,
+    pub variant: String,
+    pub group_id: Option<i32>,
+    pub duplicate_of_id: Option<i32>,
+    pub archive_mtime: Option<i64>,
+    pub archive_path: Option<String>,
+    pub content_hash: Option<String>,
+    pub size: Option<i64>,
+    /// How the file was put into the library, `None` while it is only extracted.
+    pub placement_mode: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -28,6 +37,12 @@ pub enum Relation {
         on_delete = "Cascade"
     )]
     TakeoutZip,
+    #[sea_orm(has_many = "super::album_media::Entity")]
+    AlbumMedia,
+    #[sea_orm(has_many = "super::media_file::Entity")]
+    MediaFile,
+    #[sea_orm(has_many = "super::operation::Entity")]
+    Operation,
 }
 
 impl Related<super::takeout_zip::Entity> for Entity {
@@ -36,4 +51,32 @@ impl Related<super::takeout_zip::Entity> for Entity {
     }
 }
 
+impl Related<super::album_media::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AlbumMedia.def()
+    }
+}
+
+impl Related<super::media_file::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MediaFile.def()
+    }
+}
+
+impl Related<super::operation::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Operation.def()
+    }
+}
+
+impl Related<super::album::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::album_media::Relation::Album.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(super::album_media::Relation::FileInZip.def().rev())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
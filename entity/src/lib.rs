@@ -4,6 +4,12 @@ pub mod takeout_zip;
 pub mod prelude;
 pub mod file_in_zip;
 pub mod media_file;
+pub mod album;
+pub mod album_media;
+pub mod export_item;
+pub mod near_duplicate_cluster;
+pub mod near_duplicate_member;
+pub mod operation;
 
 impl From<&TakeoutZip> for Row<'_> {
     fn from(df: &TakeoutZip) -> Self {
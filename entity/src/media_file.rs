@@ -2,7 +2,7 @@
 
 use sea_orm::entity::prelude::*;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "media_file")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -10,9 +10,113 @@ pub struct Model {
     pub file_name: String,
     pub path: String,
     pub json_meta: Json,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub date_source: Option<String>,
+    pub date_confidence: Option<String>,
+    pub utc_offset: Option<i32>,
+    pub taken_at: Option<DateTimeWithTimeZone>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
+    pub size: Option<i64>,
+    pub content_hash: Option<String>,
+    pub favorite: bool,
+    pub archived: bool,
+    pub trashed: bool,
+    pub file_in_zip_id: Option<i32>,
+    pub sidecar_file_in_zip_id: Option<i32>,
+    pub perceptual_hash: Option<i64>,
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub city: Option<String>,
+    /// The catalog columns were filled, even if no date could be found.
+    pub catalogued: bool,
+    /// The location was looked up in the gazetteer, even if no place lies
+    /// near it.
+    pub place_looked_up: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::file_in_zip::Entity",
+        from = "Column::FileInZipId",
+        to = "super::file_in_zip::Column::Id",
+        on_update = "Cascade",
+        on_delete = "SetNull"
+    )]
+    FileInZip,
+    #[sea_orm(
+        belongs_to = "super::file_in_zip::Entity",
+        from = "Column::SidecarFileInZipId",
+        to = "super::file_in_zip::Column::Id",
+        on_update = "Cascade",
+        on_delete = "SetNull"
+    )]
+    SidecarFileInZip,
+    #[sea_orm(has_many = "super::near_duplicate_member::Entity")]
+    NearDuplicateMember,
+    #[sea_orm(has_many = "super::export_item::Entity")]
+    ExportItem,
+}
+
+impl Related<super::file_in_zip::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::FileInZip.def()
+    }
+}
+
+impl Related<super::takeout_zip::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::file_in_zip::Relation::TakeoutZip.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(Relation::FileInZip.def())
+    }
+}
+
+impl Related<super::near_duplicate_member::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::NearDuplicateMember.def()
+    }
+}
+
+impl Related<super::export_item::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ExportItem.def()
+    }
+}
+
+/// From a catalog row to the sidecar it was placed with.
+#[derive(Debug)]
+pub struct MediaFileToSidecar;
+
+impl Linked for MediaFileToSidecar {
+    type FromEntity = Entity;
+    type ToEntity = super::file_in_zip::Entity;
+
+    fn link(&self) -> Vec<RelationDef> {
+        vec![Relation::SidecarFileInZip.def()]
+    }
+}
+
+/// From a sidecar to the catalog rows placed with it.
+#[derive(Debug)]
+pub struct SidecarToMediaFile;
+
+impl Linked for SidecarToMediaFile {
+    type FromEntity = super::file_in_zip::Entity;
+    type ToEntity = Entity;
+
+    fn link(&self) -> Vec<RelationDef> {
+        vec![Relation::SidecarFileInZip.def().rev()]
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -2,6 +2,12 @@
 
 pub mod prelude;
 
+pub mod album;
+pub mod album_media;
+pub mod export_item;
 pub mod file_in_zip;
 pub mod media_file;
+pub mod near_duplicate_cluster;
+pub mod near_duplicate_member;
+pub mod operation;
 pub mod takeout_zip;
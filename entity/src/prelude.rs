@@ -1,5 +1,11 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.0
 
+pub use super::album::Entity as Album;
+pub use super::album_media::Entity as AlbumMedia;
+pub use super::export_item::Entity as ExportItem;
 pub use super::file_in_zip::Entity as FileInZip;
 pub use super::media_file::Entity as MediaFile;
+pub use super::near_duplicate_cluster::Entity as NearDuplicateCluster;
+pub use super::near_duplicate_member::Entity as NearDuplicateMember;
+pub use super::operation::Entity as Operation;
 pub use super::takeout_zip::Entity as TakeoutZip;
@@ -26,4 +26,14 @@ impl Related<super::file_in_zip::Entity> for Entity {
     }
 }
 
+impl Related<super::media_file::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::file_in_zip::Relation::MediaFile.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(super::file_in_zip::Relation::TakeoutZip.def().rev())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
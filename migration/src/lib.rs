@@ -2,6 +2,22 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_tables;
 mod m20241230_130559_create_media_file_table;
+mod m20250104_101500_add_variant_to_file_in_zip;
+mod m20250106_084500_add_group_id_to_file_in_zip;
+mod m20250110_193000_create_album_tables;
+mod m20250114_120000_add_location_to_media_file;
+mod m20250118_091500_add_date_source;
+mod m20250121_104500_add_utc_offset_to_media_file;
+mod m20250125_140000_add_catalog_columns_to_media_file;
+mod m20250128_163000_link_media_file_to_file_in_zip;
+mod m20250201_090000_add_content_hash_to_file_in_zip;
+mod m20250204_101500_create_near_duplicate_tables;
+mod m20250207_143000_add_placement_mode_to_file_in_zip;
+mod m20250211_094500_create_operation_table;
+mod m20250214_113000_create_export_item_table;
+mod m20250218_101500_add_place_to_media_file;
+mod m20250221_090000_add_catalogued_to_media_file;
+mod m20250224_093000_add_place_looked_up_to_media_file;
 
 pub struct Migrator;
 
@@ -11,6 +27,22 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20220101_000001_create_tables::Migration),
             Box::new(m20241230_130559_create_media_file_table::Migration),
+            Box::new(m20250104_101500_add_variant_to_file_in_zip::Migration),
+            Box::new(m20250106_084500_add_group_id_to_file_in_zip::Migration),
+            Box::new(m20250110_193000_create_album_tables::Migration),
+            Box::new(m20250114_120000_add_location_to_media_file::Migration),
+            Box::new(m20250118_091500_add_date_source::Migration),
+            Box::new(m20250121_104500_add_utc_offset_to_media_file::Migration),
+            Box::new(m20250125_140000_add_catalog_columns_to_media_file::Migration),
+            Box::new(m20250128_163000_link_media_file_to_file_in_zip::Migration),
+            Box::new(m20250201_090000_add_content_hash_to_file_in_zip::Migration),
+            Box::new(m20250204_101500_create_near_duplicate_tables::Migration),
+            Box::new(m20250207_143000_add_placement_mode_to_file_in_zip::Migration),
+            Box::new(m20250211_094500_create_operation_table::Migration),
+            Box::new(m20250214_113000_create_export_item_table::Migration),
+            Box::new(m20250218_101500_add_place_to_media_file::Migration),
+            Box::new(m20250221_090000_add_catalogued_to_media_file::Migration),
+            Box::new(m20250224_093000_add_place_looked_up_to_media_file::Migration),
         ]
     }
 }
@@ -0,0 +1,100 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Album::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Album::Id))
+                    .col(string(Album::Title))
+                    .col(string_uniq(Album::Path))
+                    .col(json(Album::JsonMeta))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(AlbumMedia::Table)
+                    .if_not_exists()
+                    .col(pk_auto(AlbumMedia::Id))
+                    .col(integer(AlbumMedia::AlbumId))
+                    .col(integer(AlbumMedia::FileInZipId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_album_media_album")
+                            .from(AlbumMedia::Table, AlbumMedia::AlbumId)
+                            .to(Album::Table, Album::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_album_media_file_in_zip")
+                            .from(AlbumMedia::Table, AlbumMedia::FileInZipId)
+                            .to(FileInZip::Table, FileInZip::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileInZip::Table)
+                    .add_column(integer_null(FileInZip::DuplicateOfId))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileInZip::Table)
+                    .drop_column(FileInZip::DuplicateOfId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AlbumMedia::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Album::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Album {
+    Table,
+    Id,
+    Title,
+    Path,
+    JsonMeta,
+}
+
+#[derive(DeriveIden)]
+enum AlbumMedia {
+    Table,
+    Id,
+    AlbumId,
+    FileInZipId,
+}
+
+#[derive(DeriveIden)]
+enum FileInZip {
+    Table,
+    Id,
+    DuplicateOfId,
+}
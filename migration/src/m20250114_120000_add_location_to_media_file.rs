@@ -0,0 +1,38 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite alters one column at a time.
+        for column in [
+            double_null(MediaFile::Latitude),
+            double_null(MediaFile::Longitude),
+            double_null(MediaFile::Altitude),
+        ] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).add_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [MediaFile::Latitude, MediaFile::Longitude, MediaFile::Altitude] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).drop_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    Latitude,
+    Longitude,
+    Altitude,
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileInZip::Table)
+                    .add_column(big_integer_null(FileInZip::ArchiveMtime))
+                    .to_owned(),
+            )
+            .await?;
+        // SQLite alters one column at a time.
+        for column in [string_null(MediaFile::DateSource), string_null(MediaFile::DateConfidence)] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).add_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [MediaFile::DateSource, MediaFile::DateConfidence] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).drop_column(column).to_owned())
+                .await?;
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileInZip::Table)
+                    .drop_column(FileInZip::ArchiveMtime)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileInZip {
+    Table,
+    ArchiveMtime,
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    DateSource,
+    DateConfidence,
+}
@@ -0,0 +1,103 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite alters one column at a time.
+        for column in [
+            timestamp_with_time_zone_null(MediaFile::TakenAt),
+            string_null(MediaFile::CameraMake),
+            string_null(MediaFile::CameraModel),
+            integer_null(MediaFile::Width),
+            integer_null(MediaFile::Height),
+            string_null(MediaFile::MimeType),
+            big_integer_null(MediaFile::Size),
+            string_null(MediaFile::ContentHash),
+            boolean(MediaFile::Favorite).default(false).to_owned(),
+            boolean(MediaFile::Archived).default(false).to_owned(),
+            boolean(MediaFile::Trashed).default(false).to_owned(),
+        ] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).add_column(column).to_owned())
+                .await?;
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_media_file_taken_at")
+                    .table(MediaFile::Table)
+                    .col(MediaFile::TakenAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_media_file_content_hash")
+                    .table(MediaFile::Table)
+                    .col(MediaFile::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_media_file_content_hash")
+                    .table(MediaFile::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_media_file_taken_at")
+                    .table(MediaFile::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        for column in [
+            MediaFile::TakenAt,
+            MediaFile::CameraMake,
+            MediaFile::CameraModel,
+            MediaFile::Width,
+            MediaFile::Height,
+            MediaFile::MimeType,
+            MediaFile::Size,
+            MediaFile::ContentHash,
+            MediaFile::Favorite,
+            MediaFile::Archived,
+            MediaFile::Trashed,
+        ] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).drop_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    TakenAt,
+    CameraMake,
+    CameraModel,
+    Width,
+    Height,
+    MimeType,
+    Size,
+    ContentHash,
+    Favorite,
+    Archived,
+    Trashed,
+}
@@ -0,0 +1,112 @@
+use sea_orm_migration::sea_orm::DatabaseBackend;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileInZip::Table)
+                    .add_column(string_null(FileInZip::ArchivePath))
+                    .to_owned(),
+            )
+            .await?;
+
+        // SQLite alters one column at a time and cannot add a foreign key to
+        // an existing table, so there the links are plain columns.
+        for column in [integer_null(MediaFile::FileInZipId), integer_null(MediaFile::SidecarFileInZipId)] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).add_column(column).to_owned())
+                .await?;
+        }
+        if manager.get_database_backend() != DatabaseBackend::Sqlite {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MediaFile::Table)
+                        .add_foreign_key(
+                            TableForeignKey::new()
+                                .name("fk_media_file_file_in_zip")
+                                .from_tbl(MediaFile::Table)
+                                .from_col(MediaFile::FileInZipId)
+                                .to_tbl(FileInZip::Table)
+                                .to_col(FileInZip::Id)
+                                .on_delete(ForeignKeyAction::SetNull)
+                                .on_update(ForeignKeyAction::Cascade),
+                        )
+                        .add_foreign_key(
+                            TableForeignKey::new()
+                                .name("fk_media_file_sidecar_file_in_zip")
+                                .from_tbl(MediaFile::Table)
+                                .from_col(MediaFile::SidecarFileInZipId)
+                                .to_tbl(FileInZip::Table)
+                                .to_col(FileInZip::Id)
+                                .on_delete(ForeignKeyAction::SetNull)
+                                .on_update(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // Rows written so far point at the path the media was moved to.
+        // Correlated subqueries work on every backend, `UPDATE ... FROM` does not.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE media_file SET \
+                 file_in_zip_id = (SELECT file_in_zip.id FROM file_in_zip \
+                 WHERE file_in_zip.path = media_file.path AND file_in_zip.file_type = 'media' LIMIT 1), \
+                 sidecar_file_in_zip_id = (SELECT file_in_zip.related_id FROM file_in_zip \
+                 WHERE file_in_zip.path = media_file.path AND file_in_zip.file_type = 'media' LIMIT 1)",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Sqlite {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MediaFile::Table)
+                        .drop_foreign_key(Alias::new("fk_media_file_sidecar_file_in_zip"))
+                        .drop_foreign_key(Alias::new("fk_media_file_file_in_zip"))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        for column in [MediaFile::SidecarFileInZipId, MediaFile::FileInZipId] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).drop_column(column).to_owned())
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileInZip::Table)
+                    .drop_column(FileInZip::ArchivePath)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileInZip {
+    Table,
+    Id,
+    ArchivePath,
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    FileInZipId,
+    SidecarFileInZipId,
+}
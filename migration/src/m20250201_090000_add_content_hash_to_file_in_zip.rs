@@ -0,0 +1,51 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite alters one column at a time.
+        for column in [string_null(FileInZip::ContentHash), big_integer_null(FileInZip::Size)] {
+            manager
+                .alter_table(Table::alter().table(FileInZip::Table).add_column(column).to_owned())
+                .await?;
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_file_in_zip_content_hash")
+                    .table(FileInZip::Table)
+                    .col(FileInZip::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_file_in_zip_content_hash")
+                    .table(FileInZip::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        for column in [FileInZip::ContentHash, FileInZip::Size] {
+            manager
+                .alter_table(Table::alter().table(FileInZip::Table).drop_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileInZip {
+    Table,
+    ContentHash,
+    Size,
+}
@@ -0,0 +1,108 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaFile::Table)
+                    .add_column(big_integer_null(MediaFile::PerceptualHash))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(NearDuplicateCluster::Table)
+                    .if_not_exists()
+                    .col(pk_auto(NearDuplicateCluster::Id))
+                    .col(string(NearDuplicateCluster::Status))
+                    .col(integer_null(NearDuplicateCluster::KeptMediaFileId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_near_duplicate_cluster_kept_media_file")
+                            .from(NearDuplicateCluster::Table, NearDuplicateCluster::KeptMediaFileId)
+                            .to(MediaFile::Table, MediaFile::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(NearDuplicateMember::Table)
+                    .if_not_exists()
+                    .col(pk_auto(NearDuplicateMember::Id))
+                    .col(integer(NearDuplicateMember::ClusterId))
+                    .col(integer(NearDuplicateMember::MediaFileId))
+                    .col(integer(NearDuplicateMember::Distance))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_near_duplicate_member_cluster")
+                            .from(NearDuplicateMember::Table, NearDuplicateMember::ClusterId)
+                            .to(NearDuplicateCluster::Table, NearDuplicateCluster::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_near_duplicate_member_media_file")
+                            .from(NearDuplicateMember::Table, NearDuplicateMember::MediaFileId)
+                            .to(MediaFile::Table, MediaFile::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(NearDuplicateMember::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(NearDuplicateCluster::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaFile::Table)
+                    .drop_column(MediaFile::PerceptualHash)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    Id,
+    PerceptualHash,
+}
+
+#[derive(DeriveIden)]
+enum NearDuplicateCluster {
+    Table,
+    Id,
+    Status,
+    KeptMediaFileId,
+}
+
+#[derive(DeriveIden)]
+enum NearDuplicateMember {
+    Table,
+    Id,
+    ClusterId,
+    MediaFileId,
+    Distance,
+}
@@ -0,0 +1,77 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Operation::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Operation::Id))
+                    .col(string(Operation::RunId))
+                    .col(string(Operation::Kind))
+                    .col(string_null(Operation::Source))
+                    .col(string_null(Operation::Destination))
+                    .col(integer_null(Operation::FileInZipId))
+                    .col(integer_null(Operation::RevertsId))
+                    .col(timestamp_with_time_zone(Operation::CreatedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_operation_file_in_zip")
+                            .from(Operation::Table, Operation::FileInZipId)
+                            .to(FileInZip::Table, FileInZip::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_operation_reverts")
+                            .from(Operation::Table, Operation::RevertsId)
+                            .to(Operation::Table, Operation::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_operation_run_id")
+                    .table(Operation::Table)
+                    .col(Operation::RunId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Operation::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileInZip {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Operation {
+    Table,
+    Id,
+    RunId,
+    Kind,
+    Source,
+    Destination,
+    FileInZipId,
+    RevertsId,
+    CreatedAt,
+}
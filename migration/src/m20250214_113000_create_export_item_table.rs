@@ -0,0 +1,65 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ExportItem::Table)
+                    .if_not_exists()
+                    .col(pk_auto(ExportItem::Id))
+                    .col(integer(ExportItem::MediaFileId))
+                    .col(string(ExportItem::Profile))
+                    .col(string(ExportItem::Path))
+                    .col(timestamp_with_time_zone(ExportItem::ExportedAt))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_export_item_media_file")
+                            .from(ExportItem::Table, ExportItem::MediaFileId)
+                            .to(MediaFile::Table, MediaFile::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_export_item_profile_media_file")
+                    .table(ExportItem::Table)
+                    .col(ExportItem::Profile)
+                    .col(ExportItem::MediaFileId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ExportItem::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum ExportItem {
+    Table,
+    Id,
+    MediaFileId,
+    Profile,
+    Path,
+    ExportedAt,
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite alters one column at a time.
+        for column in [
+            string_null(MediaFile::CountryCode),
+            string_null(MediaFile::Country),
+            string_null(MediaFile::Region),
+            string_null(MediaFile::City),
+        ] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).add_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [MediaFile::CountryCode, MediaFile::Country, MediaFile::Region, MediaFile::City] {
+            manager
+                .alter_table(Table::alter().table(MediaFile::Table).drop_column(column).to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaFile {
+    Table,
+    CountryCode,
+    Country,
+    Region,
+    City,
+}
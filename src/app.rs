@@ -4,15 +4,15 @@ use crate::file_list_widget::FileListWidget;
 /// Application result type.
 pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct App {
     pub file_list_widget: FileListWidget
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(file_list_widget: FileListWidget) -> Self {
         Self {
-            file_list_widget: FileListWidget::default()
+            file_list_widget
         }
     }
 }
@@ -0,0 +1,272 @@
+use crate::media_utils::{is_video_extension, rexif_get_taken_date};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveTime, Utc};
+use std::path::Path;
+
+/// Where a taken date was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateSource {
+    Json,
+    Exif,
+    FileName,
+    ArchiveMtime,
+}
+
+impl DateSource {
+    pub const DEFAULT_PRIORITY: [DateSource; 4] = [
+        DateSource::Json,
+        DateSource::Exif,
+        DateSource::FileName,
+        DateSource::ArchiveMtime,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateSource::Json => "json",
+            DateSource::Exif => "exif",
+            DateSource::FileName => "filename",
+            DateSource::ArchiveMtime => "archive_mtime",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "json" => Some(DateSource::Json),
+            "exif" => Some(DateSource::Exif),
+            "filename" => Some(DateSource::FileName),
+            "archive_mtime" => Some(DateSource::ArchiveMtime),
+            _ => None,
+        }
+    }
+}
+
+/// How far a resolved date can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DateConfidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl DateConfidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateConfidence::Low => "low",
+            DateConfidence::Medium => "medium",
+            DateConfidence::High => "high",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedDate {
+    pub taken: DateTime<Utc>,
+    pub source: DateSource,
+    pub confidence: DateConfidence,
+    /// The source gave the camera's wall-clock time rather than UTC, `taken`
+    /// holds that time as if it were UTC until the offset is known.
+    pub wall_clock: bool,
+    pub offset: Option<FixedOffset>,
+}
+
+impl ResolvedDate {
+    /// Pins the date to the offset it was taken in, moving a wall-clock time to
+    /// UTC on the way.
+    pub fn with_offset(self, offset: FixedOffset) -> Self {
+        let taken = if self.wall_clock {
+            (self.taken.naive_utc() - offset).and_utc()
+        } else {
+            self.taken
+        };
+        Self {
+            taken,
+            wall_clock: false,
+            offset: Some(offset),
+            ..self
+        }
+    }
+
+    /// The taken time as it read on the clock where the photo was taken.
+    pub fn local(&self) -> DateTime<FixedOffset> {
+        self.taken
+            .with_timezone(&self.offset.unwrap_or(FixedOffset::east_opt(0).unwrap()))
+    }
+}
+
+/// Earliest year a date is believed in. Cameras with a flat clock battery write
+/// 1970 or 1980, and Takeout uses 0 for an unknown `photoTakenTime`.
+const EARLIEST_PLAUSIBLE_YEAR: i32 = 1990;
+
+/// Asks each source in `priority` order and returns the first plausible date.
+/// The file itself is only parsed when no earlier source answered.
+pub async fn resolve_taken_date(
+    path: &str,
+    name: &str,
+    json_taken: Option<DateTime<Utc>>,
+    archive_mtime: Option<i64>,
+    priority: &[DateSource],
+) -> Option<ResolvedDate> {
+    let exif_position = priority.iter().position(|source| *source == DateSource::Exif);
+    let before_exif = &priority[..exif_position.unwrap_or(priority.len())];
+    if let Some(resolved) = resolve_known_taken_date(name, json_taken, None, archive_mtime, before_exif) {
+        return Some(resolved);
+    }
+    let exif_taken = match exif_position {
+        Some(_) => rexif_get_taken_date(path).await.ok().flatten(),
+        None => None,
+    };
+    resolve_known_taken_date(name, json_taken, exif_taken, archive_mtime, priority)
+}
+
+/// Like [`resolve_taken_date`] for a file that was already read, with
+/// `exif_taken` holding what its EXIF or QuickTime header said.
+pub fn resolve_known_taken_date(
+    name: &str,
+    json_taken: Option<DateTime<Utc>>,
+    exif_taken: Option<DateTime<Utc>>,
+    archive_mtime: Option<i64>,
+    priority: &[DateSource],
+) -> Option<ResolvedDate> {
+    // Photos store local time in EXIF, QuickTime headers are in UTC.
+    let exif_is_wall_clock = !Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(is_video_extension);
+    for source in priority {
+        let resolved = match source {
+            DateSource::Json => json_taken.map(|taken| (taken, DateConfidence::High, false)),
+            DateSource::Exif => exif_taken.map(|taken| (taken, DateConfidence::High, exif_is_wall_clock)),
+            DateSource::FileName => date_from_file_name(name).map(|(taken, has_time)| {
+                let confidence = if has_time {
+                    DateConfidence::Medium
+                } else {
+                    DateConfidence::Low
+                };
+                (taken, confidence, true)
+            }),
+            // Takeout does not always keep the original file times in the archive.
+            DateSource::ArchiveMtime => archive_mtime
+                .and_then(|mtime| DateTime::from_timestamp(mtime, 0))
+                .map(|taken| (taken, DateConfidence::Low, false)),
+        };
+        if let Some((taken, confidence, wall_clock)) = resolved
+            && is_plausible(taken)
+        {
+            return Some(ResolvedDate {
+                taken,
+                source: *source,
+                confidence,
+                wall_clock,
+                offset: None,
+            });
+        }
+    }
+    None
+}
+
+fn is_plausible(taken: DateTime<Utc>) -> bool {
+    taken.year() >= EARLIEST_PLAUSIBLE_YEAR && taken <= Utc::now()
+}
+
+/// Reads the date camera apps and messengers put in file names, together with
+/// whether a time of day was found as well:
+///
+/// - `IMG_20190704_123456.jpg`, `VID_20190704_123456.mp4`, `20190704_123456.jpg`
+/// - `PXL_20190704_123456789.jpg`
+/// - `Screenshot_20190704-123456.png`, `Screenshot_2019-07-04-12-34-56.png`
+/// - `IMG-20190704-WA0001.jpg`, `WhatsApp Image 2019-07-04 at 12.34.56.jpeg`
+///
+/// The time is taken as written, file names carry no offset.
+pub fn date_from_file_name(name: &str) -> Option<(DateTime<Utc>, bool)> {
+    let stem = Path::new(name).file_stem()?.to_str()?;
+    let runs: Vec<&str> = stem
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|run| !run.is_empty())
+        .collect();
+    for (i, run) in runs.iter().enumerate() {
+        let rest = &runs[i + 1..];
+        let found = match run.len() {
+            // Date and time without a separator.
+            14.. => parse_date(&run[0..4], &run[4..6], &run[6..8])
+                .map(|date| (date, parse_compact_time(&run[8..14]))),
+            8 => parse_date(&run[0..4], &run[4..6], &run[6..8])
+                .map(|date| (date, rest.first().and_then(|time| parse_compact_time(time)))),
+            4 if rest.len() >= 2 && rest[0].len() == 2 && rest[1].len() == 2 => {
+                parse_date(run, rest[0], rest[1]).map(|date| {
+                    let time = match &rest[2..] {
+                        [time, ..] if time.len() >= 6 => parse_compact_time(time),
+                        [hour, minute, second, ..] => parse_time(hour, minute, second),
+                        _ => None,
+                    };
+                    (date, time)
+                })
+            }
+            _ => None,
+        };
+        if let Some((date, time)) = found {
+            let taken = date.and_time(time.unwrap_or(NaiveTime::MIN)).and_utc();
+            return Some((taken, time.is_some()));
+        }
+    }
+    None
+}
+
+fn parse_date(year: &str, month: &str, day: &str) -> Option<NaiveDate> {
+    let date = NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)?;
+    (date.year() >= EARLIEST_PLAUSIBLE_YEAR).then_some(date)
+}
+
+/// `HHMMSS`, possibly followed by milliseconds as Pixel phones write them.
+fn parse_compact_time(run: &str) -> Option<NaiveTime> {
+    if run.len() < 6 {
+        return None;
+    }
+    parse_time(&run[0..2], &run[2..4], &run[4..6])
+}
+
+fn parse_time(hour: &str, minute: &str, second: &str) -> Option<NaiveTime> {
+    if hour.len() != 2 || minute.len() != 2 || second.len() != 2 {
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour.parse().ok()?, minute.parse().ok()?, second.parse().ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_dates_from_file_names() {
+        let cases = [
+            ("IMG_20190704_123456.jpg", Some(("2019-07-04T12:34:56Z", true))),
+            ("VID_20190704_123456.mp4", Some(("2019-07-04T12:34:56Z", true))),
+            ("20190704_123456.jpg", Some(("2019-07-04T12:34:56Z", true))),
+            ("PXL_20190704_123456789.jpg", Some(("2019-07-04T12:34:56Z", true))),
+            ("PXL_20190704_123456789.MP.jpg", Some(("2019-07-04T12:34:56Z", true))),
+            ("Screenshot_20190704-123456.png", Some(("2019-07-04T12:34:56Z", true))),
+            ("Screenshot_2019-07-04-12-34-56.png", Some(("2019-07-04T12:34:56Z", true))),
+            ("Screenshot_2019-07-04-12-34-56-789_com.android.chrome.jpg", Some(("2019-07-04T12:34:56Z", true))),
+            ("WhatsApp Image 2019-07-04 at 12.34.56.jpeg", Some(("2019-07-04T12:34:56Z", true))),
+            ("IMG-20190704-WA0001.jpg", Some(("2019-07-04T00:00:00Z", false))),
+            ("2019-07-04.jpg", Some(("2019-07-04T00:00:00Z", false))),
+            // Not a date, or too early to be a photo of this library.
+            ("IMG_0001.jpg", None),
+            ("IMG_20191304_123456.jpg", None),
+            ("IMG_19850704_123456.jpg", None),
+            ("DSC_1234_5678.jpg", None),
+            ("", None),
+        ];
+        for (name, expected) in cases {
+            let expected = expected.map(|(taken, has_time)| {
+                (DateTime::parse_from_rfc3339(taken).unwrap().to_utc(), has_time)
+            });
+            assert_eq!(date_from_file_name(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn ignores_times_that_do_not_exist() {
+        let (taken, has_time) = date_from_file_name("IMG_20190704_253456.jpg").unwrap();
+        assert_eq!(taken, DateTime::parse_from_rfc3339("2019-07-04T00:00:00Z").unwrap().to_utc());
+        assert!(!has_time);
+    }
+}
@@ -1,13 +1,20 @@
-use crate::file_list_widget::DriveItem;
+use crate::date_resolver::ResolvedDate;
+use crate::file_list_widget::{DriveItem, GeoData};
+use crate::geocode::Place;
+use crate::media_utils::{classify_json, is_truncated_sidecar, is_video_extension, strip_edit_suffix, MediaDetails, VIDEO_EXTENSIONS};
 use anyhow::Error;
 use anyhow::Result;
 use entity::takeout_zip::{ActiveModel as TakeoutZipActiveModel, Column, Model as TakeoutZip};
-use entity::{file_in_zip, media_file, takeout_zip};
+use entity::{album, album_media, export_item, file_in_zip, media_file, near_duplicate_cluster, near_duplicate_member, operation, takeout_zip};
 use sea_orm::ActiveValue::Set;
+use sea_orm::sea_query::{Expr, Func, Query};
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
-    NotSet, PaginatorTrait, QueryFilter,
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, DatabaseConnection, EntityTrait, IntoActiveModel,
+    ModelTrait, NotSet, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, RelationTrait, Select,
 };
+use sea_orm::JoinType;
+use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::path::Path;
 
 pub const MEDIA_STATUS_NO_RELATED: &str = "no_related";
@@ -16,6 +23,20 @@ pub const MEDIA_STATUS_PROCESSED: &str = "processed";
 pub const MEDIA_STATUS_FAILED: &str = "failed";
 pub const MEDIA_STATUS_NO_DATE: &str = "no_date";
 pub const MEDIA_STATUS_HAS_RELATED: &str = "has_related";
+pub const MEDIA_STATUS_SKIPPED: &str = "skipped";
+pub const MEDIA_STATUS_WAITING: &str = "waiting";
+pub const MEDIA_STATUS_ORPHANED: &str = "orphaned";
+pub const MEDIA_STATUS_DUPLICATE: &str = "duplicate";
+
+pub const MEDIA_VARIANT_ORIGINAL: &str = "original";
+pub const MEDIA_VARIANT_EDITED: &str = "edited";
+pub const MEDIA_VARIANT_MOTION: &str = "motion";
+
+pub const FILE_TYPE_MEDIA: &str = "media";
+pub const FILE_TYPE_SIDECAR: &str = "json";
+pub const FILE_TYPE_ALBUM_METADATA: &str = "album_metadata";
+pub const FILE_TYPE_ACCOUNT_METADATA: &str = "account_metadata";
+pub const FILE_TYPE_OTHER: &str = "other";
 
 pub const ZIP_STATUS_NEW: &str = "new";
 pub const ZIP_STATUS_PROCESSING: &str = "processing";
@@ -27,11 +48,20 @@ pub const ZIP_STATUS_EXAMINE_FAILED: &str = "examine_failed";
 pub const ZIP_STATUS_DOWNLOADING: &str = "downloading";
 pub const ZIP_STATUS_DOWNLOADED: &str = "downloaded";
 
+pub const CLUSTER_STATUS_PENDING: &str = "pending";
+pub const CLUSTER_STATUS_RESOLVED: &str = "resolved";
+
+// Moves, copies and links are journaled under their `PlacementMode` name.
+pub const OPERATION_CREATE: &str = "create";
+pub const OPERATION_REMOVE: &str = "remove";
+/// A file written in place, with a copy of what it held before as destination.
+pub const OPERATION_WRITE: &str = "write";
+
 pub fn get_db_url() -> String {
     dotenv::var("DATABASE_URL").unwrap_or("sqlite::memory:".to_string())
 }
 
-async fn get_db_connection() -> Result<DatabaseConnection> {
+pub async fn get_db_connection() -> Result<DatabaseConnection> {
     let db_url = get_db_url();
     // Check this out in the future for sqlite support.
     // let mut connect_options = sea_orm::ConnectOptions::new(db_url);
@@ -50,24 +80,27 @@ async fn get_db_connection() -> Result<DatabaseConnection> {
     }
 }
 
-pub async fn check_number_of_takeouts_with_status(status: &str, n: i32) -> Result<bool> {
-    let db = get_db_connection().await?;
+pub async fn check_number_of_takeouts_with_status(
+    conn: &DatabaseConnection,
+    status: &str,
+    n: i32,
+) -> Result<bool> {
     let count = takeout_zip::Entity::find()
         .filter(Column::Status.eq(status))
-        .count(&db)
+        .count(conn)
         .await?;
     Ok(count as i32 <= n)
 }
 
 pub async fn fetch_next_takeout(
+    conn: &DatabaseConnection,
     status: &str,
     new_status: Option<&str>,
     max_with_next_status: Option<(i32, &str)>,
 ) -> Result<Option<TakeoutZipActiveModel>> {
-    let db = get_db_connection().await?;
     let model = takeout_zip::Entity::find()
         .filter(Column::Status.eq(status))
-        .one(&db)
+        .one(conn)
         .await?;
 
     match model {
@@ -75,17 +108,17 @@ pub async fn fetch_next_takeout(
             None => Ok(Some(model.into_active_model())),
             Some(new_status) => {
                 if let Some((max_allowed, next_status)) = max_with_next_status {
-                    if check_number_of_takeouts_with_status(next_status, max_allowed).await? {
+                    if check_number_of_takeouts_with_status(conn, next_status, max_allowed).await? {
                         let mut model = model.into_active_model();
                         model.status = Set(new_status.to_string());
-                        Ok(Some(model.update(&db).await?.into_active_model()))
+                        Ok(Some(model.update(conn).await?.into_active_model()))
                     } else {
                         Ok(None)
                     }
                 } else {
                     let mut model = model.into_active_model();
                     model.status = Set(new_status.to_string());
-                    Ok(Some(model.update(&db).await?.into_active_model()))
+                    Ok(Some(model.update(conn).await?.into_active_model()))
                 }
             }
         },
@@ -93,29 +126,99 @@ pub async fn fetch_next_takeout(
     }
 }
 
-pub async fn create_file_in_zip(
-    takeout_zip_id: i32,
-    name: String,
-    path: String,
-    check_association: bool,
-) -> Result<file_in_zip::Model> {
-    let file_type = if path.contains(".json") {
-        "json"
+/// Sorts a file from an archive by its name and works out the key media and
+/// sidecars are matched on. Returns the file type, that key and the variant.
+pub fn classify_file<'a>(name: &str, path: &'a str) -> (&'static str, &'a str, &'static str) {
+    let file_type = if name.to_lowercase().ends_with(".json") {
+        classify_json(name)
     } else {
-        "media"
+        FILE_TYPE_MEDIA
+    };
+
+    let path_no_ext = match file_type {
+        // Album metadata describes the folder it sits in, not a single file.
+        FILE_TYPE_ALBUM_METADATA => Path::new(path)
+            .parent()
+            .and_then(|parent| parent.to_str())
+            .unwrap_or(""),
+        _ => path.split('.').next().unwrap_or(""),
     };
+    // Edited copies have no sidecar of their own, so they are keyed on the original's path.
+    match strip_edit_suffix(path_no_ext) {
+        Some(original) if file_type == FILE_TYPE_MEDIA => (file_type, original, MEDIA_VARIANT_EDITED),
+        _ => (file_type, path_no_ext, MEDIA_VARIANT_ORIGINAL),
+    }
+}
+
+/// A file as it was extracted from a Takeout archive.
+#[derive(Debug, Clone, Default)]
+pub struct NewFileInZip {
+    pub takeout_zip_id: i32,
+    pub name: String,
+    pub path: String,
+    /// Path of the entry inside the archive.
+    pub archive_path: Option<String>,
+    pub archive_mtime: Option<i64>,
+    pub content_hash: Option<String>,
+    pub size: Option<i64>,
+    pub duplicate_of_id: Option<i32>,
+}
 
-    let path_no_ext = path.split('.').next().unwrap_or("");
-    let path = Path::new(&path);
-    let extension = path.extension().unwrap().to_str().unwrap();
+pub async fn create_file_in_zip(
+    conn: &DatabaseConnection,
+    new_file: NewFileInZip,
+    check_association: bool,
+) -> Result<file_in_zip::Model> {
+    let NewFileInZip {
+        takeout_zip_id,
+        name,
+        path,
+        archive_path,
+        archive_mtime,
+        content_hash,
+        size,
+        duplicate_of_id,
+    } = new_file;
+    let (file_type, path_no_ext, mut variant) = classify_file(&name, &path);
+    // Archives can hold entries without one, like a `README`.
+    let extension = Path::new(&path).extension().and_then(OsStr::to_str).unwrap_or_default();
     let mut status = MEDIA_STATUS_NO_RELATED.to_owned();
     let mut related_id: ActiveValue<Option<i32>> = NotSet;
     let mut related_model: Option<file_in_zip::Model> = None;
+    let mut group_id: Option<i32> = None;
+
+    // Duplicates are only recorded, they never take part in matching. Their
+    // sidecar is set aside with them below.
+    let adopt_duplicate_sidecar = check_association && duplicate_of_id.is_some() && file_type == FILE_TYPE_MEDIA;
+    let check_association = check_association && duplicate_of_id.is_none();
+    if duplicate_of_id.is_some() {
+        status = MEDIA_STATUS_DUPLICATE.to_owned();
+    }
 
-    if check_association {
-        related_model = fetch_associated_if_exists(path_no_ext, file_type).await?;
+    if check_association && file_type == FILE_TYPE_MEDIA {
+        let is_video = is_video_extension(extension);
+        if let Some(primary) = fetch_group_primary(conn, path_no_ext).await?
+            && (variant == MEDIA_VARIANT_EDITED || is_video)
+        {
+            group_id = Some(primary.id);
+            if variant == MEDIA_VARIANT_ORIGINAL {
+                variant = MEDIA_VARIANT_MOTION;
+            }
+        }
+    }
+
+    if check_association && (file_type == FILE_TYPE_MEDIA || file_type == FILE_TYPE_SIDECAR) {
+        related_model = match fetch_associated_if_exists(conn, path_no_ext, file_type).await? {
+            Some(related_model) => Some(related_model),
+            None => fetch_truncated_associate(conn, &name, path_no_ext, file_type, variant).await?,
+        };
         if let Some(rl) = &related_model {
-            status = MEDIA_STATUS_HAS_RELATED.to_owned();
+            // The sidecar of a duplicate describes a file that is never placed.
+            status = if rl.duplicate_of_id.is_some() {
+                MEDIA_STATUS_DUPLICATE.to_owned()
+            } else {
+                MEDIA_STATUS_HAS_RELATED.to_owned()
+            };
             related_id = Set(Some(rl.id));
         }
     }
@@ -124,21 +227,56 @@ pub async fn create_file_in_zip(
         takeout_zip_id: Set(takeout_zip_id),
         name: Set(name.clone()),
         path_no_ext: Set(path_no_ext.to_owned()),
-        path: Set(path.to_str().unwrap().to_owned()),
+        path: Set(path.clone()),
         status: Set(status),
         log: Set(serde_json::Value::String("".to_owned())),
         file_type: Set(file_type.to_owned()),
         related_id,
         extension: Set(extension.to_owned()),
+        variant: Set(variant.to_owned()),
+        group_id: Set(group_id),
+        duplicate_of_id: Set(duplicate_of_id),
+        archive_mtime: Set(archive_mtime),
+        archive_path: Set(archive_path),
+        content_hash: Set(content_hash),
+        size: Set(size),
         ..Default::default()
     };
-    match am.insert(&get_db_connection().await?).await {
+    match am.insert(conn).await {
         Ok(model) => {
-            if let Some(related_model) = related_model {
+            // Edited copies and motion halves only borrow the sidecar, it stays
+            // related to the still original.
+            if let Some(related_model) = related_model
+                && variant == MEDIA_VARIANT_ORIGINAL
+            {
+                // Duplicates keep their status so they are never queued.
+                let queue = related_model.status == MEDIA_STATUS_NO_RELATED;
                 let mut related_model = related_model.into_active_model();
                 related_model.related_id = Set(Some(model.id));
-                related_model.status = Set(MEDIA_STATUS_HAS_RELATED.to_owned());
-                related_model.update(&get_db_connection().await?).await?;
+                if queue {
+                    related_model.status = Set(MEDIA_STATUS_HAS_RELATED.to_owned());
+                }
+                related_model.update(conn).await?;
+            }
+            if check_association && file_type == FILE_TYPE_SIDECAR {
+                link_variants_to_json(conn, &model).await?;
+            }
+            if adopt_duplicate_sidecar
+                && let Some(sidecar) = fetch_associated_if_exists(conn, path_no_ext, FILE_TYPE_MEDIA).await?
+                && sidecar.related_id.is_none()
+                && [MEDIA_STATUS_NO_RELATED, MEDIA_STATUS_WAITING].contains(&sidecar.status.as_str())
+            {
+                let mut sidecar = sidecar.into_active_model();
+                sidecar.related_id = Set(Some(model.id));
+                sidecar.status = Set(MEDIA_STATUS_DUPLICATE.to_owned());
+                sidecar.update(conn).await?;
+            }
+            if check_association
+                && variant == MEDIA_VARIANT_ORIGINAL
+                && file_type == FILE_TYPE_MEDIA
+                && !is_video_extension(extension)
+            {
+                adopt_group_members(conn, &model).await?;
             }
             Ok(model)
         }
@@ -146,6 +284,49 @@ pub async fn create_file_in_zip(
     }
 }
 
+/// Finds the still original that edited copies and Live Photo / Motion Photo
+/// videos sharing its path are grouped under.
+pub async fn fetch_group_primary(
+    conn: &DatabaseConnection,
+    path_no_ext: &str,
+) -> Result<Option<file_in_zip::Model>> {
+    let candidates = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::PathNoExt.eq(path_no_ext))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::Variant.eq(MEDIA_VARIANT_ORIGINAL))
+        .all(conn)
+        .await?;
+    Ok(candidates
+        .into_iter()
+        .find(|candidate| !is_video_extension(&candidate.extension)))
+}
+
+/// Groups members that were stored before their still original arrived. Videos
+/// that were taken for standalone originals are demoted to motion halves.
+pub async fn adopt_group_members(conn: &DatabaseConnection, primary: &file_in_zip::Model) -> Result<()> {
+    let members = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::PathNoExt.eq(&primary.path_no_ext))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::Id.ne(primary.id))
+        .filter(file_in_zip::Column::GroupId.is_null())
+        .all(conn)
+        .await?;
+    for member in members {
+        let is_video = is_video_extension(&member.extension);
+        if member.variant != MEDIA_VARIANT_EDITED && !is_video {
+            continue;
+        }
+        let demote = member.variant == MEDIA_VARIANT_ORIGINAL;
+        let mut member = member.into_active_model();
+        member.group_id = Set(Some(primary.id));
+        if demote {
+            member.variant = Set(MEDIA_VARIANT_MOTION.to_owned());
+        }
+        member.update(conn).await?;
+    }
+    Ok(())
+}
+
 pub fn get_model(file: DriveItem) -> Result<takeout_zip::ActiveModel> {
     if let DriveItem::File(id, name) = file {
         Ok(takeout_zip::ActiveModel {
@@ -160,73 +341,197 @@ pub fn get_model(file: DriveItem) -> Result<takeout_zip::ActiveModel> {
     }
 }
 
-pub async fn store_file(file: DriveItem) -> anyhow::Result<()> {
-    let conn = get_db_connection().await?;
-    let _takeout_zip = get_model(file)?.insert(&conn).await;
+pub async fn store_file(conn: &DatabaseConnection, file: DriveItem) -> anyhow::Result<()> {
+    let _takeout_zip = get_model(file)?.insert(conn).await;
     Ok(())
 }
 
-pub async fn list_takeouts() -> anyhow::Result<Vec<TakeoutZip>> {
-    let conn = get_db_connection().await?;
-    Ok(takeout_zip::Entity::find().all(&conn).await?)
+pub async fn list_takeouts(conn: &DatabaseConnection) -> anyhow::Result<Vec<TakeoutZip>> {
+    Ok(takeout_zip::Entity::find().all(conn).await?)
 }
 
-pub async fn update_takeout_zip(model: takeout_zip::ActiveModel) -> anyhow::Result<TakeoutZip> {
-    let conn = get_db_connection().await?;
-    Ok(model.update(&conn).await?)
+pub async fn update_takeout_zip(
+    conn: &DatabaseConnection,
+    model: takeout_zip::ActiveModel,
+) -> anyhow::Result<TakeoutZip> {
+    Ok(model.update(conn).await?)
 }
 
 #[allow(dead_code)]
-pub async fn store_files(files: Vec<DriveItem>) -> anyhow::Result<()> {
-    let conn = get_db_connection().await?;
+pub async fn store_files(conn: &DatabaseConnection, files: Vec<DriveItem>) -> anyhow::Result<()> {
     for file in files {
         if let DriveItem::File(_, _) = file {
-            let _takeout_zip = get_model(file)?.insert(&conn).await?;
+            let _takeout_zip = get_model(file)?.insert(conn).await?;
         }
     }
     Ok(())
 }
 
 pub async fn fetch_media_file_if_exists(
+    conn: &DatabaseConnection,
     json_file: &file_in_zip::Model,
 ) -> Result<Option<file_in_zip::Model>> {
-    let conn = get_db_connection().await?;
     let model = if let Some(related_id) = json_file.related_id {
       file_in_zip::Entity::find_by_id(related_id)
-        .one(&conn)
+        .one(conn)
         .await?
     } else {
       file_in_zip::Entity::find()
         .filter(file_in_zip::Column::PathNoExt.eq(&json_file.path_no_ext))
-        .filter(file_in_zip::Column::FileType.eq("media"))
-        .one(&conn)
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::Variant.eq(MEDIA_VARIANT_ORIGINAL))
+        .one(conn)
         .await?
     };
-    Ok(model)
+    match model {
+        Some(model) => Ok(Some(model)),
+        None => {
+            fetch_truncated_associate(
+                conn,
+                &json_file.name,
+                &json_file.path_no_ext,
+                FILE_TYPE_SIDECAR,
+                MEDIA_VARIANT_ORIGINAL,
+            )
+            .await
+        }
+    }
 }
 
 pub async fn fetch_associated_if_exists(
+    conn: &DatabaseConnection,
     path_no_ext: &str,
     file_type: &str,
 ) -> Result<Option<file_in_zip::Model>> {
     let type_to_find = match file_type {
-        "media" => "json",
-        _ => "media",
+        FILE_TYPE_MEDIA => FILE_TYPE_SIDECAR,
+        _ => FILE_TYPE_MEDIA,
     };
-    let conn = get_db_connection().await?;
-    Ok(file_in_zip::Entity::find()
+    let query = file_in_zip::Entity::find()
         .filter(file_in_zip::Column::PathNoExt.eq(path_no_ext))
-        .filter(file_in_zip::Column::FileType.eq(type_to_find))
-        .one(&conn)
-        .await?)
+        .filter(file_in_zip::Column::FileType.eq(type_to_find));
+    if type_to_find != FILE_TYPE_MEDIA {
+        return Ok(query.one(conn).await?);
+    }
+    // A Live Photo video that arrived before its still is not a motion half
+    // yet, the still is preferred so the sidecar ends up with it.
+    let candidates = query
+        .filter(file_in_zip::Column::Variant.eq(MEDIA_VARIANT_ORIGINAL))
+        .order_by_asc(file_in_zip::Column::Id)
+        .all(conn)
+        .await?;
+    Ok(candidates
+        .iter()
+        .find(|candidate| !is_video_extension(&candidate.extension))
+        .or(candidates.first())
+        .cloned())
+}
+
+/// Whether a matching key continues the one of a sidecar cut short, within the
+/// same folder.
+fn extends_truncated_key(path_no_ext: &str, truncated: &str) -> bool {
+    path_no_ext
+        .strip_prefix(truncated)
+        .is_some_and(|rest| !rest.contains('/'))
+}
+
+/// Takeout cuts long sidecar names short, so a sidecar holding only the start of
+/// a media name is matched on that prefix once the exact match came up empty.
+/// Only edited copies and motion halves may borrow a sidecar already taken.
+pub async fn fetch_truncated_associate(
+    conn: &DatabaseConnection,
+    name: &str,
+    path_no_ext: &str,
+    file_type: &str,
+    variant: &str,
+) -> Result<Option<file_in_zip::Model>> {
+    match file_type {
+        FILE_TYPE_SIDECAR if is_truncated_sidecar(name) => {
+            // LIKE also takes `_` as a wildcard, the prefix is checked again below.
+            let candidates: Vec<_> = file_in_zip::Entity::find()
+                .filter(file_in_zip::Column::PathNoExt.starts_with(path_no_ext))
+                .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+                .filter(file_in_zip::Column::Variant.eq(MEDIA_VARIANT_ORIGINAL))
+                .filter(file_in_zip::Column::RelatedId.is_null())
+                .filter(file_in_zip::Column::DuplicateOfId.is_null())
+                .order_by_asc(file_in_zip::Column::Id)
+                .all(conn)
+                .await?
+                .into_iter()
+                .filter(|candidate| extends_truncated_key(&candidate.path_no_ext, path_no_ext))
+                .collect();
+            Ok(candidates
+                .iter()
+                .find(|candidate| !is_video_extension(&candidate.extension))
+                .or(candidates.first())
+                .cloned())
+        }
+        FILE_TYPE_MEDIA => {
+            let folder = path_no_ext.rfind('/').map_or("", |slash| &path_no_ext[..=slash]);
+            let mut query = file_in_zip::Entity::find()
+                .filter(file_in_zip::Column::PathNoExt.starts_with(folder))
+                .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_SIDECAR));
+            if variant == MEDIA_VARIANT_ORIGINAL {
+                query = query.filter(file_in_zip::Column::RelatedId.is_null());
+            }
+            let candidates = query
+                .filter(file_in_zip::Column::DuplicateOfId.is_null())
+                .order_by_asc(file_in_zip::Column::Id)
+                .all(conn)
+                .await?;
+            Ok(candidates.into_iter().find(|candidate| {
+                is_truncated_sidecar(&candidate.name)
+                    && extends_truncated_key(path_no_ext, &candidate.path_no_ext)
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Points edited copies and motion halves that arrived before their sidecar at
+/// the newly stored json.
+pub async fn link_variants_to_json(conn: &DatabaseConnection, json_file: &file_in_zip::Model) -> Result<()> {
+    let key = if is_truncated_sidecar(&json_file.name) {
+        file_in_zip::Column::PathNoExt.starts_with(&json_file.path_no_ext)
+    } else {
+        file_in_zip::Column::PathNoExt.eq(&json_file.path_no_ext)
+    };
+    let variants = file_in_zip::Entity::find()
+        .filter(key)
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::Variant.ne(MEDIA_VARIANT_ORIGINAL))
+        .filter(file_in_zip::Column::RelatedId.is_null())
+        .all(conn)
+        .await?
+        .into_iter()
+        .filter(|variant| extends_truncated_key(&variant.path_no_ext, &json_file.path_no_ext));
+    for media_file in variants {
+        let mut media_file = media_file.into_active_model();
+        media_file.related_id = Set(Some(json_file.id));
+        media_file.status = Set(MEDIA_STATUS_HAS_RELATED.to_owned());
+        media_file.update(conn).await?;
+    }
+    Ok(())
+}
+
+pub async fn has_edited_variant(conn: &DatabaseConnection, path_no_ext: &str) -> Result<bool> {
+    let count = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::PathNoExt.eq(path_no_ext))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::Variant.eq(MEDIA_VARIANT_EDITED))
+        .count(conn)
+        .await?;
+    Ok(count > 0)
 }
 
-pub async fn fetch_related(media_file: &file_in_zip::Model) -> Result<Option<file_in_zip::Model>> {
-    let conn = get_db_connection().await?;
+pub async fn fetch_related(
+    conn: &DatabaseConnection,
+    media_file: &file_in_zip::Model,
+) -> Result<Option<file_in_zip::Model>> {
     if let Some(related_id) =  media_file.related_id {
       Ok(
         file_in_zip::Entity::find_by_id(related_id)
-          .one(&conn)
+          .one(conn)
           .await?,
       )
     } else {
@@ -234,43 +539,158 @@ pub async fn fetch_related(media_file: &file_in_zip::Model) -> Result<Option<fil
     }
 }
 
-#[allow(dead_code)]
-pub async fn fetch_new_media_and_set_status_to_processing() -> Result<Option<file_in_zip::Model>> {
-    fetch_media_file_to_process(
-        MEDIA_STATUS_HAS_RELATED,
-        "media",
-        Some(MEDIA_STATUS_PROCESSING),
-    )
-    .await
+/// Media with a sidecar goes first. Media without one is only taken once every
+/// archive has been examined and every sidecar matched, as until then its
+/// sidecar may still show up.
+pub async fn fetch_new_media_and_set_status_to_processing(
+    conn: &DatabaseConnection,
+) -> Result<Option<file_in_zip::Model>> {
+    let examining = has_pending_takeouts(conn).await?;
+    let media_file =
+        fetch_ready_media_and_set_status_to_processing(conn, MEDIA_STATUS_HAS_RELATED, examining).await?;
+    if media_file.is_some() || examining || has_unmatched_json(conn).await? {
+        return Ok(media_file);
+    }
+    fetch_ready_media_and_set_status_to_processing(conn, MEDIA_STATUS_NO_RELATED, false).await
+}
+
+/// Takes media with `status` whose group can be placed. Edited copies and
+/// motion halves wait for their still original to be done, so they can follow
+/// it into its folder. With `hold_videos` standalone videos wait too, as their
+/// still may be in an archive not examined yet.
+async fn fetch_ready_media_and_set_status_to_processing(
+    conn: &DatabaseConnection,
+    status: &str,
+    hold_videos: bool,
+) -> Result<Option<file_in_zip::Model>> {
+    let done_primaries = Query::select()
+        .column(file_in_zip::Column::Id)
+        .from(file_in_zip::Entity)
+        .and_where(file_in_zip::Column::Status.is_not_in([
+            MEDIA_STATUS_NO_RELATED,
+            MEDIA_STATUS_HAS_RELATED,
+            MEDIA_STATUS_PROCESSING,
+        ]))
+        .to_owned();
+    let mut query = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::Status.eq(status))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(
+            Condition::any()
+                .add(file_in_zip::Column::GroupId.is_null())
+                .add(file_in_zip::Column::GroupId.in_subquery(done_primaries)),
+        );
+    if hold_videos {
+        query = query.filter(
+            Condition::any()
+                .add(file_in_zip::Column::Variant.ne(MEDIA_VARIANT_ORIGINAL))
+                .add(Expr::expr(Func::lower(Expr::col(file_in_zip::Column::Extension))).is_not_in(VIDEO_EXTENSIONS)),
+        );
+    }
+    match query.one(conn).await? {
+        Some(model) => {
+            let mut model = model.into_active_model();
+            model.status = Set(MEDIA_STATUS_PROCESSING.to_owned());
+            Ok(Some(model.update(conn).await?))
+        }
+        None => Ok(None),
+    }
 }
 
-#[allow(dead_code)]
 pub async fn fetch_json_without_media_and_set_status_to_processing(
+    conn: &DatabaseConnection,
 ) -> Result<Option<file_in_zip::Model>> {
     fetch_media_file_to_process(
+        conn,
         MEDIA_STATUS_NO_RELATED,
-        "json",
+        FILE_TYPE_SIDECAR,
         Some(MEDIA_STATUS_PROCESSING),
     )
     .await
 }
 
-#[allow(dead_code)]
-pub async fn fetch_file_in_zip_by_id(id: i32) -> Result<Option<file_in_zip::Model>> {
-    let conn = get_db_connection().await?;
-    Ok(file_in_zip::Entity::find_by_id(id).one(&conn).await?)
+/// Puts sidecars that were waiting for their media back in the json queue.
+pub async fn requeue_waiting_json(conn: &DatabaseConnection) -> Result<()> {
+    file_in_zip::Entity::update_many()
+        .col_expr(
+            file_in_zip::Column::Status,
+            Expr::value(MEDIA_STATUS_NO_RELATED),
+        )
+        .filter(file_in_zip::Column::Status.eq(MEDIA_STATUS_WAITING))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_SIDECAR))
+        .exec(conn)
+        .await?;
+    Ok(())
+}
+
+/// Whether any archive is still to be downloaded or examined, so media can still show up.
+pub async fn has_pending_takeouts(conn: &DatabaseConnection) -> Result<bool> {
+    let count = takeout_zip::Entity::find()
+        .filter(Column::Status.is_in([
+            ZIP_STATUS_NEW,
+            ZIP_STATUS_DOWNLOADING,
+            ZIP_STATUS_DOWNLOADED,
+            ZIP_STATUS_PROCESSING,
+        ]))
+        .count(conn)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Whether any sidecar is still queued for, or in, the json stage.
+pub async fn has_unmatched_json(conn: &DatabaseConnection) -> Result<bool> {
+    let count = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_SIDECAR))
+        .filter(file_in_zip::Column::Status.is_in([
+            MEDIA_STATUS_NO_RELATED,
+            MEDIA_STATUS_PROCESSING,
+            MEDIA_STATUS_WAITING,
+        ]))
+        .count(conn)
+        .await?;
+    Ok(count > 0)
+}
+
+/// The media row placed at `path`.
+pub async fn fetch_media_by_path(
+    conn: &DatabaseConnection,
+    path: &str,
+) -> Result<Option<file_in_zip::Model>> {
+    Ok(file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::Path.eq(path))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .one(conn)
+        .await?)
+}
+
+/// Adds an entry to a row's `log`, which starts out as an empty string and
+/// becomes a list of what was decided about the file.
+pub fn append_log(log: &serde_json::Value, entry: serde_json::Value) -> serde_json::Value {
+    let mut entries = match log {
+        serde_json::Value::Array(entries) => entries.clone(),
+        _ => Vec::new(),
+    };
+    entries.push(entry);
+    serde_json::Value::Array(entries)
+}
+
+pub async fn fetch_file_in_zip_by_id(
+    conn: &DatabaseConnection,
+    id: i32,
+) -> Result<Option<file_in_zip::Model>> {
+    Ok(file_in_zip::Entity::find_by_id(id).one(conn).await?)
 }
 
 pub async fn fetch_media_file_to_process(
+    conn: &DatabaseConnection,
     status: &str,
     file_type: &str,
     new_status: Option<&str>,
 ) -> Result<Option<file_in_zip::Model>> {
-    let conn = get_db_connection().await?;
     let model = file_in_zip::Entity::find()
         .filter(file_in_zip::Column::Status.eq(status))
         .filter(file_in_zip::Column::FileType.eq(file_type))
-        .one(&conn)
+        .one(conn)
         .await?;
     match model {
         Some(model) => match new_status {
@@ -278,31 +698,910 @@ pub async fn fetch_media_file_to_process(
             Some(new_status) => {
                 let mut model = model.into_active_model();
                 model.status = Set(new_status.to_string());
-                Ok(Some(model.update(&conn).await?))
+                Ok(Some(model.update(conn).await?))
             }
         },
         None => Ok(None),
     }
 }
 
-pub async fn update_file_in_zip(model: file_in_zip::ActiveModel) -> Result<file_in_zip::Model> {
-    let conn = get_db_connection().await?;
-    Ok(model.update(&conn).await?)
+pub async fn update_file_in_zip(
+    conn: &DatabaseConnection,
+    model: file_in_zip::ActiveModel,
+) -> Result<file_in_zip::Model> {
+    Ok(model.update(conn).await?)
+}
+
+/// What the catalog records about a media file besides its name and path.
+#[derive(Debug, Clone, Default)]
+pub struct MediaFileDetails {
+    pub location: Option<GeoData>,
+    pub place: Option<Place>,
+    pub taken: Option<ResolvedDate>,
+    pub media: MediaDetails,
+    pub mime_type: Option<&'static str>,
+    pub size: Option<i64>,
+    pub content_hash: Option<String>,
+    /// Difference hash of the image, see [`crate::perceptual_hash::dhash`].
+    pub perceptual_hash: Option<u64>,
+    pub favorite: bool,
+    pub archived: bool,
+    pub trashed: bool,
+}
+
+impl MediaFileDetails {
+    /// Sets the catalog columns. A missing location, place or date leaves what
+    /// the row already has.
+    fn apply(&self, model: &mut media_file::ActiveModel) {
+        if let Some(location) = self.location {
+            model.latitude = Set(Some(location.latitude));
+            model.longitude = Set(Some(location.longitude));
+            model.altitude = Set(Some(location.altitude));
+            model.place_looked_up = Set(true);
+        }
+        if let Some(place) = &self.place {
+            set_place_columns(model, place);
+        }
+        if let Some(taken) = self.taken {
+            model.taken_at = Set(Some(taken.local()));
+            model.date_source = Set(Some(taken.source.as_str().to_owned()));
+            model.date_confidence = Set(Some(taken.confidence.as_str().to_owned()));
+            model.utc_offset = Set(taken.offset.map(|offset| offset.local_minus_utc()));
+        }
+        model.camera_make = Set(self.media.camera_make.clone());
+        model.camera_model = Set(self.media.camera_model.clone());
+        model.width = Set(self.media.width.map(|width| width as i32));
+        model.height = Set(self.media.height.map(|height| height as i32));
+        model.mime_type = Set(self.mime_type.map(str::to_owned));
+        model.size = Set(self.size);
+        model.content_hash = Set(self.content_hash.clone());
+        // Stored bit for bit, Postgres has no unsigned bigint.
+        model.perceptual_hash = Set(self.perceptual_hash.map(|hash| hash as i64));
+        model.favorite = Set(self.favorite);
+        model.archived = Set(self.archived);
+        model.trashed = Set(self.trashed);
+        model.catalogued = Set(true);
+    }
 }
 
 pub async fn create_media_file(
-    file_name: &str,
-    path: &str,
+    conn: &DatabaseConnection,
+    media: &file_in_zip::Model,
+    sidecar: Option<&file_in_zip::Model>,
     json_meta: &serde_json::Value,
+    details: &MediaFileDetails,
 ) -> Result<media_file::Model> {
-    let m = media_file::ActiveModel {
-        file_name: Set(file_name.to_owned()),
-        path: Set(path.to_owned()),
+    let mut m = media_file::ActiveModel {
+        file_name: Set(media.name.clone()),
+        path: Set(media.path.clone()),
         json_meta: Set(json_meta.clone()),
+        file_in_zip_id: Set(Some(media.id)),
+        sidecar_file_in_zip_id: Set(sidecar.map(|sidecar| sidecar.id)),
         ..Default::default()
     };
-    match m.insert(&get_db_connection().await?).await {
+    details.apply(&mut m);
+    match m.insert(conn).await {
         Ok(model) => Ok(model),
         Err(e) => Err(Error::new(e)),
     }
 }
+
+/// Catalog rows written before the structured columns existed. Rows that were
+/// filled without finding a date are not taken again.
+pub async fn fetch_media_files_without_taken_at(conn: &DatabaseConnection) -> Result<Vec<media_file::Model>> {
+    Ok(media_file::Entity::find()
+        .filter(media_file::Column::TakenAt.is_null())
+        .filter(media_file::Column::Catalogued.eq(false))
+        .all(conn)
+        .await?)
+}
+
+pub async fn update_media_file_details(
+    conn: &DatabaseConnection,
+    media_file: media_file::Model,
+    details: &MediaFileDetails,
+) -> Result<media_file::Model> {
+    let mut media_file = media_file.into_active_model();
+    details.apply(&mut media_file);
+    Ok(media_file.update(conn).await?)
+}
+
+/// Catalog rows with a location that were catalogued before places were
+/// named. Rows whose location was looked up without finding a place, out at
+/// sea, are not taken again.
+pub async fn fetch_media_files_without_place(conn: &DatabaseConnection) -> Result<Vec<media_file::Model>> {
+    Ok(media_file::Entity::find()
+        .filter(media_file::Column::Latitude.is_not_null())
+        .filter(media_file::Column::Longitude.is_not_null())
+        .filter(media_file::Column::CountryCode.is_null())
+        .filter(media_file::Column::PlaceLookedUp.eq(false))
+        .all(conn)
+        .await?)
+}
+
+/// Records the place found at the row's location, or that there is none.
+pub async fn set_place(
+    conn: &DatabaseConnection,
+    media_file: media_file::Model,
+    place: Option<&Place>,
+) -> Result<media_file::Model> {
+    let mut media_file = media_file.into_active_model();
+    if let Some(place) = place {
+        set_place_columns(&mut media_file, place);
+    }
+    media_file.place_looked_up = Set(true);
+    Ok(media_file.update(conn).await?)
+}
+
+fn set_place_columns(model: &mut media_file::ActiveModel, place: &Place) {
+    model.country_code = Set(Some(place.country_code.clone()));
+    model.country = Set(Some(place.country.clone()));
+    model.region = Set(place.region.clone());
+    model.city = Set(Some(place.city.clone()));
+}
+
+/// The first media file extracted with this content, the one later copies are
+/// recorded against.
+pub async fn fetch_media_by_content_hash(
+    conn: &DatabaseConnection,
+    content_hash: &str,
+) -> Result<Option<file_in_zip::Model>> {
+    Ok(file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::ContentHash.eq(content_hash))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::DuplicateOfId.is_null())
+        .order_by_asc(file_in_zip::Column::Id)
+        .one(conn)
+        .await?)
+}
+
+/// Media recorded as copies of another file, with the file they point to.
+pub async fn fetch_content_duplicates(
+    conn: &DatabaseConnection,
+) -> Result<Vec<(file_in_zip::Model, file_in_zip::Model)>> {
+    let duplicates = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .filter(file_in_zip::Column::DuplicateOfId.is_not_null())
+        .order_by_asc(file_in_zip::Column::DuplicateOfId)
+        .all(conn)
+        .await?;
+    let canonical_ids: Vec<i32> = duplicates.iter().filter_map(|duplicate| duplicate.duplicate_of_id).collect();
+    let canonicals: HashMap<i32, file_in_zip::Model> = file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::Id.is_in(canonical_ids))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|canonical| (canonical.id, canonical))
+        .collect();
+    Ok(duplicates
+        .into_iter()
+        .filter_map(|duplicate| {
+            let canonical = canonicals.get(&duplicate.duplicate_of_id?)?.clone();
+            Some((duplicate, canonical))
+        })
+        .collect())
+}
+
+/// Catalog rows that may still need a perceptual hash, placed before hashes
+/// were computed or in a format that could not be decoded.
+pub async fn fetch_media_files_without_perceptual_hash(
+    conn: &DatabaseConnection,
+) -> Result<Vec<media_file::Model>> {
+    Ok(media_file::Entity::find()
+        .filter(media_file::Column::PerceptualHash.is_null())
+        .all(conn)
+        .await?)
+}
+
+pub async fn set_perceptual_hash(
+    conn: &DatabaseConnection,
+    media_file: media_file::Model,
+    hash: u64,
+) -> Result<media_file::Model> {
+    let mut media_file = media_file.into_active_model();
+    media_file.perceptual_hash = Set(Some(hash as i64));
+    Ok(media_file.update(conn).await?)
+}
+
+/// Perceptual hashes of the catalog, leaving out copies that were already
+/// put aside in a review.
+pub async fn fetch_perceptual_hashes(conn: &DatabaseConnection) -> Result<Vec<(i32, u64)>> {
+    let put_aside = fetch_put_aside_media_file_ids(conn).await?;
+    Ok(media_file::Entity::find()
+        .filter(media_file::Column::PerceptualHash.is_not_null())
+        .filter(media_file::Column::Id.is_not_in(put_aside))
+        .all(conn)
+        .await?
+        .into_iter()
+        .filter_map(|media_file| Some((media_file.id, media_file.perceptual_hash? as u64)))
+        .collect())
+}
+
+/// Copies that lost a near duplicate review.
+async fn fetch_put_aside_media_file_ids(conn: &DatabaseConnection) -> Result<Vec<i32>> {
+    let resolved = near_duplicate_cluster::Entity::find()
+        .filter(near_duplicate_cluster::Column::Status.eq(CLUSTER_STATUS_RESOLVED))
+        .find_with_related(near_duplicate_member::Entity)
+        .all(conn)
+        .await?;
+    Ok(resolved
+        .iter()
+        .flat_map(|(cluster, members)| {
+            members
+                .iter()
+                .filter(|member| Some(member.media_file_id) != cluster.kept_media_file_id)
+                .map(|member| member.media_file_id)
+        })
+        .collect())
+}
+
+/// Replaces the clusters still waiting for a review with `clusters`, lists of
+/// media file ids with their distance to the first member.
+pub async fn store_near_duplicate_clusters(
+    conn: &DatabaseConnection,
+    clusters: &[Vec<(i32, u32)>],
+) -> Result<()> {
+    near_duplicate_cluster::Entity::delete_many()
+        .filter(near_duplicate_cluster::Column::Status.eq(CLUSTER_STATUS_PENDING))
+        .exec(conn)
+        .await?;
+    for members in clusters {
+        let cluster = near_duplicate_cluster::ActiveModel {
+            status: Set(CLUSTER_STATUS_PENDING.to_owned()),
+            ..Default::default()
+        }
+        .insert(conn)
+        .await?;
+        near_duplicate_member::Entity::insert_many(members.iter().map(|(media_file_id, distance)| {
+            near_duplicate_member::ActiveModel {
+                cluster_id: Set(cluster.id),
+                media_file_id: Set(*media_file_id),
+                distance: Set(*distance as i32),
+                ..Default::default()
+            }
+        }))
+        .exec(conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Clusters waiting for a review, each with its members and their catalog rows.
+pub async fn fetch_pending_near_duplicates(
+    conn: &DatabaseConnection,
+) -> Result<Vec<(near_duplicate_cluster::Model, Vec<(near_duplicate_member::Model, media_file::Model)>)>> {
+    let clusters = near_duplicate_cluster::Entity::find()
+        .filter(near_duplicate_cluster::Column::Status.eq(CLUSTER_STATUS_PENDING))
+        .order_by_asc(near_duplicate_cluster::Column::Id)
+        .all(conn)
+        .await?;
+    let mut pending = Vec::with_capacity(clusters.len());
+    for cluster in clusters {
+        let members = near_duplicate_member::Entity::find()
+            .filter(near_duplicate_member::Column::ClusterId.eq(cluster.id))
+            .order_by_asc(near_duplicate_member::Column::Distance)
+            .find_also_related(media_file::Entity)
+            .all(conn)
+            .await?
+            .into_iter()
+            .filter_map(|(member, media_file)| Some((member, media_file?)))
+            .collect();
+        pending.push((cluster, members));
+    }
+    Ok(pending)
+}
+
+pub async fn resolve_near_duplicate_cluster(
+    conn: &DatabaseConnection,
+    cluster_id: i32,
+    kept_media_file_id: i32,
+) -> Result<()> {
+    near_duplicate_cluster::ActiveModel {
+        id: Set(cluster_id),
+        status: Set(CLUSTER_STATUS_RESOLVED.to_owned()),
+        kept_media_file_id: Set(Some(kept_media_file_id)),
+    }
+    .update(conn)
+    .await?;
+    Ok(())
+}
+
+/// Records where a catalog row and the archive row it came from were moved.
+pub async fn update_media_file_path(
+    conn: &DatabaseConnection,
+    media_file: media_file::Model,
+    path: &str,
+    log_entry: serde_json::Value,
+) -> Result<media_file::Model> {
+    if let Some(file_in_zip) = media_file.find_related(file_in_zip::Entity).one(conn).await? {
+        let log = append_log(&file_in_zip.log, log_entry);
+        let mut file_in_zip = file_in_zip.into_active_model();
+        file_in_zip.path = Set(path.to_owned());
+        file_in_zip.log = Set(log);
+        file_in_zip.update(conn).await?;
+    }
+    let mut media_file = media_file.into_active_model();
+    media_file.path = Set(path.to_owned());
+    Ok(media_file.update(conn).await?)
+}
+
+/// A filesystem action as it is appended to the journal. A remove names the
+/// file that still holds the same bytes as its destination, so it can be
+/// undone by copying that back.
+#[derive(Debug, Clone, Default)]
+pub struct NewOperation {
+    pub run_id: String,
+    pub kind: String,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    pub file_in_zip_id: Option<i32>,
+    pub reverts_id: Option<i32>,
+}
+
+pub async fn record_operation(
+    conn: &DatabaseConnection,
+    new_operation: NewOperation,
+) -> Result<operation::Model> {
+    let NewOperation {
+        run_id,
+        kind,
+        source,
+        destination,
+        file_in_zip_id,
+        reverts_id,
+    } = new_operation;
+    Ok(operation::ActiveModel {
+        run_id: Set(run_id),
+        kind: Set(kind),
+        source: Set(source),
+        destination: Set(destination),
+        file_in_zip_id: Set(file_in_zip_id),
+        reverts_id: Set(reverts_id),
+        created_at: Set(chrono::Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(conn)
+    .await?)
+}
+
+/// The operations an undo rolls back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperationFilter {
+    Run(String),
+    File(i32),
+}
+
+/// Operations that were neither undone nor undo themselves.
+fn outstanding_operations() -> Select<operation::Entity> {
+    operation::Entity::find()
+        .filter(operation::Column::RevertsId.is_null())
+        .filter(
+            operation::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(operation::Column::RevertsId)
+                    .from(operation::Entity)
+                    .and_where(operation::Column::RevertsId.is_not_null())
+                    .to_owned(),
+            ),
+        )
+}
+
+/// What is left to undo of a run or a file, newest first.
+pub async fn fetch_operations_to_undo(
+    conn: &DatabaseConnection,
+    filter: &OperationFilter,
+) -> Result<Vec<operation::Model>> {
+    let operations = outstanding_operations();
+    let operations = match filter {
+        OperationFilter::Run(run_id) => operations.filter(operation::Column::RunId.eq(run_id)),
+        OperationFilter::File(file_in_zip_id) => {
+            operations.filter(operation::Column::FileInZipId.eq(*file_in_zip_id))
+        }
+    };
+    Ok(operations
+        .order_by_desc(operation::Column::Id)
+        .all(conn)
+        .await?)
+}
+
+/// The latest run that still has something to undo.
+pub async fn fetch_last_run_id(conn: &DatabaseConnection) -> Result<Option<String>> {
+    Ok(outstanding_operations()
+        .order_by_desc(operation::Column::Id)
+        .one(conn)
+        .await?
+        .map(|operation| operation.run_id))
+}
+
+/// The latest run that still has something to undo for a takeout's files.
+pub async fn fetch_last_run_id_of_takeout(
+    conn: &DatabaseConnection,
+    takeout_zip_id: i32,
+) -> Result<Option<String>> {
+    Ok(outstanding_operations()
+        .join(JoinType::InnerJoin, operation::Relation::FileInZip.def())
+        .filter(file_in_zip::Column::TakeoutZipId.eq(takeout_zip_id))
+        .order_by_desc(operation::Column::Id)
+        .one(conn)
+        .await?
+        .map(|operation| operation.run_id))
+}
+
+pub async fn has_outstanding_operations(conn: &DatabaseConnection, file_in_zip_id: i32) -> Result<bool> {
+    let count = outstanding_operations()
+        .filter(operation::Column::FileInZipId.eq(file_in_zip_id))
+        .count(conn)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Points a row, and the catalog row made from it, at where an undo put its
+/// file. A file back where it was extracted is queued again as if it had never
+/// been processed and leaves the catalog.
+pub async fn restore_file_in_zip(
+    conn: &DatabaseConnection,
+    file_in_zip_id: i32,
+    path: &str,
+    extracted: bool,
+    log_entry: serde_json::Value,
+) -> Result<()> {
+    let Some(file_in_zip) = file_in_zip::Entity::find_by_id(file_in_zip_id).one(conn).await? else {
+        return Ok(());
+    };
+    let log = append_log(&file_in_zip.log, log_entry);
+    let status = match file_in_zip.related_id {
+        Some(_) => MEDIA_STATUS_HAS_RELATED,
+        None => MEDIA_STATUS_NO_RELATED,
+    };
+    let mut file_in_zip = file_in_zip.into_active_model();
+    file_in_zip.path = Set(path.to_owned());
+    file_in_zip.log = Set(log);
+    if extracted {
+        file_in_zip.status = Set(status.to_owned());
+        file_in_zip.duplicate_of_id = Set(None);
+        file_in_zip.placement_mode = Set(None);
+        media_file::Entity::delete_many()
+            .filter(media_file::Column::FileInZipId.eq(file_in_zip_id))
+            .exec(conn)
+            .await?;
+    } else {
+        media_file::Entity::update_many()
+            .col_expr(media_file::Column::Path, Expr::value(path))
+            .filter(media_file::Column::FileInZipId.eq(file_in_zip_id))
+            .exec(conn)
+            .await?;
+    }
+    file_in_zip.update(conn).await?;
+    Ok(())
+}
+
+pub async fn store_album(
+    conn: &DatabaseConnection,
+    path: &str,
+    title: &str,
+    json_meta: &serde_json::Value,
+) -> Result<album::Model> {
+    match fetch_album_by_path(conn, path).await? {
+        Some(model) => {
+            let mut model = model.into_active_model();
+            model.title = Set(title.to_owned());
+            model.json_meta = Set(json_meta.clone());
+            Ok(model.update(conn).await?)
+        }
+        None => Ok(album::ActiveModel {
+            title: Set(title.to_owned()),
+            path: Set(path.to_owned()),
+            json_meta: Set(json_meta.clone()),
+            ..Default::default()
+        }
+        .insert(conn)
+        .await?),
+    }
+}
+
+pub async fn fetch_album_by_path(conn: &DatabaseConnection, path: &str) -> Result<Option<album::Model>> {
+    Ok(album::Entity::find()
+        .filter(album::Column::Path.eq(path))
+        .one(conn)
+        .await?)
+}
+
+pub async fn list_albums(conn: &DatabaseConnection) -> Result<Vec<album::Model>> {
+    Ok(album::Entity::find()
+        .order_by_asc(album::Column::Id)
+        .all(conn)
+        .await?)
+}
+
+/// Adds a media file to an album, once. Duplicates are stored against the copy that is kept.
+pub async fn add_media_to_album(
+    conn: &DatabaseConnection,
+    album_id: i32,
+    media_file: &file_in_zip::Model,
+) -> Result<()> {
+    let file_in_zip_id = media_file.duplicate_of_id.unwrap_or(media_file.id);
+    let exists = album_media::Entity::find()
+        .filter(album_media::Column::AlbumId.eq(album_id))
+        .filter(album_media::Column::FileInZipId.eq(file_in_zip_id))
+        .count(conn)
+        .await?
+        > 0;
+    if !exists {
+        album_media::ActiveModel {
+            album_id: Set(album_id),
+            file_in_zip_id: Set(file_in_zip_id),
+            ..Default::default()
+        }
+        .insert(conn)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn fetch_media_in_folder(
+    conn: &DatabaseConnection,
+    folder: &str,
+) -> Result<Vec<file_in_zip::Model>> {
+    Ok(file_in_zip::Entity::find()
+        .filter(file_in_zip::Column::Path.starts_with(format!("{}/", folder)))
+        .filter(file_in_zip::Column::FileType.eq(FILE_TYPE_MEDIA))
+        .all(conn)
+        .await?)
+}
+
+/// The album a media file was exported in, if any.
+pub async fn fetch_media_album(
+    conn: &DatabaseConnection,
+    media_file: &file_in_zip::Model,
+) -> Result<Option<album::Model>> {
+    Ok(media_file.find_related(album::Entity).one(conn).await?)
+}
+
+pub async fn fetch_album_media(
+    conn: &DatabaseConnection,
+    album: &album::Model,
+) -> Result<Vec<file_in_zip::Model>> {
+    Ok(album.find_related(file_in_zip::Entity).all(conn).await?)
+}
+
+/// Catalog rows not yet exported for `profile`. Trashed photos and copies put
+/// aside in a near duplicate review are never exported.
+pub async fn fetch_media_files_to_export(
+    conn: &DatabaseConnection,
+    profile: &str,
+) -> Result<Vec<media_file::Model>> {
+    let put_aside = fetch_put_aside_media_file_ids(conn).await?;
+    Ok(media_file::Entity::find()
+        .filter(media_file::Column::Trashed.eq(false))
+        .filter(media_file::Column::Id.is_not_in(put_aside))
+        .filter(
+            media_file::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(export_item::Column::MediaFileId)
+                    .from(export_item::Entity)
+                    .and_where(export_item::Column::Profile.eq(profile))
+                    .to_owned(),
+            ),
+        )
+        .order_by_asc(media_file::Column::Id)
+        .all(conn)
+        .await?)
+}
+
+pub async fn store_export_item(
+    conn: &DatabaseConnection,
+    media_file_id: i32,
+    profile: &str,
+    path: &str,
+) -> Result<export_item::Model> {
+    Ok(export_item::ActiveModel {
+        media_file_id: Set(media_file_id),
+        profile: Set(profile.to_owned()),
+        path: Set(path.to_owned()),
+        exported_at: Set(chrono::Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(conn)
+    .await?)
+}
+
+/// Where each catalog row was exported to for `profile`, by catalog id.
+pub async fn fetch_exported_paths(conn: &DatabaseConnection, profile: &str) -> Result<HashMap<i32, String>> {
+    Ok(export_item::Entity::find()
+        .filter(export_item::Column::Profile.eq(profile))
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|export_item| (export_item.media_file_id, export_item.path))
+        .collect())
+}
+
+/// The catalog rows of an album's media.
+pub async fn fetch_album_media_files(
+    conn: &DatabaseConnection,
+    album: &album::Model,
+) -> Result<Vec<media_file::Model>> {
+    let file_in_zip_ids: Vec<i32> = album
+        .find_related(file_in_zip::Entity)
+        .all(conn)
+        .await?
+        .into_iter()
+        .map(|file_in_zip| file_in_zip.id)
+        .collect();
+    Ok(media_file::Entity::find()
+        .filter(media_file::Column::FileInZipId.is_in(file_in_zip_ids))
+        .all(conn)
+        .await?)
+}
+
+#[cfg(test)]
+pub(crate) mod test_db {
+    use super::*;
+    use crate::file_list_widget::FileListWidget;
+    use migration::{Migrator, MigratorTrait};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// A migrated SQLite database with an empty target folder next to it, both
+    /// removed when it is dropped.
+    pub(crate) struct TestDb {
+        pub conn: DatabaseConnection,
+        pub target_folder: PathBuf,
+        _folder: TempDir,
+    }
+
+    pub(crate) async fn fresh() -> TestDb {
+        let folder = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", folder.path().join("takeout.db").display());
+        let conn = sea_orm::Database::connect(&url).await.unwrap();
+        Migrator::up(&conn, None).await.unwrap();
+        let target_folder = folder.path().join("target");
+        std::fs::create_dir(&target_folder).unwrap();
+        TestDb {
+            conn,
+            target_folder,
+            _folder: folder,
+        }
+    }
+
+    impl TestDb {
+        /// A widget working on this database and target folder.
+        pub(crate) fn widget(&self) -> FileListWidget {
+            FileListWidget::new(self.conn.clone(), self.target_folder.clone())
+        }
+
+        /// Records an archive that is still new, so media can still show up.
+        pub(crate) async fn takeout(&self) -> i32 {
+            store_file(&self.conn, DriveItem::File("drive-id".to_owned(), "takeout-001.zip".to_owned()))
+                .await
+                .unwrap();
+            list_takeouts(&self.conn).await.unwrap()[0].id
+        }
+
+        pub(crate) async fn extract(
+            &self,
+            takeout_zip_id: i32,
+            path: &str,
+            check_association: bool,
+        ) -> file_in_zip::Model {
+            let name = Path::new(path).file_name().unwrap().to_str().unwrap().to_owned();
+            let new_file = NewFileInZip {
+                takeout_zip_id,
+                name,
+                path: path.to_owned(),
+                ..Default::default()
+            };
+            create_file_in_zip(&self.conn, new_file, check_association).await.unwrap()
+        }
+
+        pub(crate) async fn reload(&self, model: &file_in_zip::Model) -> file_in_zip::Model {
+            fetch_file_in_zip_by_id(&self.conn, model.id).await.unwrap().unwrap()
+        }
+
+        pub(crate) async fn set_status(&self, model: file_in_zip::Model, status: &str) -> file_in_zip::Model {
+            let mut model = model.into_active_model();
+            model.status = Set(status.to_owned());
+            update_file_in_zip(&self.conn, model).await.unwrap()
+        }
+
+        pub(crate) async fn catalog(&self) -> Vec<media_file::Model> {
+            media_file::Entity::find().all(&self.conn).await.unwrap()
+        }
+
+        pub(crate) async fn set_takeouts_status(&self, status: &str) {
+            takeout_zip::Entity::update_many()
+                .col_expr(Column::Status, Expr::value(status))
+                .exec(&self.conn)
+                .await
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_db::TestDb;
+    use super::*;
+
+    #[tokio::test]
+    async fn sidecar_prefers_the_still_over_a_video_original() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.MP4", false).await;
+        let still = db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.HEIC", false).await;
+
+        let found = fetch_associated_if_exists(&db.conn, "Takeout/Photos/IMG_0001", FILE_TYPE_SIDECAR).await.unwrap();
+        assert_eq!(found.map(|found| found.id), Some(still.id));
+    }
+
+    /// Stores a media file with `content_hash`, pointing at `duplicate_of_id`.
+    async fn hashed(
+        db: &TestDb,
+        takeout_zip_id: i32,
+        path: &str,
+        content_hash: &str,
+        duplicate_of_id: Option<i32>,
+    ) -> file_in_zip::Model {
+        let new_file = NewFileInZip {
+            takeout_zip_id,
+            name: Path::new(path).file_name().unwrap().to_str().unwrap().to_owned(),
+            path: path.to_owned(),
+            content_hash: Some(content_hash.to_owned()),
+            duplicate_of_id,
+            ..Default::default()
+        };
+        create_file_in_zip(&db.conn, new_file, true).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_first_copy_of_a_content_is_its_canonical() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let canonical = hashed(&db, takeout_zip_id, "Takeout/Photos/IMG_0001.jpg", "abc", None).await;
+        hashed(&db, takeout_zip_id, "Takeout/Album/IMG_0001.jpg", "abc", Some(canonical.id)).await;
+
+        let found = fetch_media_by_content_hash(&db.conn, "abc").await.unwrap();
+        assert_eq!(found.map(|found| found.id), Some(canonical.id));
+        assert!(fetch_media_by_content_hash(&db.conn, "def").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn sidecar_after_its_duplicate_is_set_aside() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let canonical = hashed(&db, takeout_zip_id, "Takeout/Photos/IMG_0001.jpg", "abc", None).await;
+        let duplicate = hashed(&db, takeout_zip_id, "Takeout/Album/IMG_0001.jpg", "abc", Some(canonical.id)).await;
+        let json = db.extract(takeout_zip_id, "Takeout/Album/IMG_0001.jpg.json", true).await;
+
+        assert_eq!(json.related_id, Some(duplicate.id));
+        assert_eq!(json.status, MEDIA_STATUS_DUPLICATE);
+        assert_eq!(db.reload(&duplicate).await.status, MEDIA_STATUS_DUPLICATE);
+    }
+
+    #[tokio::test]
+    async fn sidecar_before_its_duplicate_is_set_aside() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let canonical = hashed(&db, takeout_zip_id, "Takeout/Photos/IMG_0001.jpg", "abc", None).await;
+        let json = db.extract(takeout_zip_id, "Takeout/Album/IMG_0001.jpg.json", true).await;
+        assert_eq!(json.status, MEDIA_STATUS_NO_RELATED);
+        let duplicate = hashed(&db, takeout_zip_id, "Takeout/Album/IMG_0001.jpg", "abc", Some(canonical.id)).await;
+
+        let json = db.reload(&json).await;
+        assert_eq!(json.related_id, Some(duplicate.id));
+        assert_eq!(json.status, MEDIA_STATUS_DUPLICATE);
+        assert!(fetch_json_without_media_and_set_status_to_processing(&db.conn).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn truncated_sidecar_meets_media_stored_before_it() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let media = db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_name.jpg", true).await;
+        db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_name/IMG_0001.jpg", true).await;
+        let json = db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_na.json", true).await;
+
+        assert_eq!(json.file_type, FILE_TYPE_SIDECAR);
+        assert_eq!(json.related_id, Some(media.id));
+        assert_eq!(db.reload(&media).await.related_id, Some(json.id));
+    }
+
+    #[tokio::test]
+    async fn media_stored_after_its_truncated_sidecar_meets_it() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let json = db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_na.json", true).await;
+        let nested = db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_na/IMG_0001.jpg", true).await;
+        let media = db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_name.jpg", true).await;
+        let edited = db.extract(takeout_zip_id, "Takeout/Photos/Screenshot_20190101-123456_Some_long_app_name-edited.jpg", true).await;
+
+        assert_eq!(nested.related_id, None);
+        assert_eq!(media.related_id, Some(json.id));
+        assert_eq!(edited.related_id, Some(json.id));
+        assert_eq!(db.reload(&json).await.related_id, Some(media.id));
+    }
+
+    #[tokio::test]
+    async fn short_json_without_a_media_extension_is_not_matched() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let json = db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.json", true).await;
+        let media = db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.jpg", true).await;
+        let longer = db.extract(takeout_zip_id, "Takeout/Photos/IMG_00012.jpg", true).await;
+
+        assert_eq!(json.file_type, FILE_TYPE_OTHER);
+        assert_eq!(media.related_id, None);
+        assert_eq!(longer.related_id, None);
+    }
+
+    #[tokio::test]
+    async fn undatable_rows_are_backfilled_once() {
+        let db = test_db::fresh().await;
+        media_file::ActiveModel {
+            file_name: Set("IMG_0001.jpg".to_owned()),
+            path: Set("/library/IMG_0001.jpg".to_owned()),
+            json_meta: Set(serde_json::Value::Null),
+            ..Default::default()
+        }
+        .insert(&db.conn)
+        .await
+        .unwrap();
+
+        let media_files = fetch_media_files_without_taken_at(&db.conn).await.unwrap();
+        assert_eq!(media_files.len(), 1);
+        let media_file = update_media_file_details(&db.conn, media_files[0].clone(), &MediaFileDetails::default())
+            .await
+            .unwrap();
+
+        assert_eq!(media_file.taken_at, None);
+        assert!(fetch_media_files_without_taken_at(&db.conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rows_without_a_place_nearby_are_looked_up_once() {
+        let db = test_db::fresh().await;
+        media_file::ActiveModel {
+            file_name: Set("IMG_0001.jpg".to_owned()),
+            path: Set("/library/IMG_0001.jpg".to_owned()),
+            json_meta: Set(serde_json::Value::Null),
+            latitude: Set(Some(-30.0)),
+            longitude: Set(Some(-120.0)),
+            ..Default::default()
+        }
+        .insert(&db.conn)
+        .await
+        .unwrap();
+
+        let media_files = fetch_media_files_without_place(&db.conn).await.unwrap();
+        assert_eq!(media_files.len(), 1);
+        let media_file = set_place(&db.conn, media_files[0].clone(), None).await.unwrap();
+
+        assert_eq!(media_file.country_code, None);
+        assert!(fetch_media_files_without_place(&db.conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn live_photo_video_arriving_first_follows_its_still() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let video = db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.MP4", true).await;
+        let json = db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.HEIC.json", true).await;
+        // Until every archive is examined the video may still turn out to be a
+        // motion half, so it is not placed on its own.
+        assert!(fetch_new_media_and_set_status_to_processing(&db.conn).await.unwrap().is_none());
+
+        let still = db.extract(takeout_zip_id, "Takeout/Photos/IMG_0001.HEIC", true).await;
+        let (video, json) = (db.reload(&video).await, db.reload(&json).await);
+        assert_eq!(json.related_id, Some(still.id));
+        assert_eq!(video.variant, MEDIA_VARIANT_MOTION);
+        assert_eq!(video.group_id, Some(still.id));
+
+        // The motion half waits for the still, then follows it.
+        let next = fetch_new_media_and_set_status_to_processing(&db.conn).await.unwrap().unwrap();
+        assert_eq!(next.id, still.id);
+        assert!(fetch_new_media_and_set_status_to_processing(&db.conn).await.unwrap().is_none());
+        db.set_status(next, MEDIA_STATUS_PROCESSED).await;
+        let next = fetch_new_media_and_set_status_to_processing(&db.conn).await.unwrap().unwrap();
+        assert_eq!(next.id, video.id);
+    }
+}
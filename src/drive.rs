@@ -197,10 +197,6 @@ pub fn get_target_folder() -> PathBuf {
         .join(env::var("TARGET_FOLDER").expect("Missing the TARGET_FOLDER environment variable."))
 }
 
-pub fn get_file_path(file_name: &str) -> PathBuf {
-    get_target_folder().join(file_name)
-}
-
 fn get_token_file_path() -> PathBuf {
     let home_dir = dirs::home_dir().expect("Could not determine home directory");
     home_dir.join(".config/takeout-fixer/tokens.json")
@@ -0,0 +1,170 @@
+use crate::db::{fetch_album_media, list_albums, MEDIA_STATUS_PROCESSED};
+use crate::file_list_widget::{FileListWidget, LoadingState};
+use crate::media_utils::sanitize_file_name;
+use crate::placement::{plan_placement, Placement};
+use crate::settings::{get_album_export_mode, AlbumExportMode};
+use anyhow::Result;
+use entity::album::Model as AlbumModel;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+pub const ALBUM_EXPORT_FOLDER: &str = "Albums";
+
+impl FileListWidget {
+    pub fn export_albums(&self) {
+        let this = self.clone();
+        tokio::spawn(this.export_albums_with_progress());
+    }
+
+    async fn export_albums_with_progress(self) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.write_albums().await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    async fn write_albums(&self) -> Result<()> {
+        let export_folder = self.target_folder.join(ALBUM_EXPORT_FOLDER);
+        fs::create_dir_all(&export_folder).await?;
+        let mode = get_album_export_mode();
+        let albums = list_albums(&self.db).await?;
+        let names = album_names(&albums);
+        let len = albums.len();
+        for (i, (album, album_name)) in albums.iter().zip(&names).enumerate() {
+            self.update_item_progress(&album.title, "export album", i as f64 / len as f64);
+            // Only files that made it into the library can be pointed at.
+            let paths: Vec<PathBuf> = fetch_album_media(&self.db, album)
+                .await?
+                .into_iter()
+                .filter(|media_file| media_file.status == MEDIA_STATUS_PROCESSED)
+                .map(|media_file| PathBuf::from(media_file.path))
+                .collect();
+            write_album(&export_folder, album, album_name, &paths, mode).await?;
+            self.update_item_progress(&album.title, "export album", 1.0);
+        }
+        Ok(())
+    }
+}
+
+/// Names each album is exported under. Albums sharing a title, which Takeout
+/// keeps in separate folders, keep apart too: the one stored first gets the
+/// plain title, the others are suffixed with their id.
+fn album_names(albums: &[AlbumModel]) -> Vec<String> {
+    let mut taken = HashSet::new();
+    albums
+        .iter()
+        .map(|album| {
+            let name = sanitize_file_name(&album.title);
+            if taken.insert(name.to_lowercase()) {
+                name
+            } else {
+                format!("{}-{}", name, album.id)
+            }
+        })
+        .collect()
+}
+
+async fn write_album(
+    export_folder: &Path,
+    album: &AlbumModel,
+    album_name: &str,
+    paths: &[PathBuf],
+    mode: AlbumExportMode,
+) -> Result<()> {
+    match mode {
+        AlbumExportMode::Symlink | AlbumExportMode::Hardlink => {
+            let album_folder = export_folder.join(album_name);
+            fs::create_dir_all(&album_folder).await?;
+            for path in paths {
+                // Photos from different folders may share a name, the same
+                // content is only linked once.
+                let link = match plan_placement(path, &album_folder.join(path.file_name().unwrap())).await? {
+                    Placement::Duplicate(_) => continue,
+                    placement => placement.path().to_path_buf(),
+                };
+                if mode == AlbumExportMode::Hardlink {
+                    fs::hard_link(path, &link).await?;
+                } else {
+                    symlink(path, &link).await?;
+                }
+            }
+        }
+        AlbumExportMode::M3u => {
+            let playlist = paths.iter().fold("#EXTM3U\n".to_string(), |acc, path| {
+                format!("{}{}\n", acc, path.display())
+            });
+            fs::write(export_folder.join(format!("{}.m3u", album_name)), playlist).await?;
+        }
+        AlbumExportMode::Json => {
+            let list = serde_json::json!({
+                "title": album.title,
+                "items": paths,
+            });
+            fs::write(
+                export_folder.join(format!("{}.json", album_name)),
+                serde_json::to_string_pretty(&list)?,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn symlink(original: &Path, link: &Path) -> Result<()> {
+    Ok(fs::symlink(original, link).await?)
+}
+
+#[cfg(not(unix))]
+async fn symlink(original: &Path, link: &Path) -> Result<()> {
+    Ok(fs::hard_link(original, link).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn album(id: i32, title: &str) -> AlbumModel {
+        AlbumModel {
+            id,
+            title: title.to_owned(),
+            path: format!("Takeout/Google Photos/{}", id),
+            json_meta: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn albums_sharing_a_title_get_their_own_name() {
+        let albums = [album(1, "Trip"), album(2, "Beach"), album(3, "trip")];
+        assert_eq!(album_names(&albums), ["Trip", "Beach", "trip-3"]);
+    }
+
+    #[tokio::test]
+    async fn links_photos_sharing_a_name_once_per_content() {
+        let folder = tempfile::tempdir().unwrap();
+        let library = folder.path().join("library");
+        for (path, content) in [("a/IMG_0001.JPG", "one"), ("b/IMG_0001.JPG", "two"), ("c/IMG_0001.JPG", "one")] {
+            let path = library.join(path);
+            fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+            fs::write(path, content).await.unwrap();
+        }
+        let paths: Vec<PathBuf> = ["a", "b", "c"]
+            .iter()
+            .map(|folder| library.join(folder).join("IMG_0001.JPG"))
+            .collect();
+        let export_folder = folder.path().join(ALBUM_EXPORT_FOLDER);
+
+        write_album(&export_folder, &album(1, "Trip"), "Trip", &paths, AlbumExportMode::Hardlink)
+            .await
+            .unwrap();
+
+        let mut entries = std::fs::read_dir(export_folder.join("Trip"))
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["one", "two"]);
+    }
+}
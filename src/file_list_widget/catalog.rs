@@ -0,0 +1,122 @@
+use crate::date_resolver::{resolve_taken_date, ResolvedDate};
+use crate::db::{
+    fetch_media_files_without_place, fetch_media_files_without_taken_at, set_place, update_media_file_details,
+    MediaFileDetails,
+};
+use crate::file_list_widget::{FileListWidget, GeoData, LoadingState, PhotoMetadata};
+use crate::geocode::place_at;
+use crate::media_utils::{content_hash, mime_type, read_media_details};
+use crate::perceptual_hash::perceptual_hash;
+use crate::settings::get_date_source_priority;
+use crate::timezone::resolve_offset;
+use anyhow::Result;
+use chrono::FixedOffset;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use tokio::fs;
+
+impl FileListWidget {
+    pub fn backfill_catalog(&self) {
+        let this = self.clone();
+        tokio::spawn(this.backfill_catalog_with_progress());
+    }
+
+    async fn backfill_catalog_with_progress(self) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.backfill_media_files().await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    /// Fills the structured columns of catalog rows that only have `json_meta`,
+    /// then names the places of rows that have a location but no place.
+    async fn backfill_media_files(&self) -> Result<()> {
+        let media_files = fetch_media_files_without_taken_at(&self.db).await?;
+        let len = media_files.len();
+        for (i, media_file) in media_files.into_iter().enumerate() {
+            self.update_item_progress(&media_file.file_name, "backfill catalog", i as f64 / len as f64);
+            let raw_json = Some(&media_file.json_meta).filter(|raw_json| !raw_json.is_null());
+            let taken = self
+                .resolve_placed_taken_date(&media_file.path, &media_file.file_name, raw_json, media_file.utc_offset)
+                .await;
+            let details = describe_media_file(&media_file.path, raw_json, taken).await?;
+            update_media_file_details(&self.db, media_file, &details).await?;
+        }
+
+        let media_files = fetch_media_files_without_place(&self.db).await?;
+        let len = media_files.len();
+        for (i, media_file) in media_files.into_iter().enumerate() {
+            self.update_item_progress(&media_file.file_name, "name place", i as f64 / len as f64);
+            let (Some(latitude), Some(longitude)) = (media_file.latitude, media_file.longitude) else {
+                continue;
+            };
+            let location = GeoData {
+                latitude,
+                longitude,
+                altitude: media_file.altitude.unwrap_or_default(),
+            };
+            set_place(&self.db, media_file, place_at(location).as_ref()).await?;
+        }
+        Ok(())
+    }
+
+    /// Dates a file that is already in the library, keeping the offset it was
+    /// placed with when that is known.
+    pub(super) async fn resolve_placed_taken_date(
+        &self,
+        path: &str,
+        name: &str,
+        raw_json: Option<&Value>,
+        utc_offset: Option<i32>,
+    ) -> Option<ResolvedDate> {
+        let json_taken = raw_json.and_then(|raw_json| self.get_date_taken_from_json(raw_json).ok().flatten());
+        let taken = resolve_taken_date(path, name, json_taken, None, &get_date_source_priority()).await?;
+        let offset = match utc_offset.and_then(FixedOffset::east_opt) {
+            Some(offset) => offset,
+            None => {
+                let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+                let location = raw_json.and_then(|raw_json| PhotoMetadata::deserialize(raw_json).ok()?.best_location());
+                resolve_offset(path, extension, location, &taken).await
+            }
+        };
+        Some(taken.with_offset(offset))
+    }
+}
+
+/// Gathers the catalog columns from the placed file and its sidecar.
+pub(super) async fn describe_media_file(
+    path: &str,
+    raw_json: Option<&Value>,
+    taken: Option<ResolvedDate>,
+) -> Result<MediaFileDetails> {
+    // Sidecars missing fields we do not need here should not stop the catalog.
+    let metadata = raw_json.and_then(|raw_json| PhotoMetadata::deserialize(raw_json).ok());
+    let extension = Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let size = match fs::metadata(path).await {
+        Ok(file_metadata) => Some(file_metadata.len() as i64),
+        Err(_) => None,
+    };
+    let content_hash = match size {
+        Some(_) => Some(content_hash(path).await?),
+        None => None,
+    };
+    // A file the decoder chokes on is catalogued all the same.
+    let perceptual_hash = perceptual_hash(path).await.unwrap_or_default();
+    let location = metadata.as_ref().and_then(PhotoMetadata::best_location);
+    Ok(MediaFileDetails {
+        location,
+        place: location.and_then(place_at),
+        taken,
+        // Formats nom-exif cannot read simply have no camera details.
+        media: read_media_details(path).await.unwrap_or_default(),
+        mime_type: mime_type(extension),
+        size,
+        content_hash,
+        perceptual_hash,
+        favorite: metadata.as_ref().is_some_and(|metadata| metadata.favorited),
+        archived: metadata.as_ref().is_some_and(|metadata| metadata.archived),
+        trashed: metadata.as_ref().is_some_and(|metadata| metadata.trashed),
+    })
+}
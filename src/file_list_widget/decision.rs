@@ -0,0 +1,136 @@
+use crate::date_resolver::ResolvedDate;
+use crate::db::{MEDIA_VARIANT_EDITED, MEDIA_VARIANT_ORIGINAL};
+use crate::file_list_widget::{FileListWidget, PhotoMetadata};
+use crate::placement::Placement;
+use crate::plan::PlanAction;
+use crate::settings::{get_edited_policy, EditedPolicy};
+use crate::timezone::resolve_known_offset;
+use chrono::FixedOffset;
+use std::path::PathBuf;
+
+/// What is known about a media file when a run decides what to do with it. A
+/// dry run reads it from the archive, a real run from the extracted file.
+pub(super) struct MediaFacts<'a> {
+    pub variant: &'a str,
+    /// An edited copy of the same photo is in the archives.
+    pub has_edited_variant: bool,
+    pub metadata: Option<&'a PhotoMetadata>,
+    /// The taken date, before the offset it was taken in is known.
+    pub taken: Option<ResolvedDate>,
+    pub exif_offset: Option<FixedOffset>,
+}
+
+/// Why a media file is left out of the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SkipReason {
+    EditedPolicy,
+    Trashed,
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::EditedPolicy => "edited policy",
+            SkipReason::Trashed => "trashed",
+        }
+    }
+}
+
+/// What happens to a media file, short of the name it is placed under.
+#[derive(Debug)]
+pub(super) enum MediaDecision {
+    Skip(SkipReason),
+    NoDate,
+    /// Placed below `library_root`, in the folders its date renders to.
+    Place { library_root: PathBuf, taken: ResolvedDate },
+}
+
+impl FileListWidget {
+    /// Decides a media file the same way for a dry run and a real one: the
+    /// edited policy first, then the sidecar's flags, then the taken date in
+    /// the offset it was taken in.
+    pub(super) fn decide_media(&self, facts: MediaFacts) -> MediaDecision {
+        let excluded = match get_edited_policy() {
+            EditedPolicy::KeepBoth => false,
+            EditedPolicy::OriginalOnly => facts.variant == MEDIA_VARIANT_EDITED,
+            EditedPolicy::EditedOnly => facts.variant == MEDIA_VARIANT_ORIGINAL && facts.has_edited_variant,
+        };
+        if excluded {
+            return MediaDecision::Skip(SkipReason::EditedPolicy);
+        }
+        let Some(library_root) = self.get_library_root(facts.metadata) else {
+            return MediaDecision::Skip(SkipReason::Trashed);
+        };
+        let Some(taken) = facts.taken else {
+            return MediaDecision::NoDate;
+        };
+        let location = facts.metadata.and_then(PhotoMetadata::best_location);
+        let offset = resolve_known_offset(facts.exif_offset, location, &taken);
+        MediaDecision::Place {
+            library_root,
+            taken: taken.with_offset(offset),
+        }
+    }
+}
+
+/// The plan action a placement amounts to.
+pub(super) fn placement_action(placement: &Placement) -> PlanAction {
+    match placement {
+        Placement::Free(_) => PlanAction::Place,
+        Placement::Renamed { .. } => PlanAction::Rename,
+        Placement::Duplicate(_) => PlanAction::Duplicate,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date_resolver::{DateConfidence, DateSource};
+        use chrono::DateTime;
+
+    fn wall_clock(taken: &str) -> ResolvedDate {
+        ResolvedDate {
+            taken: DateTime::parse_from_rfc3339(taken).unwrap().to_utc(),
+            source: DateSource::Exif,
+            confidence: DateConfidence::High,
+            wall_clock: true,
+            offset: None,
+        }
+    }
+
+    fn facts(taken: Option<ResolvedDate>, exif_offset: Option<FixedOffset>) -> MediaFacts<'static> {
+        MediaFacts {
+            variant: MEDIA_VARIANT_ORIGINAL,
+            has_edited_variant: true,
+            metadata: None,
+            taken,
+            exif_offset,
+        }
+    }
+
+    #[tokio::test]
+    async fn places_dated_files_in_the_offset_they_were_taken_in() {
+        let db = crate::db::test_db::fresh().await;
+        let widget = db.widget();
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let decision = widget.decide_media(facts(Some(wall_clock("2019-07-04T23:30:00Z")), Some(offset)));
+        let MediaDecision::Place { library_root, taken } = decision else {
+            panic!("{:?}", decision);
+        };
+        assert_eq!(library_root, db.target_folder);
+        assert_eq!(taken.local().to_rfc3339(), "2019-07-04T23:30:00+02:00");
+
+        assert!(matches!(widget.decide_media(facts(None, None)), MediaDecision::NoDate));
+    }
+
+    #[test]
+    fn names_what_a_placement_amounts_to() {
+        let path = PathBuf::from("2019/IMG_0001.jpg");
+        assert_eq!(placement_action(&Placement::Free(path.clone())), PlanAction::Place);
+        assert_eq!(
+            placement_action(&Placement::Renamed { path: path.clone(), taken_by: path.clone() }),
+            PlanAction::Rename
+        );
+        assert_eq!(placement_action(&Placement::Duplicate(path)), PlanAction::Duplicate);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::db::fetch_content_duplicates;
+use crate::file_list_widget::{FileListWidget, LoadingState};
+use anyhow::Result;
+use serde_json::json;
+use tokio::fs;
+
+pub const DUPLICATE_REPORT_FILE: &str = "duplicates.json";
+
+impl FileListWidget {
+    pub fn report_duplicates(&self) {
+        let this = self.clone();
+        tokio::spawn(this.report_duplicates_with_progress());
+    }
+
+    async fn report_duplicates_with_progress(self) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.write_duplicate_report().await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    /// Lists every copy that was recorded against a canonical file instead of
+    /// being kept, and how much space that saved.
+    async fn write_duplicate_report(&self) -> Result<()> {
+        let duplicates = fetch_content_duplicates(&self.db).await?;
+        let bytes_saved: i64 = duplicates.iter().filter_map(|(duplicate, _)| duplicate.size).sum();
+        let entries: Vec<_> = duplicates
+            .iter()
+            .map(|(duplicate, canonical)| {
+                json!({
+                    "path": duplicate.archive_path.as_ref().unwrap_or(&duplicate.path),
+                    "takeout_zip_id": duplicate.takeout_zip_id,
+                    "size": duplicate.size,
+                    "content_hash": duplicate.content_hash,
+                    "canonical": canonical.path,
+                })
+            })
+            .collect();
+        let report = json!({
+            "duplicates": entries.len(),
+            "bytes_saved": bytes_saved,
+            "files": entries,
+        });
+        let target_folder = self.target_folder.clone();
+        fs::create_dir_all(&target_folder).await?;
+        let report_path = target_folder.join(DUPLICATE_REPORT_FILE);
+        fs::write(&report_path, serde_json::to_string_pretty(&report)?).await?;
+        let summary = format!(
+            "{} copies, {:.1} MiB saved, see {}",
+            entries.len(),
+            bytes_saved as f64 / (1024.0 * 1024.0),
+            report_path.display()
+        );
+        self.update_item_progress(&summary, "duplicate report", 0.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use crate::db::{
+    fetch_album_media_files, fetch_exported_paths, fetch_media_files_to_export, list_albums, store_export_item,
+};
+use crate::file_list_widget::{FileListWidget, GeoData, LoadingState, PhotoMetadata};
+use crate::geocode::Place;
+use crate::media_utils::{media_type, sanitize_file_name};
+use crate::path_template::{PathTemplate, TemplateValues};
+use crate::placement::{plan_placement, transfer, Placement};
+use crate::settings::{
+    get_export_folder, get_export_mode, get_export_profile, get_month_locale, get_source_account, ExportProfile,
+    PlacementMode,
+};
+use crate::xmp::{to_xmp, xmp_path, XmpData};
+use anyhow::{Context, Result};
+use entity::media_file::Model as MediaFileModel;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Exports go next to the target folder, in a folder named after it with this
+/// suffix, unless `EXPORT_FOLDER` is set. A photo server pointed at the export
+/// then does not index the library below it too.
+pub const EXPORT_FOLDER_SUFFIX: &str = "-export";
+pub const EXPORT_ALBUM_FOLDER: &str = "Albums";
+
+/// How a photo server wants its files arranged.
+struct ExportLayout {
+    path_template: &'static str,
+    xmp_keeps_extension: bool,
+    /// Albums are folders of linked files, rather than manifests listing the
+    /// exported paths.
+    album_folders: bool,
+}
+
+fn export_layout(profile: ExportProfile) -> ExportLayout {
+    match profile {
+        // Immich's default storage template, with the `IMG_1.jpg.xmp` it
+        // looks for first.
+        ExportProfile::Immich => ExportLayout {
+            path_template: "{year}/{year}-{month}-{day}/{name}",
+            xmp_keeps_extension: true,
+            // Immich imports every path of an external library as an asset of
+            // its own, hard links included, so album folders would show each
+            // photo twice.
+            album_folders: false,
+        },
+        // PhotoPrism sorts originals by year and month and pairs `IMG_1.xmp`
+        // with every file named `IMG_1`.
+        ExportProfile::PhotoPrism => ExportLayout {
+            path_template: "{year}/{month}/{name}",
+            xmp_keeps_extension: false,
+            album_folders: true,
+        },
+    }
+}
+
+impl FileListWidget {
+    pub fn export_library(&self) {
+        let this = self.clone();
+        tokio::spawn(this.export_library_with_progress());
+    }
+
+    async fn export_library_with_progress(self) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.write_export().await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    /// Exports the catalog rows the profile has not seen yet, each with an
+    /// `.xmp` written from the catalog, then fills the album folders.
+    async fn write_export(&self) -> Result<()> {
+        let profile = get_export_profile();
+        let layout = export_layout(profile);
+        let template = PathTemplate::parse(layout.path_template)?;
+        let mode = get_export_mode();
+        let export_root = get_export_folder()
+            .unwrap_or_else(|| default_export_root(&self.target_folder, profile));
+
+        let media_files = fetch_media_files_to_export(&self.db, profile.as_str()).await?;
+        let len = media_files.len();
+        let mut exported = HashSet::new();
+        let mut undated = 0;
+        for (i, media_file) in media_files.into_iter().enumerate() {
+            self.update_item_progress(&media_file.file_name, "export", i as f64 / len as f64);
+            let Some(relative) = export_path(&template, &media_file) else {
+                undated += 1;
+                continue;
+            };
+            let path = self
+                .export_file(Path::new(&media_file.path), &export_root.join(relative), mode)
+                .await?;
+            let xmp = xmp_path(&path, layout.xmp_keeps_extension);
+            if !fs::try_exists(&xmp).await? {
+                fs::write(&xmp, to_xmp(&catalog_xmp_data(&media_file))).await?;
+            }
+            store_export_item(&self.db, media_file.id, profile.as_str(), &path.to_string_lossy()).await?;
+            exported.insert(media_file.id);
+        }
+
+        let linked = if layout.album_folders {
+            self.export_album_folders(&export_root, profile, &exported, layout.xmp_keeps_extension, mode)
+                .await?
+        } else {
+            self.export_album_manifests(&export_root, profile).await?
+        };
+        let summary = format!(
+            "{} files and {} album entries exported for {} to {}, {} without a date",
+            exported.len(),
+            linked,
+            profile.as_str(),
+            export_root.display(),
+            undated
+        );
+        self.update_item_progress(&summary, "export", 0.0);
+        Ok(())
+    }
+
+    /// Puts a library file at `target`, or next to it when another file has
+    /// the name. The same content already there is taken as exported.
+    async fn export_file(&self, source: &Path, target: &Path, mode: PlacementMode) -> Result<PathBuf> {
+        let folder = target
+            .parent()
+            .with_context(|| format!("Export target {} has no folder", target.display()))?;
+        fs::create_dir_all(folder).await?;
+        let placement = plan_placement(source, target).await?;
+        if !matches!(placement, Placement::Duplicate(_)) {
+            transfer(source, placement.path(), mode).await?;
+        }
+        Ok(placement.path().to_path_buf())
+    }
+
+    /// Gives every album a folder with its exported files and their `.xmp`.
+    /// Files from earlier exports are only added when their name is free, so
+    /// album folders are not hashed over again on every export.
+    async fn export_album_folders(
+        &self,
+        export_root: &Path,
+        profile: ExportProfile,
+        exported: &HashSet<i32>,
+        xmp_keeps_extension: bool,
+        mode: PlacementMode,
+    ) -> Result<usize> {
+        let exported_paths = fetch_exported_paths(&self.db, profile.as_str()).await?;
+        let albums = list_albums(&self.db).await?;
+        let len = albums.len();
+        let mut linked = 0;
+        for (i, album) in albums.iter().enumerate() {
+            self.update_item_progress(&album.title, "export album", i as f64 / len as f64);
+            let album_folder = export_root.join(EXPORT_ALBUM_FOLDER).join(sanitize_file_name(&album.title));
+            for media_file in fetch_album_media_files(&self.db, album).await? {
+                let Some(source) = exported_paths.get(&media_file.id).map(PathBuf::from) else {
+                    continue;
+                };
+                let name = source
+                    .file_name()
+                    .with_context(|| format!("Exported file {} has no name", source.display()))?;
+                let target = album_folder.join(name);
+                if !exported.contains(&media_file.id) && fs::try_exists(&target).await? {
+                    continue;
+                }
+                let path = self.export_file(&source, &target, mode).await?;
+                let xmp_source = xmp_path(&source, xmp_keeps_extension);
+                let xmp_target = xmp_path(&path, xmp_keeps_extension);
+                if fs::try_exists(&xmp_source).await? && !fs::try_exists(&xmp_target).await? {
+                    transfer(&xmp_source, &xmp_target, mode).await?;
+                }
+                linked += 1;
+            }
+            self.update_item_progress(&album.title, "export album", 1.0);
+        }
+        Ok(linked)
+    }
+
+    /// Writes an `Albums/<title>.json` per album with the title and the
+    /// exported paths, relative to the export root, for a script or the
+    /// server's API to build the album from.
+    async fn export_album_manifests(&self, export_root: &Path, profile: ExportProfile) -> Result<usize> {
+        let exported_paths = fetch_exported_paths(&self.db, profile.as_str()).await?;
+        let album_folder = export_root.join(EXPORT_ALBUM_FOLDER);
+        fs::create_dir_all(&album_folder).await?;
+        let albums = list_albums(&self.db).await?;
+        let len = albums.len();
+        let mut listed = 0;
+        for (i, album) in albums.iter().enumerate() {
+            self.update_item_progress(&album.title, "export album", i as f64 / len as f64);
+            let items: Vec<PathBuf> = fetch_album_media_files(&self.db, album)
+                .await?
+                .iter()
+                .filter_map(|media_file| exported_paths.get(&media_file.id))
+                .map(|path| {
+                    let path = Path::new(path);
+                    path.strip_prefix(export_root).unwrap_or(path).to_path_buf()
+                })
+                .collect();
+            listed += items.len();
+            let manifest = serde_json::json!({
+                "title": album.title,
+                "items": items,
+            });
+            fs::write(
+                album_folder.join(format!("{}.json", sanitize_file_name(&album.title))),
+                serde_json::to_string_pretty(&manifest)?,
+            )
+            .await?;
+            self.update_item_progress(&album.title, "export album", 1.0);
+        }
+        Ok(listed)
+    }
+}
+
+/// The export folder of a profile when `EXPORT_FOLDER` is unset: next to the
+/// target folder, so hard links stay on its file system.
+fn default_export_root(target: &Path, profile: ExportProfile) -> PathBuf {
+    let name = target.file_name().and_then(|name| name.to_str()).unwrap_or("library");
+    target
+        .with_file_name(format!("{}{}", name, EXPORT_FOLDER_SUFFIX))
+        .join(profile.as_str())
+}
+
+/// Where the profile puts a catalog row, below the export folder. Rows without
+/// a taken date have not been backfilled and cannot be placed.
+fn export_path(template: &PathTemplate, media_file: &MediaFileModel) -> Option<PathBuf> {
+    let taken = media_file.taken_at?;
+    let name = Path::new(&media_file.path).file_name()?.to_str()?;
+    let extension = Path::new(name).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let account = get_source_account();
+    let place = catalog_place(media_file);
+    Some(template.render(&TemplateValues {
+        taken,
+        name,
+        album: None,
+        camera_make: media_file.camera_make.as_deref(),
+        camera_model: media_file.camera_model.as_deref(),
+        media_type: media_type(extension),
+        account: account.as_deref(),
+        place: place.as_ref(),
+        locale: get_month_locale(),
+    }))
+}
+
+/// The place named on a catalog row.
+fn catalog_place(media_file: &MediaFileModel) -> Option<Place> {
+    Some(Place {
+        country_code: media_file.country_code.clone()?,
+        country: media_file.country.clone()?,
+        region: media_file.region.clone(),
+        city: media_file.city.clone()?,
+    })
+}
+
+/// The sidecar contents from the catalog columns. Descriptions and people have
+/// no columns and come from the json kept on the row.
+fn catalog_xmp_data(media_file: &MediaFileModel) -> XmpData {
+    let metadata = PhotoMetadata::deserialize(&media_file.json_meta).ok();
+    let location = match (media_file.latitude, media_file.longitude) {
+        (Some(latitude), Some(longitude)) => Some(GeoData {
+            latitude,
+            longitude,
+            altitude: media_file.altitude.unwrap_or_default(),
+        }),
+        _ => None,
+    };
+    XmpData {
+        taken: media_file.taken_at,
+        location,
+        description: metadata
+            .as_ref()
+            .map(|metadata| metadata.description.clone())
+            .unwrap_or_default(),
+        people: metadata
+            .map(|metadata| metadata.people.into_iter().map(|person| person.name).collect())
+            .unwrap_or_default(),
+        favorite: media_file.favorite,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_next_to_the_target_folder() {
+        let target = Path::new("/home/me/Photos");
+        let root = default_export_root(target, ExportProfile::Immich);
+        assert!(!root.starts_with(target));
+        assert_eq!(root, Path::new("/home/me/Photos-export/immich"));
+    }
+}
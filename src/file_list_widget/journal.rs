@@ -0,0 +1,365 @@
+use crate::db::{
+    fetch_last_run_id, fetch_last_run_id_of_takeout, fetch_operations_to_undo,
+    has_outstanding_operations, record_operation, restore_file_in_zip, NewOperation, OperationFilter,
+    OPERATION_CREATE, OPERATION_REMOVE, OPERATION_WRITE,
+};
+use crate::file_list_widget::{FileListWidget, FileListWidgetViewState, LoadingState};
+use crate::placement::{move_without_overwrite, transfer};
+use crate::settings::PlacementMode;
+use anyhow::Result;
+use chrono::Utc;
+use entity::operation::Model as OperationModel;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs;
+
+/// What an undo rolls back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoScope {
+    /// What is selected in the TUI, see [`FileListWidget::selected_undo_filter`].
+    Selection,
+    /// The latest run with something left to undo, whatever is selected.
+    LastRun,
+}
+
+/// Runs are named after the moment they started.
+pub fn new_run_id() -> String {
+    Utc::now().format("%Y%m%dT%H%M%S%.3f").to_string()
+}
+
+impl FileListWidget {
+    /// Starts a new run, the unit operations are journaled and undone in, and
+    /// returns its id.
+    pub(super) fn begin_run(&self) -> String {
+        let run_id = new_run_id();
+        self.get_write_state().run_id = run_id.clone();
+        run_id
+    }
+
+    /// Appends a filesystem action of the current run to the journal.
+    pub(super) async fn journal(
+        &self,
+        kind: &str,
+        source: Option<&Path>,
+        destination: Option<&Path>,
+        file_in_zip_id: Option<i32>,
+    ) -> Result<()> {
+        let run_id = self.get_read_state().run_id.clone();
+        record_operation(&self.db, NewOperation {
+            run_id,
+            kind: kind.to_owned(),
+            source: source.map(|source| source.to_string_lossy().into_owned()),
+            destination: destination.map(|destination| destination.to_string_lossy().into_owned()),
+            file_in_zip_id,
+            reverts_id: None,
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub(crate) fn undo(&self, scope: UndoScope) {
+        let this = self.clone();
+        tokio::spawn(this.undo_with_progress(scope));
+    }
+
+    async fn undo_with_progress(self, scope: UndoScope) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.undo_operations(scope).await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    /// Rolls back what `scope` names, newest operation first, and points the
+    /// rows at where their files are again. Files that were written in place
+    /// get their old bytes back.
+    pub(super) async fn undo_operations(&self, scope: UndoScope) -> Result<()> {
+        if self.is_processing() {
+            return Err(anyhow::Error::msg("Stop processing before undoing"));
+        }
+        let Some(filter) = self.undo_filter(scope).await? else {
+            self.update_item_progress("nothing to undo", "undo", 0.0);
+            return Ok(());
+        };
+        let operations = fetch_operations_to_undo(&self.db, &filter).await?;
+        let run_id = self.begin_run();
+        let len = operations.len();
+        let mut skipped = 0;
+        // Going back in time, the last path seen for a file is where it started.
+        let mut restored: HashMap<i32, String> = HashMap::new();
+        for (i, operation) in operations.iter().enumerate() {
+            let name = operation.destination.as_deref().or(operation.source.as_deref()).unwrap_or_default();
+            self.update_item_progress(name, "undo", i as f64 / len as f64);
+            if !self.revert(operation, &run_id).await? {
+                skipped += 1;
+                continue;
+            }
+            // An `.xmp` written over next to a file is not where the file was.
+            if operation.kind != OPERATION_CREATE
+                && let (Some(file_in_zip_id), Some(source)) = (operation.file_in_zip_id, &operation.source)
+                && Path::new(source).extension().is_none_or(|extension| extension != "xmp")
+            {
+                restored.insert(file_in_zip_id, source.clone());
+            }
+        }
+        for (file_in_zip_id, path) in restored {
+            let extracted = !has_outstanding_operations(&self.db, file_in_zip_id).await?;
+            let log_entry = json!({
+                "decision": "undone",
+                "run": run_id,
+                "path": path,
+            });
+            restore_file_in_zip(&self.db, file_in_zip_id, &path, extracted, log_entry).await?;
+        }
+        let undone = match &filter {
+            OperationFilter::Run(run_id) => format!("run {}", run_id),
+            OperationFilter::File(file_in_zip_id) => format!("file {}", file_in_zip_id),
+        };
+        let summary = format!("{}: {} operations undone, {} skipped", undone, len - skipped, skipped);
+        self.update_item_progress(&summary, "undo", 0.0);
+        Ok(())
+    }
+
+    async fn undo_filter(&self, scope: UndoScope) -> Result<Option<OperationFilter>> {
+        match scope {
+            UndoScope::Selection => self.selected_undo_filter().await,
+            UndoScope::LastRun => Ok(fetch_last_run_id(&self.db).await?.map(OperationFilter::Run)),
+        }
+    }
+
+    /// What is selected in the TUI: the file under review among the near
+    /// duplicates, or else the latest run of the selected takeout. With
+    /// nothing selected, the latest run.
+    async fn selected_undo_filter(&self) -> Result<Option<OperationFilter>> {
+        let (file_in_zip_id, takeout_zip_id) = {
+            let state = self.get_read_state();
+            let selected = state.table_state.selected();
+            match state.view_state {
+                FileListWidgetViewState::NearDuplicates => (
+                    selected
+                        .and_then(|selected| state.near_duplicates.get(selected))
+                        .and_then(|row| row.media_file.file_in_zip_id),
+                    None,
+                ),
+                _ => (
+                    None,
+                    selected
+                        .and_then(|selected| state.zip_files.get(selected))
+                        .map(|takeout_zip| takeout_zip.id),
+                ),
+            }
+        };
+        if let Some(file_in_zip_id) = file_in_zip_id {
+            return Ok(Some(OperationFilter::File(file_in_zip_id)));
+        }
+        let run_id = match takeout_zip_id {
+            Some(takeout_zip_id) => fetch_last_run_id_of_takeout(&self.db, takeout_zip_id).await?,
+            None => fetch_last_run_id(&self.db).await?,
+        };
+        Ok(run_id.map(OperationFilter::Run))
+    }
+
+    /// Reverts one operation and journals what that took. Returns `false`
+    /// when the file is gone or its old place is taken, then nothing changes.
+    async fn revert(&self, operation: &OperationModel, run_id: &str) -> Result<bool> {
+        let source = operation.source.as_deref().map(Path::new);
+        let destination = operation.destination.as_deref().map(Path::new);
+        let (kind, reverted_source, reverted_destination) = match (operation.kind.as_str(), source, destination) {
+            (OPERATION_REMOVE, Some(source), Some(copy)) => {
+                create_parent(source).await?;
+                match transfer(copy, source, PlacementMode::Copy).await {
+                    Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::AlreadyExists) => {
+                        return Ok(false);
+                    }
+                    result => result?,
+                };
+                (PlacementMode::Copy.as_str(), Some(copy), Some(source))
+            }
+            (OPERATION_REMOVE, _, _) => return Ok(false),
+            // The copy kept from before the write takes the file's place again.
+            (OPERATION_WRITE, Some(written), Some(backup)) => {
+                create_parent(written).await?;
+                match fs::rename(backup, written).await {
+                    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+                    result => result?,
+                };
+                (PlacementMode::Move.as_str(), Some(backup), Some(written))
+            }
+            (kind, Some(source), Some(destination)) if kind == PlacementMode::Move.as_str() => {
+                create_parent(source).await?;
+                match move_without_overwrite(destination, source).await {
+                    Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::AlreadyExists) => {
+                        return Ok(false);
+                    }
+                    result => result?,
+                };
+                if let Some(parent) = destination.parent() {
+                    remove_empty_folders(parent, &self.target_folder).await;
+                }
+                (PlacementMode::Move.as_str(), Some(destination), Some(source))
+            }
+            // Copies, links and written files only have to go. A copy keeps
+            // its bytes at the source.
+            (_, source, Some(destination)) => {
+                match fs::remove_file(destination).await {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+                if let Some(parent) = destination.parent() {
+                    remove_empty_folders(parent, &self.target_folder).await;
+                }
+                (OPERATION_REMOVE, Some(destination), source)
+            }
+            _ => return Ok(false),
+        };
+        record_operation(&self.db, NewOperation {
+            run_id: run_id.to_owned(),
+            kind: kind.to_owned(),
+            source: reverted_source.map(|source| source.to_string_lossy().into_owned()),
+            destination: reverted_destination.map(|destination| destination.to_string_lossy().into_owned()),
+            file_in_zip_id: operation.file_in_zip_id,
+            reverts_id: Some(operation.id),
+        })
+        .await?;
+        Ok(true)
+    }
+}
+
+async fn create_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).await,
+        None => Ok(()),
+    }
+}
+
+/// Removes `folder` and the folders above it while they are empty, stopping
+/// at `root`.
+async fn remove_empty_folders(folder: &Path, root: &Path) {
+    let mut folder = folder;
+    while folder.starts_with(root) && folder != root && fs::remove_dir(folder).await.is_ok() {
+        match folder.parent() {
+            Some(parent) => folder = parent,
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_db::{self, TestDb};
+    use std::path::PathBuf;
+
+    /// Writes `bytes` at `path` below the target folder and returns it.
+    async fn file(db: &TestDb, path: &str, bytes: &[u8]) -> PathBuf {
+        let path = db.target_folder.join(path);
+        create_parent(&path).await.unwrap();
+        fs::write(&path, bytes).await.unwrap();
+        path
+    }
+
+    async fn journaled(
+        db: &TestDb,
+        widget: &FileListWidget,
+        kind: &str,
+        source: &Path,
+        destination: &Path,
+    ) -> OperationModel {
+        widget.journal(kind, Some(source), Some(destination), None).await.unwrap();
+        let run_id = widget.get_read_state().run_id.clone();
+        fetch_operations_to_undo(&db.conn, &OperationFilter::Run(run_id))
+            .await
+            .unwrap()
+            .remove(0)
+    }
+
+    #[tokio::test]
+    async fn reverts_each_kind_of_operation() {
+        let db = test_db::fresh().await;
+        let widget = db.widget();
+        let extracted = db.target_folder.join("Takeout");
+
+        let moved = file(&db, "2019/07/moved.jpg", b"moved").await;
+        let operation = journaled(&db, &widget, PlacementMode::Move.as_str(), &extracted.join("moved.jpg"), &moved).await;
+        assert!(widget.revert(&operation, "undo").await.unwrap());
+        assert_eq!(fs::read(extracted.join("moved.jpg")).await.unwrap(), b"moved");
+        // The folders the move created went with it.
+        assert!(!fs::try_exists(db.target_folder.join("2019")).await.unwrap());
+
+        let copied = file(&db, "2019/copied.jpg", b"copied").await;
+        file(&db, "Takeout/copied.jpg", b"copied").await;
+        let operation = journaled(&db, &widget, PlacementMode::Copy.as_str(), &extracted.join("copied.jpg"), &copied).await;
+        assert!(widget.revert(&operation, "undo").await.unwrap());
+        assert!(!fs::try_exists(&copied).await.unwrap());
+        assert!(fs::try_exists(extracted.join("copied.jpg")).await.unwrap());
+
+        let canonical = file(&db, "2019/canonical.jpg", b"same").await;
+        let operation = journaled(&db, &widget, OPERATION_REMOVE, &extracted.join("removed.jpg"), &canonical).await;
+        assert!(widget.revert(&operation, "undo").await.unwrap());
+        assert_eq!(fs::read(extracted.join("removed.jpg")).await.unwrap(), b"same");
+        assert!(fs::try_exists(&canonical).await.unwrap());
+
+        let written = file(&db, "Takeout/written.jpg", b"with dates").await;
+        let backup = file(&db, "Takeout/.1-written.jpg.orig", b"as extracted").await;
+        let operation = journaled(&db, &widget, OPERATION_WRITE, &written, &backup).await;
+        assert!(widget.revert(&operation, "undo").await.unwrap());
+        assert_eq!(fs::read(&written).await.unwrap(), b"as extracted");
+        assert!(!fs::try_exists(&backup).await.unwrap());
+
+        // Every revert is journaled as undoing its operation.
+        let run_id = widget.get_read_state().run_id.clone();
+        assert!(fetch_operations_to_undo(&db.conn, &OperationFilter::Run(run_id)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaves_files_whose_old_place_is_taken() {
+        let db = test_db::fresh().await;
+        let widget = db.widget();
+        let taken = file(&db, "Takeout/IMG_0001.jpg", b"other").await;
+        let placed = file(&db, "2019/IMG_0001.jpg", b"placed").await;
+        let operation = journaled(&db, &widget, PlacementMode::Move.as_str(), &taken, &placed).await;
+
+        assert!(!widget.revert(&operation, "undo").await.unwrap());
+        assert_eq!(fs::read(&taken).await.unwrap(), b"other");
+        assert_eq!(fs::read(&placed).await.unwrap(), b"placed");
+        let missing = db.target_folder.join("2019/missing.jpg");
+        let operation = journaled(&db, &widget, PlacementMode::Move.as_str(), &taken, &missing).await;
+        assert!(!widget.revert(&operation, "undo").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn undoes_the_last_run_of_the_selected_takeout() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let extracted = file(&db, "Takeout/IMG_0001.jpg", b"with dates").await;
+        let media = db.extract(takeout_zip_id, extracted.to_str().unwrap(), false).await;
+        let widget = db.widget();
+        widget.get_write_state().view_state = FileListWidgetViewState::Processing;
+        widget.on_fetch_takeouts(&crate::db::list_takeouts(&db.conn).await.unwrap());
+
+        // A run that wrote into the extract and moved it.
+        widget.begin_run();
+        let backup = file(&db, "Takeout/.1-IMG_0001.jpg.orig", b"as extracted").await;
+        widget.journal(OPERATION_WRITE, Some(&extracted), Some(&backup), Some(media.id)).await.unwrap();
+        let placed = db.target_folder.join("2019/IMG_0001.jpg");
+        create_parent(&placed).await.unwrap();
+        fs::rename(&extracted, &placed).await.unwrap();
+        widget.journal(PlacementMode::Move.as_str(), Some(&extracted), Some(&placed), Some(media.id)).await.unwrap();
+        // A later run without files of the takeout.
+        let later_run = widget.begin_run();
+        widget.journal(OPERATION_CREATE, None, Some(&db.target_folder.join("other.xmp")), None).await.unwrap();
+
+        widget.undo_operations(UndoScope::Selection).await.unwrap();
+        assert_eq!(fs::read(&extracted).await.unwrap(), b"as extracted");
+        assert!(!fs::try_exists(&placed).await.unwrap());
+        assert_eq!(db.reload(&media).await.path, extracted.to_str().unwrap());
+        assert_eq!(fetch_last_run_id(&db.conn).await.unwrap(), Some(later_run.clone()));
+        assert_eq!(fetch_operations_to_undo(&db.conn, &OperationFilter::Run(later_run.clone())).await.unwrap().len(), 1);
+
+        // The last run is undone whatever is selected.
+        widget.undo_operations(UndoScope::LastRun).await.unwrap();
+        assert!(fetch_operations_to_undo(&db.conn, &OperationFilter::Run(later_run)).await.unwrap().is_empty());
+    }
+}
@@ -1,4 +1,12 @@
 pub(crate) mod ui_actions;
+mod albums;
+mod catalog;
+mod decision;
+mod dedup;
+mod export;
+mod journal;
+mod near_duplicates;
+mod planning;
 mod processing;
 mod rendering;
 
@@ -8,21 +16,33 @@ use entity::takeout_zip::Model as TakeoutZipModel;
 use ratatui::widgets::{Row, TableState};
 use std::collections::HashMap;
 use serde::Deserialize;
+use chrono::{DateTime, FixedOffset};
+use crate::xmp::XmpData;
 use ui_actions::UiActions;
 use crate::db::list_takeouts;
 use crate::drive::list_google_drive;
+use crate::plan::PlannedEntries;
+use crate::settings::{get_metadata_writes, get_path_template, MetadataWrites};
+use near_duplicates::NearDuplicateRow;
+use sea_orm::DatabaseConnection;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
 pub struct FileListWidget {
     pub is_running: bool,
     state: Arc<RwLock<FileListState>>,
+    db: DatabaseConnection,
+    /// Where archives are downloaded and extracted and the library is built.
+    target_folder: PathBuf,
 }
 
-impl Default for FileListWidget {
-    fn default() -> Self {
+impl FileListWidget {
+    pub fn new(db: DatabaseConnection, target_folder: PathBuf) -> Self {
         Self {
             is_running: true,
             state: Arc::new(RwLock::new(FileListState::default())),
+            db,
+            target_folder,
         }
     }
 }
@@ -40,6 +60,7 @@ pub enum Task {
 pub struct FileListState {
     files: Vec<DriveItem>,
     zip_files: Vec<TakeoutZipModel>,
+    near_duplicates: Vec<NearDuplicateRow>,
     loading_state: LoadingState,
     view_state: FileListWidgetViewState,
     table_state: TableState,
@@ -49,6 +70,18 @@ pub struct FileListState {
     task_counts: HashMap<Task, u8>,
     progress_count: u16,
     progress_hash: HashMap<String, (String, f64)>,
+    /// The run filesystem operations are journaled under.
+    run_id: String,
+    /// `PATH_TEMPLATE`, or what it was changed to with `t`.
+    path_template: String,
+    /// The template being typed, while it is edited.
+    template_input: Option<String>,
+    /// The outcome of the last action, shown above the progress.
+    status_line: String,
+    /// The plan the current run follows, empty when it decides for itself.
+    planned_entries: Arc<PlannedEntries>,
+    /// What is written into the files a run places.
+    metadata_writes: MetadataWrites,
     pub max_downloaded_zip_files: i32,
 }
 
@@ -57,6 +90,7 @@ impl Default for FileListState {
         Self {
             files: Vec::new(),
             zip_files: Vec::new(),
+            near_duplicates: Vec::new(),
             loading_state: LoadingState::Idle,
             view_state: FileListWidgetViewState::Files,
             table_state: TableState::default(),
@@ -66,6 +100,12 @@ impl Default for FileListState {
             max_task_counts: HashMap::new(),
             progress_count: 0,
             progress_hash: HashMap::new(),
+            run_id: journal::new_run_id(),
+            path_template: get_path_template(),
+            template_input: None,
+            status_line: String::new(),
+            planned_entries: Arc::default(),
+            metadata_writes: get_metadata_writes(),
             max_downloaded_zip_files: 10,
         }
     }
@@ -86,6 +126,7 @@ pub enum FileListWidgetViewState {
     #[default]
     Files,
     Processing,
+    NearDuplicates,
 }
 
 // UI ACTIONS
@@ -117,7 +158,7 @@ impl FileListWidget {
 
     async fn fetch_takeout_zips(self) {
         self.set_loading_state(LoadingState::Loading);
-        match list_takeouts().await {
+        match list_takeouts(&self.db).await {
             Ok(takeouts) => self.on_fetch_takeouts(&takeouts),
             Err(err) => self.on_err(&err),
         }
@@ -137,6 +178,7 @@ impl FileListWidget {
                         self.start_processing();
                     }
                 }
+                FileListWidgetViewState::NearDuplicates => {}
             },
             UiActions::ScrollDown => {
                 self.scroll_down();
@@ -144,9 +186,61 @@ impl FileListWidget {
             UiActions::ScrollUp => {
                 self.scroll_up();
             }
-            UiActions::SelectItem => {
-                self.open_drive_file();
+            UiActions::ExportAlbums => {
+                if view_state == FileListWidgetViewState::Processing {
+                    self.export_albums();
+                }
+            }
+            UiActions::BackfillCatalog => {
+                if view_state == FileListWidgetViewState::Processing {
+                    self.backfill_catalog();
+                }
+            }
+            UiActions::EditPathTemplate => {
+                if view_state == FileListWidgetViewState::Processing {
+                    self.edit_path_template();
+                }
+            }
+            UiActions::TypePathTemplate(c) => {
+                self.type_path_template(Some(c));
+            }
+            UiActions::ErasePathTemplate => {
+                self.type_path_template(None);
+            }
+            UiActions::ApplyPathTemplate => {
+                self.apply_path_template();
             }
+            UiActions::CancelPathTemplate => {
+                self.cancel_path_template();
+            }
+            UiActions::ReportDuplicates => {
+                if view_state == FileListWidgetViewState::Processing {
+                    self.report_duplicates();
+                }
+            }
+            UiActions::ReviewNearDuplicates => {
+                if view_state == FileListWidgetViewState::Processing {
+                    self.review_near_duplicates();
+                }
+            }
+            UiActions::ExportLibrary => {
+                if view_state == FileListWidgetViewState::Processing {
+                    self.export_library();
+                }
+            }
+            UiActions::Undo(scope) => {
+                if view_state != FileListWidgetViewState::Files {
+                    self.undo(scope);
+                }
+            }
+            UiActions::SelectItem => match view_state {
+                FileListWidgetViewState::NearDuplicates => {
+                    self.keep_near_duplicate();
+                }
+                _ => {
+                    self.open_drive_file();
+                }
+            },
             UiActions::SwitchView => match view_state {
                 FileListWidgetViewState::Files => {
                     self.show_processing();
@@ -154,6 +248,9 @@ impl FileListWidget {
                 FileListWidgetViewState::Processing => {
                     self.show_files();
                 }
+                FileListWidgetViewState::NearDuplicates => {
+                    self.show_processing();
+                }
             },
             UiActions::Quit => {
                 self.quit();
@@ -200,6 +297,10 @@ impl FileListWidget {
         }
     }
 
+    fn set_status_line(&self, status_line: String) {
+        self.get_write_state().status_line = status_line;
+    }
+
     pub fn update_item_progress(&self, item: &str, task: &str, progress: f64) {
         if let Ok(mut state) = self.state.write() {
             state
@@ -283,7 +384,63 @@ impl From<&GoogleDriveFile> for DriveItem {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PhotoMetadata {
-    photo_taken_time: PhotoTakenTime,
+    /// Missing from sidecars that only carry a location or flags.
+    photo_taken_time: Option<PhotoTakenTime>,
+    geo_data: Option<GeoData>,
+    geo_data_exif: Option<GeoData>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    people: Vec<Person>,
+    #[serde(default)]
+    favorited: bool,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    trashed: bool,
+    #[serde(default)]
+    in_locked_folder: bool,
+}
+
+impl PhotoMetadata {
+    /// Takeout zeroes out locations it does not have. `geoDataExif` comes from the
+    /// camera itself, `geoData` may have been edited or estimated by Google Photos.
+    fn best_location(&self) -> Option<GeoData> {
+        [&self.geo_data_exif, &self.geo_data]
+            .into_iter()
+            .flatten()
+            .find(|geo_data| geo_data.is_known())
+            .cloned()
+    }
+
+    fn to_xmp_data(&self, taken: Option<DateTime<FixedOffset>>) -> XmpData {
+        XmpData {
+            taken,
+            location: self.best_location(),
+            description: self.description.clone(),
+            people: self.people.iter().map(|person| person.name.clone()).collect(),
+            favorite: self.favorited,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct GeoData {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default)]
+    pub altitude: f64,
+}
+
+impl GeoData {
+    pub fn is_known(&self) -> bool {
+        self.latitude != 0.0 || self.longitude != 0.0
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Person {
+    name: String,
 }
 
 #[derive(Debug, Deserialize)]
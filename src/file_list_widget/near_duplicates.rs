@@ -0,0 +1,236 @@
+use crate::db::{
+    fetch_file_in_zip_by_id, fetch_media_files_without_perceptual_hash, fetch_pending_near_duplicates,
+    fetch_perceptual_hashes, resolve_near_duplicate_cluster, set_perceptual_hash, store_near_duplicate_clusters,
+    update_file_in_zip, update_media_file_path, OPERATION_REMOVE,
+};
+use crate::file_list_widget::processing::placement_log;
+use crate::file_list_widget::{FileListWidget, FileListWidgetViewState, LoadingState};
+use crate::perceptual_hash::{cluster_hashes, is_perceptual_hashable, perceptual_hash};
+use crate::placement::Placement;
+use crate::settings::{get_near_duplicate_distance, get_xmp_keeps_extension};
+use crate::xmp::xmp_path;
+use anyhow::{Context, Result};
+use entity::media_file::Model as MediaFileModel;
+use ratatui::widgets::Row;
+use sea_orm::ActiveValue::Set;
+use sea_orm::IntoActiveModel;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Copies that lost a review are moved here, below the target folder, with the
+/// folders they had in the library.
+pub const NEAR_DUPLICATE_FOLDER: &str = "Near duplicates";
+
+/// One member of a cluster on the review screen.
+#[derive(Debug, Clone)]
+pub struct NearDuplicateRow {
+    pub cluster_id: i32,
+    pub distance: i32,
+    pub media_file: MediaFileModel,
+}
+
+impl From<&NearDuplicateRow> for Row<'_> {
+    fn from(row: &NearDuplicateRow) -> Self {
+        let media_file = &row.media_file;
+        let dimensions = match (media_file.width, media_file.height) {
+            (Some(width), Some(height)) => format!("{}x{}", width, height),
+            _ => "?".to_owned(),
+        };
+        let size = media_file
+            .size
+            .map(|size| format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0)))
+            .unwrap_or_default();
+        let place = [&media_file.city, &media_file.country]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        Row::new(vec![
+            row.cluster_id.to_string(),
+            media_file.file_name.clone(),
+            dimensions,
+            size,
+            media_file.mime_type.clone().unwrap_or_default(),
+            row.distance.to_string(),
+            place,
+            media_file.path.clone(),
+        ])
+    }
+}
+
+impl FileListWidget {
+    pub fn review_near_duplicates(&self) {
+        let this = self.clone();
+        tokio::spawn(this.review_near_duplicates_with_progress());
+    }
+
+    async fn review_near_duplicates_with_progress(self) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.find_near_duplicates().await {
+            Ok(_) => {
+                self.set_view_state(FileListWidgetViewState::NearDuplicates);
+                self.set_loading_state(LoadingState::Idle);
+            }
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    /// Hashes images catalogued before hashes were computed, then groups the
+    /// catalog again. Decisions already made are kept.
+    async fn find_near_duplicates(&self) -> Result<()> {
+        let media_files: Vec<_> = fetch_media_files_without_perceptual_hash(&self.db)
+            .await?
+            .into_iter()
+            .filter(|media_file| {
+                Path::new(&media_file.path)
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(is_perceptual_hashable)
+            })
+            .collect();
+        let len = media_files.len();
+        for (i, media_file) in media_files.into_iter().enumerate() {
+            self.update_item_progress(&media_file.file_name, "perceptual hash", i as f64 / len as f64);
+            if let Ok(Some(hash)) = perceptual_hash(&media_file.path).await {
+                set_perceptual_hash(&self.db, media_file, hash).await?;
+            }
+        }
+        let clusters = cluster_hashes(&fetch_perceptual_hashes(&self.db).await?, get_near_duplicate_distance());
+        store_near_duplicate_clusters(&self.db, &clusters).await?;
+        self.update_item_progress(
+            &format!("{} clusters", clusters.len()),
+            "near duplicates",
+            0.0,
+        );
+        self.load_near_duplicates().await
+    }
+
+    async fn load_near_duplicates(&self) -> Result<()> {
+        let rows: Vec<NearDuplicateRow> = fetch_pending_near_duplicates(&self.db)
+            .await?
+            .into_iter()
+            .flat_map(|(cluster, members)| {
+                members.into_iter().map(move |(member, media_file)| NearDuplicateRow {
+                    cluster_id: cluster.id,
+                    distance: member.distance,
+                    media_file,
+                })
+            })
+            .collect();
+        let mut state = self.get_write_state();
+        let selected = match rows.len() {
+            0 => None,
+            len => Some(state.table_state.selected().unwrap_or(0).min(len - 1)),
+        };
+        state.near_duplicates = rows;
+        state.table_state.select(selected);
+        Ok(())
+    }
+
+    /// Keeps the selected copy of its cluster and moves the others out of the
+    /// library.
+    pub fn keep_near_duplicate(&self) {
+        let selected = {
+            let state = self.get_read_state();
+            state
+                .table_state
+                .selected()
+                .and_then(|selected| state.near_duplicates.get(selected).cloned())
+        };
+        if let Some(kept) = selected {
+            let this = self.clone();
+            tokio::spawn(this.keep_near_duplicate_with_progress(kept));
+        }
+    }
+
+    async fn keep_near_duplicate_with_progress(self, kept: NearDuplicateRow) {
+        self.set_loading_state(LoadingState::Processing);
+        self.begin_run();
+        match self.put_aside_near_duplicates(&kept).await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    async fn put_aside_near_duplicates(&self, kept: &NearDuplicateRow) -> Result<()> {
+        let others: Vec<NearDuplicateRow> = self
+            .get_read_state()
+            .near_duplicates
+            .iter()
+            .filter(|row| row.cluster_id == kept.cluster_id && row.media_file.id != kept.media_file.id)
+            .cloned()
+            .collect();
+        for other in others {
+            self.update_item_progress(&other.media_file.file_name, "put aside near duplicate", 0.5);
+            self.put_aside(other.media_file, &kept.media_file).await?;
+        }
+        resolve_near_duplicate_cluster(&self.db, kept.cluster_id, kept.media_file.id).await?;
+        self.load_near_duplicates().await
+    }
+
+    /// Moves a copy to [`NEAR_DUPLICATE_FOLDER`] together with its `.xmp` and the
+    /// json sidecar, unless the kept copy was placed with the same sidecar.
+    async fn put_aside(&self, media_file: MediaFileModel, kept: &MediaFileModel) -> Result<()> {
+        let target_folder = self.target_folder.clone();
+        let source = PathBuf::from(&media_file.path);
+        let relative = match source.strip_prefix(&target_folder) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => {
+                let name = source.file_name().with_context(|| format!("{} has no name", source.display()))?;
+                PathBuf::from(name)
+            }
+        };
+        let target = target_folder.join(NEAR_DUPLICATE_FOLDER).join(relative);
+        let folder = target.parent().with_context(|| format!("{} has no folder", target.display()))?;
+        fs::create_dir_all(folder).await?;
+        let placement = self.place_file(&source, &target, media_file.file_in_zip_id).await?;
+        if let Placement::Duplicate(existing) = &placement {
+            // The very same bytes were put aside before.
+            fs::remove_file(&source).await?;
+            self.journal(OPERATION_REMOVE, Some(&source), Some(existing), media_file.file_in_zip_id)
+                .await?;
+        }
+        let new_path = placement.path().to_path_buf();
+
+        let keep_extension = get_xmp_keeps_extension();
+        let xmp_source = xmp_path(&source, keep_extension);
+        if fs::try_exists(&xmp_source).await? {
+            self.place_file(&xmp_source, &xmp_path(&new_path, keep_extension), media_file.file_in_zip_id)
+                .await?;
+        }
+
+        if let Some(sidecar_id) = media_file.sidecar_file_in_zip_id
+            && kept.sidecar_file_in_zip_id != Some(sidecar_id)
+            && let Some(sidecar) = fetch_file_in_zip_by_id(&self.db, sidecar_id).await?
+            && fs::try_exists(&sidecar.path).await?
+        {
+            let json_name = Path::new(&sidecar.path)
+                .file_name()
+                .with_context(|| format!("Sidecar {} has no name", sidecar.path))?;
+            let json_path = new_path.with_file_name(json_name);
+            let json_placement = self
+                .place_file(Path::new(&sidecar.path), &json_path, Some(sidecar.id))
+                .await?;
+            let log = placement_log(&sidecar.log, &json_placement);
+            let mut sidecar = sidecar.into_active_model();
+            sidecar.path = Set(utf8(json_placement.path())?.to_owned());
+            sidecar.log = Set(log);
+            update_file_in_zip(&self.db, sidecar).await?;
+        }
+
+        let log_entry = json!({
+            "decision": "near_duplicate",
+            "kept": kept.path,
+            "path": new_path,
+        });
+        update_media_file_path(&self.db, media_file, utf8(&new_path)?, log_entry).await?;
+        Ok(())
+    }
+}
+
+/// Paths are stored as text, one that is not valid UTF-8 cannot be.
+fn utf8(path: &Path) -> Result<&str> {
+    path.to_str().with_context(|| format!("{} is not valid UTF-8", path.display()))
+}
@@ -0,0 +1,321 @@
+use crate::date_resolver::{resolve_known_taken_date, DateSource};
+use crate::db::{classify_file, list_takeouts, FILE_TYPE_ALBUM_METADATA, FILE_TYPE_MEDIA, FILE_TYPE_SIDECAR, MEDIA_VARIANT_EDITED, MEDIA_VARIANT_ORIGINAL};
+use crate::file_list_widget::decision::{placement_action, MediaDecision, MediaFacts};
+use crate::file_list_widget::processing::render_media_template;
+use crate::file_list_widget::{FileListWidget, LoadingState, PhotoMetadata};
+use crate::geocode::place_at;
+use crate::media_utils::{copy_and_hash, is_video_extension, is_year_folder, read_media_details, rexif_get_taken_date, MediaDetails};
+use crate::metadata_writer::{is_exif_writable, read_exif_offset};
+use crate::path_template::PathTemplate;
+use crate::placement::{plan_placement_of, Placement};
+use crate::plan::{Plan, PlanAction, PlanEntry, PLAN_CSV_FILE, PLAN_JSON_FILE};
+use crate::settings::get_date_source_priority;
+use anyhow::Result;
+use async_compression::tokio::bufread::GzipDecoder;
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, BufReader};
+use tokio_tar::{Archive, EntryType};
+
+/// What a dry run learns about a media file while it passes through.
+#[derive(Debug)]
+struct ScannedMedia {
+    archive: String,
+    archive_path: String,
+    name: String,
+    key: String,
+    variant: &'static str,
+    content_hash: String,
+    size: u64,
+    archive_mtime: Option<i64>,
+    exif_taken: Option<DateTime<Utc>>,
+    exif_offset: Option<FixedOffset>,
+    details: MediaDetails,
+}
+
+impl ScannedMedia {
+    fn is_video(&self) -> bool {
+        Path::new(&self.name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(is_video_extension)
+    }
+
+    /// Edited copies and the video halves of Live Photos go next to their still.
+    fn is_group_member(&self) -> bool {
+        self.variant == MEDIA_VARIANT_EDITED || self.is_video()
+    }
+}
+
+/// Everything a dry run read from the archives.
+#[derive(Debug, Default)]
+struct Scan {
+    media: Vec<ScannedMedia>,
+    sidecars: HashMap<String, Value>,
+    album_titles: HashMap<String, String>,
+}
+
+impl FileListWidget {
+    pub fn plan_dry_run(&self) {
+        let this = self.clone();
+        tokio::spawn(this.plan_dry_run_with_progress());
+    }
+
+    async fn plan_dry_run_with_progress(self) {
+        self.set_loading_state(LoadingState::Processing);
+        match self.write_plan().await {
+            Ok(_) => self.set_loading_state(LoadingState::Idle),
+            Err(err) => self.on_err(&err),
+        }
+    }
+
+    /// Plans the archives that are already on disk without downloading or
+    /// extracting anything, and writes the plan as json and csv.
+    async fn write_plan(&self) -> Result<()> {
+        let mut scan = Scan::default();
+        for takeout_zip in list_takeouts(&self.db).await? {
+            if fs::try_exists(&takeout_zip.local_path).await.unwrap_or(false) {
+                self.scan_archive(&takeout_zip.name, Path::new(&takeout_zip.local_path), &mut scan)
+                    .await?;
+            }
+        }
+        let plan = self.plan(&scan).await?;
+        let target_folder = self.target_folder.clone();
+        fs::create_dir_all(&target_folder).await?;
+        fs::write(target_folder.join(PLAN_JSON_FILE), serde_json::to_string_pretty(&plan)?).await?;
+        fs::write(target_folder.join(PLAN_CSV_FILE), plan.to_csv()).await?;
+        let summary = format!(
+            "{} to place, {} renamed, {} duplicates, {} without a date, {} skipped",
+            plan.count(PlanAction::Place),
+            plan.count(PlanAction::Rename),
+            plan.count(PlanAction::Duplicate),
+            plan.count(PlanAction::NoDate),
+            plan.count(PlanAction::Skip),
+        );
+        self.update_item_progress(&summary, "dry run", 0.0);
+        Ok(())
+    }
+
+    /// Reads an archive entry by entry. Json is kept in memory. Media passes
+    /// through one scratch file, so the date and camera readers see the same
+    /// bytes as in a real run, and is gone again before the next entry.
+    async fn scan_archive(&self, archive_name: &str, local_path: &Path, scan: &mut Scan) -> Result<()> {
+        let scratch_folder = std::env::temp_dir().join("takeout-fixer-plan");
+        fs::create_dir_all(&scratch_folder).await?;
+        let file = fs::File::open(local_path).await?;
+        let mut archive = Archive::new(GzipDecoder::new(BufReader::new(file)));
+        let mut entries = archive.entries()?;
+        let mut count = 0;
+        let priority = get_date_source_priority();
+        let needs_camera = PathTemplate::parse(&self.path_template())
+            .is_ok_and(|template| template.uses("camera_make") || template.uses("camera_model"));
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry?;
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+            count += 1;
+            let archive_path = entry.path()?.to_string_lossy().into_owned();
+            let Some(name) = Path::new(&archive_path).file_name().and_then(|name| name.to_str()).map(str::to_owned)
+            else {
+                continue;
+            };
+            self.update_item_progress(archive_name, &format!("plan, {} files read", count), 0.0);
+            let (file_type, key, variant) = classify_file(&name, &archive_path);
+            let key = key.to_owned();
+            match file_type {
+                FILE_TYPE_SIDECAR | FILE_TYPE_ALBUM_METADATA => {
+                    let mut content = String::new();
+                    entry.read_to_string(&mut content).await?;
+                    let Ok(raw_json) = serde_json::from_str::<Value>(&content) else {
+                        continue;
+                    };
+                    if file_type == FILE_TYPE_SIDECAR {
+                        scan.sidecars.insert(key, raw_json);
+                    } else {
+                        let folder_name = Path::new(&key).file_name().and_then(|name| name.to_str()).unwrap_or("");
+                        if !is_year_folder(folder_name) {
+                            let title = raw_json
+                                .get("title")
+                                .and_then(Value::as_str)
+                                .filter(|title| !title.is_empty())
+                                .unwrap_or(folder_name)
+                                .to_owned();
+                            scan.album_titles.insert(key, title);
+                        }
+                    }
+                }
+                FILE_TYPE_MEDIA => {
+                    let extension = Path::new(&name).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+                    let scratch_path = scratch_folder.join(format!("entry.{}", extension));
+                    let mut scratch = fs::File::create(&scratch_path).await?;
+                    let (content_hash, size) = copy_and_hash(&mut entry, &mut scratch).await?;
+                    drop(scratch);
+                    let exif_taken = if priority.contains(&DateSource::Exif) {
+                        rexif_get_taken_date(&scratch_path).await.ok().flatten()
+                    } else {
+                        None
+                    };
+                    let exif_offset = if is_exif_writable(extension) {
+                        read_exif_offset(&scratch_path).await.ok().flatten()
+                    } else {
+                        None
+                    };
+                    let details = if needs_camera {
+                        read_media_details(&scratch_path).await.unwrap_or_default()
+                    } else {
+                        MediaDetails::default()
+                    };
+                    fs::remove_file(&scratch_path).await?;
+                    scan.media.push(ScannedMedia {
+                        archive: archive_name.to_owned(),
+                        archive_path,
+                        name,
+                        key,
+                        variant,
+                        content_hash,
+                        size,
+                        archive_mtime: entry.header().mtime().ok().map(|mtime| mtime as i64),
+                        exif_taken,
+                        exif_offset,
+                        details,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Decides for every scanned media file what a real run would do, in the
+    /// order a real run takes them: stills first, then what is grouped with
+    /// them.
+    async fn plan(&self, scan: &Scan) -> Result<Plan> {
+        let template = PathTemplate::parse(&self.path_template())?;
+        let priority = get_date_source_priority();
+        let edited_keys: HashSet<&str> = scan
+            .media
+            .iter()
+            .filter(|media| media.variant == MEDIA_VARIANT_EDITED)
+            .map(|media| media.key.as_str())
+            .collect();
+        let primaries: HashSet<&str> = scan
+            .media
+            .iter()
+            .filter(|media| media.variant == MEDIA_VARIANT_ORIGINAL && !media.is_video())
+            .map(|media| media.key.as_str())
+            .collect();
+        let mut first_with_hash: HashMap<&str, &str> = HashMap::new();
+        let mut planned: HashMap<PathBuf, String> = HashMap::new();
+        let mut primary_folders: HashMap<&str, PathBuf> = HashMap::new();
+        let mut entries: Vec<(usize, PlanEntry)> = Vec::with_capacity(scan.media.len());
+
+        let grouped = |media: &ScannedMedia| media.is_group_member() && primaries.contains(media.key.as_str());
+        let order = scan
+            .media
+            .iter()
+            .enumerate()
+            .filter(|(_, media)| !grouped(media))
+            .chain(scan.media.iter().enumerate().filter(|(_, media)| grouped(media)));
+        for (index, media) in order {
+            let mut entry = PlanEntry {
+                archive: media.archive.clone(),
+                archive_path: media.archive_path.clone(),
+                content_hash: media.content_hash.clone(),
+                size: media.size,
+                action: PlanAction::Skip,
+                destination: None,
+                duplicate_of: None,
+                taken_at: None,
+                date_source: None,
+                reason: None,
+            };
+            if let Some(canonical) = first_with_hash.get(media.content_hash.as_str()) {
+                entry.action = PlanAction::Duplicate;
+                entry.duplicate_of = Some((*canonical).to_owned());
+                entries.push((index, entry));
+                continue;
+            }
+            first_with_hash.insert(&media.content_hash, &media.archive_path);
+
+            let raw_json = scan.sidecars.get(&media.key);
+            let metadata = raw_json.and_then(|raw_json| PhotoMetadata::deserialize(raw_json).ok());
+            let json_taken = raw_json.and_then(|raw_json| self.get_date_taken_from_json(raw_json).ok().flatten());
+            let decision = self.decide_media(MediaFacts {
+                variant: media.variant,
+                has_edited_variant: edited_keys.contains(media.key.as_str()),
+                metadata: metadata.as_ref(),
+                taken: resolve_known_taken_date(&media.name, json_taken, media.exif_taken, media.archive_mtime, &priority),
+                exif_offset: media.exif_offset,
+            });
+            let (library_root, resolved) = match decision {
+                MediaDecision::Skip(reason) => {
+                    entry.reason = Some(reason.as_str().to_owned());
+                    entries.push((index, entry));
+                    continue;
+                }
+                MediaDecision::NoDate => {
+                    entry.action = PlanAction::NoDate;
+                    entries.push((index, entry));
+                    continue;
+                }
+                MediaDecision::Place { library_root, taken } => (library_root, taken),
+            };
+            let location = metadata.as_ref().and_then(PhotoMetadata::best_location);
+            entry.taken_at = Some(resolved.local());
+            entry.date_source = Some(resolved.source.as_str().to_owned());
+
+            let group_folder = if grouped(media) {
+                primary_folders.get(media.key.as_str()).cloned()
+            } else {
+                None
+            };
+            let target = match group_folder {
+                Some(group_folder) => group_folder.join(&media.name),
+                None => {
+                    let folder = Path::new(&media.archive_path).parent().and_then(|folder| folder.to_str());
+                    let album = folder.and_then(|folder| scan.album_titles.get(folder)).map(String::as_str);
+                    let place = location.and_then(place_at);
+                    library_root.join(render_media_template(
+                        &template,
+                        &media.name,
+                        resolved.local(),
+                        album,
+                        &media.details,
+                        place.as_ref(),
+                    ))
+                }
+            };
+            let placement = plan_placement_of(&media.content_hash, &target, &planned).await?;
+            entry.action = placement_action(&placement);
+            match placement {
+                Placement::Duplicate(existing) => {
+                    entry.duplicate_of = Some(existing.to_string_lossy().into_owned());
+                }
+                placement => {
+                    let destination = placement.path().to_path_buf();
+                    if media.variant == MEDIA_VARIANT_ORIGINAL && !media.is_video() {
+                        primary_folders.insert(&media.key, destination.parent().unwrap().to_path_buf());
+                    }
+                    planned.insert(destination.clone(), media.content_hash.clone());
+                    entry.destination = Some(destination);
+                }
+            }
+            entries.push((index, entry));
+        }
+
+        // The report lists files in archive order.
+        entries.sort_by_key(|(index, _)| *index);
+        Ok(Plan {
+            created_at: Utc::now(),
+            target_folder: self.target_folder.clone(),
+            path_template: self.path_template(),
+            entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+        })
+    }
+}
@@ -1,16 +1,30 @@
-use crate::db::{create_file_in_zip, create_media_file, fetch_media_file_if_exists, fetch_media_file_to_process, fetch_next_takeout, fetch_related, store_file, update_file_in_zip, update_takeout_zip, MEDIA_STATUS_FAILED, MEDIA_STATUS_HAS_RELATED, MEDIA_STATUS_NO_DATE, MEDIA_STATUS_NO_RELATED, MEDIA_STATUS_PROCESSED, MEDIA_STATUS_PROCESSING, ZIP_STATUS_DOWNLOADED, ZIP_STATUS_DOWNLOADING, ZIP_STATUS_EXAMINE_FAILED, ZIP_STATUS_FAILED, ZIP_STATUS_NEW, ZIP_STATUS_PROCESSED, ZIP_STATUS_PROCESSING, ZIP_STATUS_REMOVED, ZIP_STATUS_REMOVING};
-use crate::drive::{download, get_file_path, get_target_folder};
-use crate::file_list_widget::{DriveItem, FileListWidget, LoadingState, PhotoMetadata, Task};
-use anyhow::Result;
+use crate::db::{fetch_operations_to_undo, OperationFilter, add_media_to_album, append_log, fetch_media_by_path, fetch_media_album, create_file_in_zip, fetch_album_by_path, fetch_media_in_folder, fetch_media_by_content_hash, store_album, NewFileInZip, FILE_TYPE_ALBUM_METADATA, FILE_TYPE_ACCOUNT_METADATA, FILE_TYPE_MEDIA, FILE_TYPE_OTHER, create_media_file, fetch_file_in_zip_by_id, fetch_json_without_media_and_set_status_to_processing, fetch_media_file_if_exists, has_pending_takeouts, requeue_waiting_json, has_edited_variant, fetch_new_media_and_set_status_to_processing, fetch_next_takeout, fetch_related, store_file, update_file_in_zip, update_takeout_zip, MEDIA_STATUS_DUPLICATE, MEDIA_STATUS_FAILED, MEDIA_STATUS_HAS_RELATED, MEDIA_STATUS_NO_DATE, MEDIA_STATUS_NO_RELATED, MEDIA_STATUS_ORPHANED, MEDIA_STATUS_PROCESSED, MEDIA_STATUS_WAITING, MEDIA_STATUS_PROCESSING, MEDIA_STATUS_SKIPPED, MEDIA_VARIANT_ORIGINAL, ZIP_STATUS_DOWNLOADED, ZIP_STATUS_DOWNLOADING, ZIP_STATUS_EXAMINE_FAILED, ZIP_STATUS_FAILED, ZIP_STATUS_NEW, ZIP_STATUS_PROCESSED, ZIP_STATUS_PROCESSING, ZIP_STATUS_REMOVED, ZIP_STATUS_REMOVING, OPERATION_CREATE, OPERATION_REMOVE, OPERATION_WRITE};
+use crate::date_resolver::{resolve_taken_date, DateSource};
+use crate::drive::download;
+use crate::file_list_widget::catalog::describe_media_file;
+use crate::file_list_widget::decision::{MediaDecision, MediaFacts};
+use crate::file_list_widget::{DriveItem, FileListWidget, GeoData, LoadingState, PhotoMetadata, Task};
+use crate::media_utils::{copy_and_hash, is_year_folder, media_type, read_media_details, set_file_times, MediaDetails};
+use crate::geocode::{place_at, Place};
+use crate::path_template::{PathTemplate, TemplateValues, PLACE_TOKENS};
+use crate::plan::{PlanAction, PlanEntry, PlannedEntries};
+use crate::placement::{plan_placement, transfer, Placement};
+use crate::metadata_writer::{is_exif_writable, is_quicktime, read_exif_offset, write_exif_location, write_exif_taken_date, write_quicktime_taken_date};
+use crate::xmp::{needs_xmp_sidecar, write_xmp_sidecar, xmp_path, XmpData};
+use crate::settings::{get_write_xmp_sidecars, get_xmp_keeps_extension, get_file_times_policy, get_date_source_priority, get_trashed_policy, get_month_locale, get_source_account, get_separate_archived, get_favorite_policy, get_placement_mode, get_dry_run, FileTimesPolicy, TrashedPolicy, FavoritePolicy, PlacementMode};
+use anyhow::{Context, Result};
 use async_compression::tokio::bufread::GzipDecoder;
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, FixedOffset, Utc};
 use entity::file_in_zip::{Model as FileInZipModel, Model};
-use entity::takeout_zip::Model as TakeoutZipModel;
+use entity::takeout_zip::{ActiveModel as TakeoutZipActiveModel, Model as TakeoutZipModel};
 use futures::StreamExt;
 use sea_orm::ActiveValue::Set;
 use sea_orm::{IntoActiveModel, TryIntoModel};
-use serde_json::Value;
-use std::path::Path;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs;
 use tokio::fs::File as TokioFile;
@@ -18,6 +32,10 @@ use tokio::io::{AsyncWriteExt, BufReader};
 use tokio_tar::{Archive, EntryType};
 
 pub const REMOVE_ZIPS_AFTER_PROCESSING: bool = false;
+pub const ACCOUNT_METADATA_FOLDER: &str = "Account metadata";
+pub const TRASH_FOLDER: &str = "Trash";
+pub const ARCHIVE_FOLDER: &str = "Archive";
+pub const FAVORITES_FOLDER: &str = "Favorites";
 
 impl FileListWidget {
     pub(crate) async fn store_files_in_db(self, files: Vec<DriveItem>) {
@@ -26,7 +44,7 @@ impl FileListWidget {
         for (i, file) in files.iter().enumerate() {
             if let DriveItem::File(_, name) = file {
                 self.update_item_progress("Storing", name, i as f64 / len as f64);
-                store_file(file.clone())
+                store_file(&self.db, file.clone())
                     .await
                     .expect("Failed to store file");
             }
@@ -34,7 +52,22 @@ impl FileListWidget {
         self.set_loading_state(LoadingState::Idle);
     }
 
+    /// Has the run follow `planned_entries` rather than decide for itself.
+    pub(super) fn follow_plan(&self, planned_entries: PlannedEntries) {
+        self.get_write_state().planned_entries = Arc::new(planned_entries);
+    }
+
     pub fn start_processing(&self) {
+        if get_dry_run() {
+            self.plan_dry_run();
+            return;
+        }
+        let planned_entries = match PlannedEntries::from_settings() {
+            Ok(planned_entries) => planned_entries,
+            Err(err) => return self.on_err(&err),
+        };
+        self.follow_plan(planned_entries);
+        self.begin_run();
         let mut state = self.get_write_state();
         state.processing = true;
         state.max_task_counts.insert(Task::Download, 5);
@@ -60,23 +93,16 @@ impl FileListWidget {
             interval.tick().await; // Wait before each poll
 
             if self.start_task(Task::RemoveProcessed) {
-                if let Ok(Some(mut item)) =
-                    fetch_next_takeout(ZIP_STATUS_PROCESSED, Some(ZIP_STATUS_REMOVING), None).await
+                if let Ok(Some(item)) =
+                    fetch_next_takeout(&self.db, ZIP_STATUS_PROCESSED, Some(ZIP_STATUS_REMOVING), None).await
                 {
                     let this = self.clone();
 
                     tokio::spawn(async move {
-                        match tokio::fs::remove_file(&item.local_path.clone().unwrap()).await {
-                            Ok(_) => {
-                                item.status = Set(ZIP_STATUS_REMOVED.to_string());
-                                this.stop_task(Task::RemoveProcessed);
-                            }
-                            Err(err) => {
-                                item.status = Set(format!("{}: {}", ZIP_STATUS_FAILED, err));
-                                this.stop_task(Task::RemoveProcessed);
-                            }
+                        if let Err(err) = this.remove_processed_takeout(item).await {
+                            this.on_err(&err);
                         }
-                        update_takeout_zip(item).await.unwrap();
+                        this.stop_task(Task::RemoveProcessed);
                     });
                 } else {
                     self.stop_task(Task::RemoveProcessed);
@@ -85,7 +111,8 @@ impl FileListWidget {
 
             // Check for "new" items to download
             if self.start_task(Task::Download) {
-                if let Ok(Some(mut item)) = fetch_next_takeout(
+                if let Ok(Some(item)) = fetch_next_takeout(
+                    &self.db,
                     ZIP_STATUS_NEW,
                     Some(ZIP_STATUS_DOWNLOADING),
                     Some((self.get_max_number_of_downloaded(), ZIP_STATUS_DOWNLOADED)),
@@ -95,27 +122,10 @@ impl FileListWidget {
                     let this = self.clone();
 
                     tokio::spawn(async move {
-                        let later = this.clone();
-                        // Simulate processing for "new" items
-                        match this
-                            .download_to_disk_with_progress(DriveItem::File(
-                                item.drive_id.clone().unwrap(),
-                                item.name.clone().unwrap(),
-                            ))
-                            .await
-                        {
-                            Ok(path) => {
-                                item.status = Set(ZIP_STATUS_DOWNLOADED.to_string());
-                                item.local_path = Set(path.clone());
-                                later.stop_task(Task::Download);
-                            }
-                            Err(err) => {
-                                item.status =
-                                    Set(format!("{}: {}", ZIP_STATUS_FAILED, err));
-                                later.stop_task(Task::Download);
-                            }
+                        if let Err(err) = this.download_takeout(item).await {
+                            this.on_err(&err);
                         }
-                        update_takeout_zip(item).await.unwrap();
+                        this.stop_task(Task::Download);
                     });
                 } else {
                     self.stop_task(Task::Download);
@@ -124,28 +134,17 @@ impl FileListWidget {
 
             // Check for "downloaded" items
             if self.start_task(Task::Examination) {
-                if let Ok(Some(mut item)) =
-                    fetch_next_takeout(ZIP_STATUS_DOWNLOADED, Some(ZIP_STATUS_PROCESSING), None)
+                if let Ok(Some(item)) =
+                    fetch_next_takeout(&self.db, ZIP_STATUS_DOWNLOADED, Some(ZIP_STATUS_PROCESSING), None)
                         .await
                 {
                     let this = self.clone();
 
                     tokio::spawn(async move {
-                        let later = this.clone();
-                        match this
-                            .examine_zip_with_progress(item.clone().try_into_model().unwrap())
-                            .await
-                        {
-                            Ok(_) => {
-                                item.status = Set(ZIP_STATUS_PROCESSED.to_string());
-                                later.stop_task(Task::Examination);
-                            }
-                            Err(err) => {
-                                item.status = Set(format!("{}: {}", ZIP_STATUS_EXAMINE_FAILED, err));
-                                later.stop_task(Task::Examination);
-                            }
+                        if let Err(err) = this.examine_takeout(item).await {
+                            this.on_err(&err);
                         }
-                        update_takeout_zip(item).await.unwrap();
+                        this.stop_task(Task::Examination);
                     });
                 } else {
                     self.stop_task(Task::Examination);
@@ -153,65 +152,112 @@ impl FileListWidget {
             }
 
             if self.start_task(Task::MediaProcessing) {
-                if let Ok(Some(item)) = fetch_media_file_to_process(
-                    MEDIA_STATUS_HAS_RELATED,
-                    "media",
-                    Some(MEDIA_STATUS_PROCESSING),
-                )
-                .await
-                {
+                if let Ok(Some(item)) = fetch_new_media_and_set_status_to_processing(&self.db).await {
                     let this = self.clone();
 
                     tokio::spawn(async move {
-                        let later = this.clone();
-                        match this.process_media_file(item.clone()).await {
-                            Ok(_) => {
-                                later.stop_task(Task::MediaProcessing);
-                            }
-                            Err(err) => {
-                                let mut item = item.into_active_model();
-                                item.status = Set(format!("{}: {}", MEDIA_STATUS_FAILED, err));
-                                update_file_in_zip(item).await.unwrap();
-                                later.stop_task(Task::MediaProcessing);
-                            }
+                        if let Err(err) = this.process_next_media_file(item).await {
+                            this.on_err(&err);
                         }
+                        this.stop_task(Task::MediaProcessing);
                     });
                 } else {
                     self.stop_task(Task::MediaProcessing);
                 }
             }
 
-            // if self.start_task(Task::JsonProcessing) {
-            //     if let Ok(Some(item)) =
-            //         fetch_json_without_media_and_set_status_to_processing().await
-            //     {
-            //         let this = self.clone();
-            //
-            //         tokio::spawn(async move {
-            //             let later = this.clone();
-            //             match this.process_json_file(item.clone()).await {
-            //                 Ok(_) => {
-            //                     let item = fetch_file_in_zip_by_id(item.id).await.unwrap().unwrap();
-            //                     if item.status.as_str() == MEDIA_STATUS_PROCESSING {
-            //                         let mut item = item.into_active_model();
-            //                         item.status = Set(MEDIA_STATUS_PROCESSED.to_owned());
-            //                         update_file_in_zip(item).await.unwrap();
-            //                     }
-            //                     later.stop_task(Task::JsonProcessing);
-            //                 }
-            //                 Err(err) => {
-            //                     let mut item = item.into_active_model();
-            //                     item.status = Set(format!("{}: {}", MEDIA_STATUS_FAILED, err));
-            //                     update_file_in_zip(item).await.unwrap();
-            //                     later.stop_task(Task::JsonProcessing);
-            //                 }
-            //             }
-            //         });
-            //     } else {
-            //         self.stop_task(Task::JsonProcessing);
-            //     }
-            // }
+            if self.start_task(Task::JsonProcessing) {
+                if let Ok(Some(item)) =
+                    fetch_json_without_media_and_set_status_to_processing(&self.db).await
+                {
+                    let this = self.clone();
+
+                    tokio::spawn(async move {
+                        if let Err(err) = this.process_next_json_file(item).await {
+                            this.on_err(&err);
+                        }
+                        this.stop_task(Task::JsonProcessing);
+                    });
+                } else {
+                    self.stop_task(Task::JsonProcessing);
+                }
+            }
+        }
+    }
+
+    /// Removes the archive of a processed takeout and records how that went.
+    async fn remove_processed_takeout(&self, mut item: TakeoutZipActiveModel) -> Result<()> {
+        let takeout_zip = item.clone().try_into_model()?;
+        item.status = match fs::remove_file(&takeout_zip.local_path).await {
+            Ok(_) => Set(ZIP_STATUS_REMOVED.to_string()),
+            Err(err) => Set(format!("{}: {}", ZIP_STATUS_FAILED, err)),
+        };
+        update_takeout_zip(&self.db, item).await?;
+        Ok(())
+    }
+
+    /// Downloads a new takeout and records where it went, or why it did not.
+    async fn download_takeout(&self, mut item: TakeoutZipActiveModel) -> Result<()> {
+        let takeout_zip = item.clone().try_into_model()?;
+        match self
+            .clone()
+            .download_to_disk_with_progress(DriveItem::File(takeout_zip.drive_id, takeout_zip.name))
+            .await
+        {
+            Ok(path) => {
+                item.status = Set(ZIP_STATUS_DOWNLOADED.to_string());
+                item.local_path = Set(path);
+            }
+            Err(err) => item.status = Set(format!("{}: {}", ZIP_STATUS_FAILED, err)),
+        }
+        update_takeout_zip(&self.db, item).await?;
+        Ok(())
+    }
+
+    /// Extracts a downloaded takeout and records how that went.
+    async fn examine_takeout(&self, mut item: TakeoutZipActiveModel) -> Result<()> {
+        let takeout_zip = item.clone().try_into_model()?;
+        item.status = match self.clone().examine_zip_with_progress(takeout_zip).await {
+            Ok(_) => Set(ZIP_STATUS_PROCESSED.to_string()),
+            Err(err) => Set(format!("{}: {}", ZIP_STATUS_EXAMINE_FAILED, err)),
+        };
+        update_takeout_zip(&self.db, item).await?;
+        // Media from this archive may be what waiting sidecars were missing.
+        requeue_waiting_json(&self.db).await?;
+        Ok(())
+    }
+
+    /// Processes a media file, marking it failed with the reason when that fails.
+    async fn process_next_media_file(&self, item: FileInZipModel) -> Result<()> {
+        if let Err(err) = self.clone().process_media_file(item.clone()).await {
+            let mut item = item.into_active_model();
+            item.status = Set(format!("{}: {}", MEDIA_STATUS_FAILED, err));
+            update_file_in_zip(&self.db, item).await?;
         }
+        Ok(())
+    }
+
+    /// Processes a sidecar, marking it failed with the reason when that fails.
+    /// A sidecar that was not set aside on the way is done.
+    async fn process_next_json_file(&self, item: FileInZipModel) -> Result<()> {
+        match self.clone().process_json_file(item.clone()).await {
+            Ok(_) => {
+                let item = fetch_file_in_zip_by_id(&self.db, item.id)
+                    .await?
+                    .ok_or(anyhow::Error::msg("Processed json file disappeared"))?;
+                if item.status.as_str() == MEDIA_STATUS_PROCESSING {
+                    let mut item = item.into_active_model();
+                    item.status = Set(MEDIA_STATUS_PROCESSED.to_owned());
+                    update_file_in_zip(&self.db, item).await?;
+                }
+            }
+            Err(err) => {
+                let mut item = item.into_active_model();
+                item.status = Set(format!("{}: {}", MEDIA_STATUS_FAILED, err));
+                update_file_in_zip(&self.db, item).await?;
+            }
+        }
+        Ok(())
     }
 
     pub fn open_drive_file(&self) {
@@ -227,41 +273,42 @@ impl FileListWidget {
             }
     }
 
-    #[allow(dead_code)]
+    /// Reconciles a sidecar that had no media file when it was stored. The media
+    /// may have shown up since, possibly in a later archive part, in whatever
+    /// state the media stage left it.
     async fn process_json_file(self, json_file: FileInZipModel) -> Result<()> {
         self.update_item_progress(&json_file.name, "start processing", 0.1);
-        let media_file = fetch_media_file_if_exists(&json_file).await?;
+        let media_file = fetch_media_file_if_exists(&self.db, &json_file).await?;
         self.update_item_progress(&json_file.name, "check for media file", 0.2);
         if let Some(media_file) = media_file {
             //there is a media file. It might or might not be processed...
             match media_file.status.as_str() {
-                MEDIA_STATUS_NO_DATE | MEDIA_STATUS_NO_RELATED => {
+                MEDIA_STATUS_NO_RELATED | MEDIA_STATUS_HAS_RELATED | MEDIA_STATUS_PROCESSING => {
                     self.update_item_progress(&json_file.name, "associate media with json", 0.3);
-                    if media_file.related_id.is_none() {
-                        let (_media_file, _json_file) = self
-                            .associate_media_with_json(&media_file, &json_file)
-                            .await?;
+                    let (media_file, json_file) = self
+                        .associate_media_with_json(&media_file, &json_file)
+                        .await?;
+                    // The media stage moves the json along with the media file.
+                    if media_file.status == MEDIA_STATUS_NO_RELATED {
+                        let mut media_file = media_file.into_active_model();
+                        media_file.status = Set(MEDIA_STATUS_HAS_RELATED.to_owned());
+                        update_file_in_zip(&self.db, media_file).await?;
                     }
-                    let mut media_file = media_file.clone().into_active_model();
-                    media_file.status = Set(MEDIA_STATUS_NO_RELATED.to_owned());
-                    self.update_item_progress(&json_file.name, "set media to new", 1.0);
-                    update_file_in_zip(media_file).await?;
+                    let mut json_file = json_file.into_active_model();
+                    json_file.status = Set(MEDIA_STATUS_HAS_RELATED.to_owned());
+                    let json_file = update_file_in_zip(&self.db, json_file).await?;
+                    self.update_item_progress(&json_file.name, "media queued", 1.0);
                 }
-                MEDIA_STATUS_PROCESSING => {
-                    self.update_item_progress(&json_file.name, "associate media with json", 0.3);
-                    if media_file.related_id.is_none() {
-                        let (_media_file, _json_file) = self
-                            .associate_media_with_json(&media_file, &json_file)
-                            .await?;
-                    }
+                MEDIA_STATUS_NO_DATE => {
+                    // The media stage already read this json and found no date in it.
                     let mut json_file = json_file.into_active_model();
-                    json_file.status = Set(MEDIA_STATUS_NO_RELATED.to_owned());
-                    let json_file = update_file_in_zip(json_file).await?;
-                    self.update_item_progress(&json_file.name, "set media to new", 1.0);
+                    json_file.status = Set(MEDIA_STATUS_NO_DATE.to_owned());
+                    let json_file = update_file_in_zip(&self.db, json_file).await?;
+                    self.update_item_progress(&json_file.name, "media has no date", 1.0);
                 }
                 MEDIA_STATUS_PROCESSED => {
                     self.update_item_progress(&json_file.name, "media processed", 0.4);
-                    self.update_item_progress(&json_file.name, "associte with json", 0.5);
+                    self.update_item_progress(&json_file.name, "associate with json", 0.5);
                     let media_file = match media_file.related_id {
                         None => {
                             self.associate_media_with_json(&media_file, &json_file)
@@ -270,124 +317,730 @@ impl FileListWidget {
                         }
                         Some(_) => media_file,
                     };
-                    let path = Path::new(&media_file.path);
-                    let target_folder = path.parent().unwrap();
-                    let json_path = target_folder.join(&json_file.name);
-                    fs::rename(&json_file.path, &json_path).await?;
-                    self.update_item_progress(&json_file.name, "json moved", 0.6);
-                    let mut json_file = json_file.into_active_model();
-                    json_file.status = Set(MEDIA_STATUS_PROCESSED.to_string());
-                    json_file.path = Set(json_path.to_str().unwrap().to_owned());
-                    let json_file = update_file_in_zip(json_file).await?;
-                    self.update_item_progress(&json_file.name, "read json contents", 0.7);
-                    let file_content = fs::read_to_string(&json_file.path).await?;
-                    self.update_item_progress(&json_file.name, "convert to raw json", 0.8);
-                    let raw_json: Value = serde_json::from_str(&file_content)?;
+                    self.update_item_progress(&json_file.name, "read json contents", 0.6);
+                    let (json_file, raw_json) = self.read_json_meta(json_file).await?;
+                    let metadata = PhotoMetadata::deserialize(&raw_json).ok();
+                    let Some(media_file) = self.rehome_placed_media(media_file, metadata.as_ref()).await? else {
+                        let mut json_file = json_file.into_active_model();
+                        json_file.status = Set(MEDIA_STATUS_SKIPPED.to_owned());
+                        let json_file = update_file_in_zip(&self.db, json_file).await?;
+                        self.update_item_progress(&json_file.name, "skipped trashed item", 1.0);
+                        return Ok(());
+                    };
+                    let json_file = self.move_json_file(json_file, &media_file).await?;
+                    if let Some(datetime_utc) = self.get_date_taken_from_json(&raw_json)? {
+                        self.set_taken_file_times(&json_file.path, datetime_utc).await?;
+                    }
+                    self.update_item_progress(&json_file.name, "json moved", 0.8);
+                    let taken = self
+                        .resolve_placed_taken_date(&media_file.path, &media_file.name, Some(&raw_json), None)
+                        .await;
+                    self.write_xmp_for(&media_file, metadata.as_ref(), taken.as_ref().map(|taken| taken.local()))
+                        .await?;
                     self.update_item_progress(&json_file.name, "create media file in db", 0.9);
-                    let _ = create_media_file(&json_file.name, &media_file.path, &raw_json).await?;
+                    let details = describe_media_file(&media_file.path, Some(&raw_json), taken).await?;
+                    let _ = create_media_file(&self.db, &media_file, Some(&json_file), &raw_json, &details).await?;
                     self.update_item_progress(&json_file.name, "created media file in db", 1.0);
                 }
-                MEDIA_STATUS_FAILED => {
+                MEDIA_STATUS_SKIPPED | MEDIA_STATUS_DUPLICATE => {
+                    let mut json_file = json_file.into_active_model();
+                    json_file.status = Set(media_file.status.clone());
+                    let json_file = update_file_in_zip(&self.db, json_file).await?;
+                    self.update_item_progress(&json_file.name, "media skipped", 1.0);
+                }
+                status if status.starts_with(MEDIA_STATUS_FAILED) => {
                     let mut json_file = json_file.into_active_model();
                     json_file.status = Set(format!(
                         "{}: media file already failed",
                         MEDIA_STATUS_FAILED
                     ));
-                    let json_file = update_file_in_zip(json_file).await?;
-                    self.update_item_progress(&json_file.name, "created media file in db", 1.0);
+                    let json_file = update_file_in_zip(&self.db, json_file).await?;
+                    self.update_item_progress(&json_file.name, "media failed", 1.0);
+                }
+                status => {
+                    let mut json_file = json_file.into_active_model();
+                    json_file.status = Set(format!(
+                        "{}: media file has unexpected status {}",
+                        MEDIA_STATUS_FAILED, status
+                    ));
+                    let json_file = update_file_in_zip(&self.db, json_file).await?;
+                    self.update_item_progress(&json_file.name, "media in unexpected state", 1.0);
                 }
-                _ => {}
             }
-        } else {
+        } else if has_pending_takeouts(&self.db).await? {
             self.update_item_progress(&json_file.name, "no media file", 0.5);
             let mut json_file = json_file.into_active_model();
-            json_file.status = Set(MEDIA_STATUS_NO_RELATED.to_owned());
-            //This one will simply wait for its turn.
-            let json_file = update_file_in_zip(json_file).await?;
-            self.update_item_progress(&json_file.name, "no media file", 1.0);
+            json_file.status = Set(MEDIA_STATUS_WAITING.to_owned());
+            //This one will be requeued when the next archive has been examined.
+            let json_file = update_file_in_zip(&self.db, json_file).await?;
+            // The last archive may have finished, and requeued what was waiting,
+            // after the check above.
+            if !has_pending_takeouts(&self.db).await? {
+                requeue_waiting_json(&self.db).await?;
+            }
+            self.update_item_progress(&json_file.name, "waiting for media file", 1.0);
+        } else {
+            // Every archive has been examined, the media is not coming.
+            let mut json_file = json_file.into_active_model();
+            json_file.status = Set(MEDIA_STATUS_ORPHANED.to_owned());
+            let json_file = update_file_in_zip(&self.db, json_file).await?;
+            self.update_item_progress(&json_file.name, "orphaned", 1.0);
         }
         Ok(())
     }
 
-    async fn get_date_taken_from_json(
-        &self,
-        json_file: &FileInZipModel,
-    ) -> Result<Option<DateTime<Utc>>> {
-        let file_content = fs::read_to_string(&json_file.path).await?;
-        let metadata: PhotoMetadata = serde_json::from_str(&file_content)?;
-        let timestamp: i64 = metadata.photo_taken_time.timestamp.parse()?;
+    pub(super) fn get_date_taken_from_json(&self, raw_json: &Value) -> Result<Option<DateTime<Utc>>> {
+        let metadata = PhotoMetadata::deserialize(raw_json)?;
+        let Some(photo_taken_time) = metadata.photo_taken_time else {
+            return Ok(None);
+        };
+        let timestamp: i64 = photo_taken_time.timestamp.parse()?;
 
         Ok(DateTime::from_timestamp(timestamp, 0))
     }
 
-    async fn process_media_file(&self, media_file: FileInZipModel) -> Result<()> {
-        self.update_item_progress(&media_file.name, "start processing", 0.1);
+    /// Reads the sidecar contents. Several media files can share one sidecar, so if
+    /// another task already moved it we reload its row and read it from there.
+    async fn read_json_meta(&self, json_file: FileInZipModel) -> Result<(FileInZipModel, Value)> {
+        let json_file = match fs::read_to_string(&json_file.path).await {
+            Ok(file_content) => return Ok((json_file, serde_json::from_str(&file_content)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => fetch_file_in_zip_by_id(&self.db, json_file.id)
+                .await?
+                .ok_or(anyhow::Error::msg("Related json file disappeared"))?,
+            Err(err) => return Err(err.into()),
+        };
+        let file_content = fs::read_to_string(&json_file.path).await?;
+        Ok((json_file, serde_json::from_str(&file_content)?))
+    }
+
+    /// Moves the sidecar next to the placed media file, unless another media
+    /// file that shares it has already done so. A media file that was renamed
+    /// to avoid a collision takes its sidecar's name along.
+    async fn move_json_file(
+        &self,
+        json_file: FileInZipModel,
+        media_file: &FileInZipModel,
+    ) -> Result<FileInZipModel> {
+        if json_file.status == MEDIA_STATUS_PROCESSED {
+            return Ok(json_file);
+        }
+        let media_path = Path::new(&media_file.path);
+        let placed_name = media_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Placed media file has no name")?;
+        let json_name = match json_file.name.strip_prefix(&media_file.name) {
+            Some(rest) if placed_name != media_file.name => format!("{}{}", placed_name, rest),
+            _ => json_file.name.clone(),
+        };
+        let json_path = media_path.parent().context("Placed media file has no folder")?.join(json_name);
+        let (placement, mode) = match self
+            .place_file_as(Path::new(&json_file.path), &json_path, placement_mode_for(false), Some(json_file.id))
+            .await
+        {
+            Ok(placed) => placed,
+            Err(err)
+                if err
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == ErrorKind::NotFound) =>
+            {
+                return fetch_file_in_zip_by_id(&self.db, json_file.id)
+                    .await?
+                    .ok_or(anyhow::Error::msg("Related json file disappeared"));
+            }
+            Err(err) => return Err(err),
+        };
+        let log = placement_log(&json_file.log, &placement);
+        let mut json_file = json_file.into_active_model();
+        json_file.status = Set(MEDIA_STATUS_PROCESSED.to_string());
+        json_file.path = Set(placement.path().to_string_lossy().into_owned());
+        json_file.log = Set(log);
+        json_file.placement_mode = Set(Some(mode.as_str().to_owned()));
+        update_file_in_zip(&self.db, json_file).await
+    }
+
+    /// Moves a file to `target`, or next to it when the name is taken.
+    pub(super) async fn place_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        file_in_zip_id: Option<i32>,
+    ) -> Result<Placement> {
+        Ok(self
+            .place_file_as(source, target, PlacementMode::Move, file_in_zip_id)
+            .await?
+            .0)
+    }
+
+    /// Puts a file at `target`, or next to it when the name is taken, the way
+    /// `mode` asks, and returns the mode that was used. Another task can claim
+    /// the same name between planning and placing, then the placement is
+    /// planned again. What was done is journaled for the file's row.
+    async fn place_file_as(
+        &self,
+        source: &Path,
+        target: &Path,
+        mode: PlacementMode,
+        file_in_zip_id: Option<i32>,
+    ) -> Result<(Placement, PlacementMode)> {
+        loop {
+            let placement = plan_placement(source, target).await?;
+            if let Placement::Duplicate(_) = placement {
+                return Ok((placement, mode));
+            }
+            match transfer(source, placement.path(), mode).await {
+                Ok(used) => {
+                    self.journal(used.as_str(), Some(source), Some(placement.path()), file_in_zip_id)
+                        .await?;
+                    return Ok((placement, used));
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// The template files are placed by, `PATH_TEMPLATE` unless it was
+    /// changed with `t`.
+    pub(super) fn path_template(&self) -> String {
+        self.get_read_state().path_template.clone()
+    }
+
+    pub fn is_editing_path_template(&self) -> bool {
+        self.get_read_state().template_input.is_some()
+    }
+
+    /// Starts editing the path template in the footer. Files already placed
+    /// keep their layout, so it cannot change while processing.
+    pub fn edit_path_template(&self) {
+        if self.is_processing() {
+            self.set_status_line("Stop processing before changing the path template".to_owned());
+            return;
+        }
+        let template = self.path_template();
+        self.preview_path_template(&template);
+        self.get_write_state().template_input = Some(template);
+    }
+
+    /// Adds a character to the template being edited, or erases the last one.
+    pub fn type_path_template(&self, c: Option<char>) {
+        let template = {
+            let mut state = self.get_write_state();
+            let Some(input) = state.template_input.as_mut() else {
+                return;
+            };
+            match c {
+                Some(c) => input.push(c),
+                None => {
+                    input.pop();
+                }
+            }
+            input.clone()
+        };
+        self.preview_path_template(&template);
+    }
+
+    /// Places files by the edited template from now on, if it is valid.
+    /// Otherwise it stays open for editing.
+    pub fn apply_path_template(&self) {
+        let Some(template) = self.get_read_state().template_input.clone() else {
+            return;
+        };
+        if self.preview_path_template(&template) {
+            let mut state = self.get_write_state();
+            state.path_template = template;
+            state.template_input = None;
+        }
+    }
+
+    pub fn cancel_path_template(&self) {
+        self.get_write_state().template_input = None;
+        self.set_status_line(format!("Path template unchanged: {}", self.path_template()));
+    }
 
-        let json_file = fetch_related(&media_file).await?;
-        if json_file.is_none() {
-            return Err(anyhow::Error::msg("No related json file"));
+    /// Shows where a sample photo would be placed, or what is wrong with the
+    /// template. Returns whether it is valid.
+    fn preview_path_template(&self, template: &str) -> bool {
+        match PathTemplate::parse(template) {
+            Ok(template) => {
+                let account = get_source_account();
+                let sample = template.render(&TemplateValues {
+                    taken: DateTime::parse_from_rfc3339("2019-07-04T23:30:00+02:00").unwrap(),
+                    name: "IMG_20190704_233000.jpg",
+                    album: Some("Summer holiday"),
+                    camera_make: Some("Google"),
+                    camera_model: Some("Pixel 7"),
+                    media_type: "photo",
+                    account: account.as_deref(),
+                    place: Some(&Place {
+                        country_code: "IT".to_owned(),
+                        country: "Italy".to_owned(),
+                        region: Some("Tuscany".to_owned()),
+                        city: "Florence".to_owned(),
+                    }),
+                    locale: get_month_locale(),
+                });
+                self.set_status_line(format!("A sample photo goes to {}", sample.display()));
+                true
+            }
+            Err(err) => {
+                self.set_status_line(err.to_string());
+                false
+            }
         }
+    }
 
-        let json_file = json_file.unwrap();
+    /// Renders the path template for a media file. The album, camera and place
+    /// are only looked up when the template uses them.
+    async fn render_media_path(
+        &self,
+        media_file: &FileInZipModel,
+        taken: DateTime<FixedOffset>,
+        location: Option<GeoData>,
+    ) -> Result<PathBuf> {
+        let template = PathTemplate::parse(&self.path_template())?;
+        let album = if template.uses("album") {
+            fetch_media_album(&self.db, media_file).await?
+        } else {
+            None
+        };
+        let details = if template.uses("camera_make") || template.uses("camera_model") {
+            read_media_details(&media_file.path).await.unwrap_or_default()
+        } else {
+            MediaDetails::default()
+        };
+        let place = if PLACE_TOKENS.iter().any(|token| template.uses(token)) {
+            location.and_then(place_at)
+        } else {
+            None
+        };
+        Ok(render_media_template(
+            &template,
+            &media_file.name,
+            taken,
+            album.as_ref().map(|album| album.title.as_str()),
+            &details,
+            place.as_ref(),
+        ))
+    }
 
-        // Extract the timestamp
-        self.update_item_progress(&media_file.name, "read taken date", 0.35);
-        let datetime_utc = self.get_date_taken_from_json(&json_file).await?;
+    /// The tree a file is placed in, from the flags on its sidecar. Trashed
+    /// photos are checked first, then archived and locked folder ones, then
+    /// favorites. `None` means the file is not placed at all.
+    pub(super) fn get_library_root(&self, metadata: Option<&PhotoMetadata>) -> Option<PathBuf> {
+        let target_folder = self.target_folder.clone();
+        let Some(metadata) = metadata else {
+            return Some(target_folder);
+        };
+        if metadata.trashed {
+            match get_trashed_policy() {
+                TrashedPolicy::Skip => return None,
+                TrashedPolicy::Quarantine => return Some(target_folder.join(TRASH_FOLDER)),
+                TrashedPolicy::Keep => {}
+            }
+        }
+        if (metadata.archived || metadata.in_locked_folder) && get_separate_archived() {
+            return Some(target_folder.join(ARCHIVE_FOLDER));
+        }
+        if metadata.favorited && get_favorite_policy() == FavoritePolicy::Folder {
+            return Some(target_folder.join(FAVORITES_FOLDER));
+        }
+        Some(target_folder)
+    }
 
-        //     match rexif_get_taken_date(&media_file.path).await {
-        //     Ok(Some(dt)) => Some(dt),
-        //     _ => match json_file.clone() {
-        //         Some(json_file) => {
-        //             self.update_item_progress(&media_file.name, "read date from json", 0.4);
-        //             self.get_date_taken_from_json(json_file).await?
-        //         }
-        //         _ => None,
-        //     },
-        // };
+    /// Writes the `.xmp` next to a placed file when its format keeps metadata
+    /// out of the file, or when favorites are rated. Favorites keep their
+    /// rating in their own folder too, only the `none` policy drops it.
+    async fn write_xmp_for(
+        &self,
+        media_file: &FileInZipModel,
+        metadata: Option<&PhotoMetadata>,
+        taken: Option<DateTime<FixedOffset>>,
+    ) -> Result<()> {
+        let favorite_policy = get_favorite_policy();
+        let rate_favorite = favorite_policy == FavoritePolicy::Rating && metadata.is_some_and(|metadata| metadata.favorited);
+        if !get_write_xmp_sidecars() || !(needs_xmp_sidecar(&media_file.extension) || rate_favorite) {
+            return Ok(());
+        }
+        self.update_item_progress(&media_file.name, "write xmp sidecar", 0.55);
+        let mut xmp_data = match metadata {
+            Some(metadata) => metadata.to_xmp_data(taken),
+            None => XmpData {
+                taken,
+                ..Default::default()
+            },
+        };
+        if favorite_policy == FavoritePolicy::None {
+            xmp_data.favorite = false;
+        }
+        // An `.xmp` already there, from the user's editor or an earlier run, is
+        // backed up so an undo puts it back instead of deleting it.
+        let keep_extension = get_xmp_keeps_extension();
+        let existing = xmp_path(Path::new(&media_file.path), keep_extension);
+        if fs::try_exists(&existing).await? {
+            self.back_up_before_writing(&existing, media_file.id).await?;
+            write_xmp_sidecar(Path::new(&media_file.path), keep_extension, &xmp_data).await?;
+        } else {
+            let xmp_path = write_xmp_sidecar(Path::new(&media_file.path), keep_extension, &xmp_data).await?;
+            self.journal(OPERATION_CREATE, None, Some(&xmp_path), Some(media_file.id)).await?;
+        }
+        Ok(())
+    }
 
-        let datetime_utc = match datetime_utc {
-            Some(dt) => dt,
+    /// A sidecar that shows up after its media was placed still decides the
+    /// tree the file belongs in, so the file follows. A copy of a trashed photo
+    /// leaves the library when those are skipped, the extract still has it. A
+    /// moved file has no other copy and is quarantined instead. Returns `None`
+    /// when the file left the library.
+    async fn rehome_placed_media(
+        &self,
+        media_file: FileInZipModel,
+        metadata: Option<&PhotoMetadata>,
+    ) -> Result<Option<FileInZipModel>> {
+        let target_folder = self.target_folder.clone();
+        let path = PathBuf::from(&media_file.path);
+        let Ok(relative) = path.strip_prefix(&target_folder) else {
+            return Ok(Some(media_file));
+        };
+        let (current_root, relative) = match relative.components().next() {
+            Some(first)
+                if [TRASH_FOLDER, ARCHIVE_FOLDER, FAVORITES_FOLDER]
+                    .iter()
+                    .any(|folder| first.as_os_str() == *folder) =>
+            {
+                (target_folder.join(first), relative.strip_prefix(first)?.to_path_buf())
+            }
+            _ => (target_folder.clone(), relative.to_path_buf()),
+        };
+        let root = match self.get_library_root(metadata) {
+            Some(root) => root,
             None => {
+                let extract = fetch_operations_to_undo(&self.db, &OperationFilter::File(media_file.id))
+                    .await?
+                    .into_iter()
+                    .find(|operation| {
+                        operation.kind != PlacementMode::Move.as_str()
+                            && operation.destination.as_deref() == Some(media_file.path.as_str())
+                    })
+                    .and_then(|operation| operation.source)
+                    .filter(|source| Path::new(source).is_file());
+                match extract {
+                    Some(extract) => {
+                        fs::remove_file(&path).await?;
+                        self.journal(OPERATION_REMOVE, Some(&path), Some(Path::new(&extract)), Some(media_file.id))
+                            .await?;
+                        let mut media_file = media_file.into_active_model();
+                        media_file.status = Set(MEDIA_STATUS_SKIPPED.to_owned());
+                        media_file.path = Set(extract);
+                        update_file_in_zip(&self.db, media_file).await?;
+                        return Ok(None);
+                    }
+                    None => target_folder.join(TRASH_FOLDER),
+                }
+            }
+        };
+        if root == current_root {
+            return Ok(Some(media_file));
+        }
+        self.update_item_progress(&media_file.name, "follow sidecar flags", 0.55);
+        let target = root.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let placement = self.place_file(&path, &target, Some(media_file.id)).await?;
+        if let Placement::Duplicate(existing) = &placement {
+            fs::remove_file(&path).await?;
+            self.journal(OPERATION_REMOVE, Some(&path), Some(existing), Some(media_file.id))
+                .await?;
+        }
+        // The xmp is written again next to where the file went.
+        remove_if_exists(&xmp_path(&path, get_xmp_keeps_extension())).await?;
+        let log = placement_log(&media_file.log, &placement);
+        let mut media_file = media_file.into_active_model();
+        media_file.path = Set(placement.path().to_string_lossy().into_owned());
+        media_file.log = Set(log);
+        Ok(Some(update_file_in_zip(&self.db, media_file).await?))
+    }
+
+    /// Copies a file that is about to be written in place next to it and
+    /// journals the write, so an undo puts the old bytes back.
+    async fn back_up_before_writing(&self, path: &Path, file_in_zip_id: i32) -> Result<PathBuf> {
+        let name = path.file_name().context("File to back up has no name")?.to_string_lossy();
+        let backup = path.with_file_name(format!(".{}-{}.orig", file_in_zip_id, name));
+        remove_if_exists(&backup).await?;
+        transfer(path, &backup, PlacementMode::Reflink).await?;
+        self.journal(OPERATION_WRITE, Some(path), Some(&backup), Some(file_in_zip_id))
+            .await?;
+        Ok(backup)
+    }
+
+    /// Sets a file aside the way the plan says, without looking at it again.
+    async fn follow_planned_action(&self, media_file: FileInZipModel, entry: &PlanEntry) -> Result<()> {
+        let status = match entry.action {
+            PlanAction::Duplicate => MEDIA_STATUS_DUPLICATE,
+            PlanAction::NoDate => MEDIA_STATUS_NO_DATE,
+            _ => MEDIA_STATUS_SKIPPED,
+        };
+        let duplicate_of_id = match &entry.duplicate_of {
+            Some(duplicate_of) => fetch_media_by_path(&self.db, duplicate_of).await?.map(|original| original.id),
+            None => None,
+        };
+        let log = append_log(
+            &media_file.log,
+            json!({
+                "decision": "planned",
+                "action": entry.action.as_str(),
+                "duplicate_of": entry.duplicate_of,
+                "reason": entry.reason,
+            }),
+        );
+        let mut media_file = media_file.into_active_model();
+        media_file.status = Set(status.to_owned());
+        if duplicate_of_id.is_some() {
+            media_file.duplicate_of_id = Set(duplicate_of_id);
+        }
+        media_file.log = Set(log);
+        let media_file = update_file_in_zip(&self.db, media_file).await?;
+        self.update_item_progress(&media_file.name, &format!("planned {}", entry.action.as_str()), 1.0);
+        Ok(())
+    }
+
+    /// Leaves a file the plan cannot be followed for where it was extracted and
+    /// fails it with the reason, so the plan can be made again.
+    async fn report_divergence(&self, media_file: FileInZipModel, reason: &str) -> Result<()> {
+        let log = append_log(&media_file.log, json!({ "decision": "diverged", "reason": reason }));
+        let mut media_file = media_file.into_active_model();
+        media_file.status = Set(format!("{}: diverged from the plan, {}", MEDIA_STATUS_FAILED, reason));
+        media_file.log = Set(log);
+        let media_file = update_file_in_zip(&self.db, media_file).await?;
+        self.update_item_progress(&media_file.name, "diverged from the plan", 1.0);
+        Ok(())
+    }
+
+    /// Edited copies and motion halves follow their still original into the folder
+    /// it was already placed in.
+    async fn get_group_folder(&self, media_file: &FileInZipModel) -> Result<Option<PathBuf>> {
+        let Some(group_id) = media_file.group_id else {
+            return Ok(None);
+        };
+        Ok(fetch_file_in_zip_by_id(&self.db, group_id)
+            .await?
+            .filter(|primary| primary.status == MEDIA_STATUS_PROCESSED)
+            .and_then(|primary| Path::new(&primary.path).parent().map(Path::to_path_buf)))
+    }
+
+    async fn set_taken_file_times(&self, path: &str, datetime_utc: DateTime<Utc>) -> Result<()> {
+        set_file_times(path, datetime_utc, get_file_times_policy()).await
+    }
+
+    async fn process_media_file(&self, media_file: FileInZipModel) -> Result<()> {
+        self.update_item_progress(&media_file.name, "start processing", 0.1);
+
+        // A plan from a dry run has the last word on what happens to the file.
+        let planned_entries = self.get_read_state().planned_entries.clone();
+        let planned = match (&media_file.archive_path, &media_file.content_hash) {
+            (Some(archive_path), Some(content_hash)) => planned_entries.get(archive_path, content_hash).cloned(),
+            _ => None,
+        };
+        if let Some(entry) = &planned
+            && !matches!(entry.action, PlanAction::Place | PlanAction::Rename)
+        {
+            return self.follow_planned_action(media_file, entry).await;
+        }
+
+        let json = match fetch_related(&self.db, &media_file).await? {
+            Some(json_file) => {
+                self.update_item_progress(&media_file.name, "read json contents", 0.2);
+                Some(self.read_json_meta(json_file).await?)
+            }
+            None => None,
+        };
+        let raw_json = json.as_ref().map(|(_, raw_json)| raw_json);
+        let metadata = raw_json.and_then(|raw_json| PhotoMetadata::deserialize(raw_json).ok());
+
+        self.update_item_progress(&media_file.name, "resolve taken date", 0.35);
+        // A sidecar without a usable photoTakenTime leaves the date to the other sources.
+        let json_taken = raw_json.and_then(|raw_json| self.get_date_taken_from_json(raw_json).ok().flatten());
+        let taken = resolve_taken_date(
+            &media_file.path,
+            &media_file.name,
+            json_taken,
+            media_file.archive_mtime,
+            &get_date_source_priority(),
+        )
+        .await;
+        // Folders follow the day on the clock where the photo was taken.
+        self.update_item_progress(&media_file.name, "resolve timezone", 0.36);
+        let exif_offset = if is_exif_writable(&media_file.extension) {
+            read_exif_offset(&media_file.path).await.ok().flatten()
+        } else {
+            None
+        };
+        let has_edited_variant =
+            media_file.variant == MEDIA_VARIANT_ORIGINAL && has_edited_variant(&self.db, &media_file.path_no_ext).await?;
+        let decision = self.decide_media(MediaFacts {
+            variant: &media_file.variant,
+            has_edited_variant,
+            metadata: metadata.as_ref(),
+            taken,
+            exif_offset,
+        });
+        let (library_root, resolved) = match decision {
+            MediaDecision::Place { library_root, taken } => (library_root, taken),
+            decision if planned.is_some() => {
+                let reason = match decision {
+                    MediaDecision::Skip(reason) => format!("it is skipped now, {}", reason.as_str()),
+                    _ => "it has no date now".to_owned(),
+                };
+                return self.report_divergence(media_file, &reason).await;
+            }
+            MediaDecision::Skip(reason) => {
                 let mut media_file = media_file.into_active_model();
-                media_file.status = Set(MEDIA_STATUS_NO_DATE.to_owned()); // This can then be handled using
-                                                                          //the json I guess.
-                let media_file = update_file_in_zip(media_file).await?;
+                media_file.status = Set(MEDIA_STATUS_SKIPPED.to_owned());
+                let media_file = update_file_in_zip(&self.db, media_file).await?;
+                self.update_item_progress(&media_file.name, &format!("skipped, {}", reason.as_str()), 1.0);
+                return Ok(());
+            }
+            MediaDecision::NoDate => {
+                let mut media_file = media_file.into_active_model();
+                media_file.status = Set(MEDIA_STATUS_NO_DATE.to_owned());
+                let media_file = update_file_in_zip(&self.db, media_file).await?;
                 self.update_item_progress(&media_file.name, "no date", 1.0);
                 return Ok(());
             }
         };
+        let location = metadata.as_ref().and_then(PhotoMetadata::best_location);
+        let datetime_utc = resolved.taken;
+        let datetime_local = resolved.local();
 
-        let year = datetime_utc.year();
-        let month_name = datetime_utc.format("%B").to_string();
-        let day = datetime_utc.day();
-
-        let target_folder = get_target_folder().join(format!("{}/{}/{}/", year, month_name, day));
+        let planned_destination = planned.and_then(|entry| entry.destination);
+        let planned_name = planned_destination.is_some();
+        let media_path = match planned_destination {
+            Some(destination) => destination,
+            None => match self.get_group_folder(&media_file).await? {
+                Some(group_folder) => group_folder.join(&media_file.name),
+                None => library_root.join(self.render_media_path(&media_file, datetime_local, location).await?),
+            },
+        };
+        let target_folder = media_path.parent().context("Media path has no folder")?.to_path_buf();
         fs::create_dir_all(&target_folder).await?;
 
-        let media_path = target_folder.join(&media_file.name);
+        let metadata_writes = self.get_read_state().metadata_writes;
+        // Only the sidecar's taken time is written back. A date guessed from
+        // the name or the archive would be burned into the file as if the
+        // camera had set it.
+        let from_sidecar = resolved.source == DateSource::Json;
+        let write_exif_dates =
+            metadata_writes.exif_dates && from_sidecar && is_exif_writable(&media_file.extension);
+        let write_video_dates =
+            metadata_writes.video_dates && from_sidecar && is_quicktime(&media_file.extension);
+        let write_gps = location.is_some() && metadata_writes.exif_gps && is_exif_writable(&media_file.extension);
+        let writes = write_exif_dates || write_video_dates || write_gps;
+        let mode = placement_mode_for(writes);
+        // Unless the extract is moved, the library gets a file of its own next
+        // to where it goes and metadata is only written to that one.
+        let (working_path, mode) = match mode {
+            PlacementMode::Move => (PathBuf::from(&media_file.path), mode),
+            mode => {
+                self.update_item_progress(&media_file.name, "stage media file", 0.37);
+                let staging_path = target_folder.join(format!(".{}-{}", media_file.id, media_file.name));
+                remove_if_exists(&staging_path).await?;
+                let used = transfer(Path::new(&media_file.path), &staging_path, mode).await?;
+                self.journal(used.as_str(), Some(Path::new(&media_file.path)), Some(&staging_path), Some(media_file.id))
+                    .await?;
+                (staging_path, used)
+            }
+        };
+
+        // A moved extract is written in place, a copy of it is kept for undo.
+        let backup = if writes && mode == PlacementMode::Move {
+            Some(self.back_up_before_writing(&working_path, media_file.id).await?)
+        } else {
+            None
+        };
+        let written = async {
+            if write_exif_dates {
+                self.update_item_progress(&media_file.name, "write exif dates", 0.38);
+                write_exif_taken_date(&working_path, datetime_local).await?;
+            }
+
+            if write_video_dates {
+                self.update_item_progress(&media_file.name, "write video dates", 0.38);
+                write_quicktime_taken_date(&working_path, datetime_utc).await?;
+            }
+
+            if let Some(location) = location
+                && write_gps
+            {
+                self.update_item_progress(&media_file.name, "write exif location", 0.39);
+                write_exif_location(&working_path, location).await?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        // A staged copy is not left behind in the library for a failed write,
+        // an extract gets its old bytes back.
+        if let Err(err) = written {
+            match &backup {
+                Some(backup) => fs::rename(backup, &working_path).await?,
+                None => remove_if_exists(&working_path).await?,
+            }
+            return Err(err);
+        }
+
+        // A planned name is kept or the file is left where it was, never renamed.
+        if planned_name
+            && let Placement::Renamed { taken_by, .. } = plan_placement(&working_path, &media_path).await?
+        {
+            if working_path != Path::new(&media_file.path) {
+                remove_if_exists(&working_path).await?;
+            }
+            let reason = format!("{} is taken by another file", taken_by.display());
+            return self.report_divergence(media_file, &reason).await;
+        }
+
         self.update_item_progress(&media_file.name, "move media file", 0.4);
-        fs::rename(&media_file.path, &media_path).await?;
+        let placement = self.place_file(&working_path, &media_path, Some(media_file.id)).await?;
+        let log = placement_log(&media_file.log, &placement);
+        if let Placement::Duplicate(existing) = &placement {
+            if mode != PlacementMode::Move {
+                fs::remove_file(&working_path).await?;
+                self.journal(OPERATION_REMOVE, Some(&working_path), Some(existing), Some(media_file.id))
+                    .await?;
+            }
+            let original = fetch_media_by_path(&self.db, &existing.to_string_lossy()).await?;
+            let mut media_file = media_file.into_active_model();
+            media_file.status = Set(MEDIA_STATUS_DUPLICATE.to_owned());
+            media_file.duplicate_of_id = Set(original.map(|original| original.id));
+            media_file.log = Set(log);
+            let media_file = update_file_in_zip(&self.db, media_file).await?;
+            self.update_item_progress(&media_file.name, "duplicate of a placed file", 1.0);
+            return Ok(());
+        }
+        let media_path = placement.path().to_path_buf();
         self.update_item_progress(&media_file.name, "update path in db", 0.5);
         let mut media_file = media_file.into_active_model();
         media_file.status = Set(MEDIA_STATUS_PROCESSED.to_string());
-        media_file.path = Set(media_path.to_str().unwrap().to_owned());
-        let media_file = update_file_in_zip(media_file).await?;
+        media_file.path = Set(media_path.to_string_lossy().into_owned());
+        media_file.log = Set(log);
+        media_file.placement_mode = Set(Some(mode.as_str().to_owned()));
+        let media_file = update_file_in_zip(&self.db, media_file).await?;
+        self.write_xmp_for(&media_file, metadata.as_ref(), Some(datetime_local)).await?;
         self.update_item_progress(&media_file.name, "done with media file", 0.6);
-        self.update_item_progress(&media_file.name, "move json file if exists", 0.65);
-        let json_path = target_folder.clone().join(&json_file.name);
-        fs::rename(&json_file.path, &json_path).await?;
-        self.update_item_progress(&media_file.name, "json moved", 0.7);
-        let mut json_file = json_file.into_active_model();
-        json_file.status = Set(MEDIA_STATUS_PROCESSED.to_string());
-        json_file.path = Set(json_path.to_str().unwrap().to_owned());
-        let json_file = update_file_in_zip(json_file).await?;
-        self.update_item_progress(&media_file.name, "read json contents", 0.75);
-        let file_content = fs::read_to_string(json_file.path).await?;
-        self.update_item_progress(&media_file.name, "convert to raw json", 0.8);
-        let raw_json: Value = serde_json::from_str(&file_content)?;
+        self.update_item_progress(&media_file.name, "set file times", 0.62);
+        self.set_taken_file_times(&media_file.path, datetime_utc).await?;
+        if let Some((json_file, _)) = &json {
+            self.update_item_progress(&media_file.name, "move json file if exists", 0.65);
+            let json_file = self.move_json_file(json_file.clone(), &media_file).await?;
+            self.update_item_progress(&media_file.name, "json moved", 0.7);
+            self.set_taken_file_times(&json_file.path, datetime_utc).await?;
+        }
         self.update_item_progress(&media_file.name, "create media file in db", 0.85);
 
-        let _ = create_media_file(&media_file.name, &media_file.path, &raw_json).await?;
+        let json_meta = raw_json.cloned().unwrap_or(Value::Null);
+        let details = describe_media_file(&media_file.path, raw_json, Some(resolved)).await?;
+        let json_file = json.as_ref().map(|(json_file, _)| json_file);
+        let _ = create_media_file(&self.db, &media_file, json_file, &json_meta, &details).await?;
         self.update_item_progress(&media_file.name, "create media file in db", 0.9);
 
         self.update_item_progress(&media_file.name, "done", 1.0);
@@ -402,13 +1055,120 @@ impl FileListWidget {
     ) -> Result<(Model, Model)> {
         let mut to_save_media_file = media_file.clone().into_active_model();
         to_save_media_file.related_id = Set(Some(json_data.id));
-        let to_save_media_file = update_file_in_zip(to_save_media_file).await?;
+        let to_save_media_file = update_file_in_zip(&self.db, to_save_media_file).await?;
         let mut json_data = json_data.clone().into_active_model();
         json_data.related_id = Set(Some(to_save_media_file.id));
-        let json_data = update_file_in_zip(json_data).await?;
+        let json_data = update_file_in_zip(&self.db, json_data).await?;
         Ok((to_save_media_file, json_data))
     }
 
+    /// The same photo turns up in its year folder, in every album it was added
+    /// to and in overlapping exports. The first copy extracted is kept, later
+    /// copies with the same content are recorded as duplicates of it.
+    async fn find_content_duplicate(&self, name: &str, content_hash: &str) -> Result<Option<i32>> {
+        if name.to_lowercase().ends_with(".json") {
+            return Ok(None);
+        }
+        Ok(fetch_media_by_content_hash(&self.db, content_hash)
+            .await?
+            .map(|canonical| canonical.id))
+    }
+
+    /// When extracts are moved into the library only the canonical copy is
+    /// kept on disk, the row stays for albums. The canonical holds the bytes an
+    /// undo restores from. Other modes leave every extract as it was.
+    async fn remove_duplicate_extract(&self, file_in_zip: &FileInZipModel) -> Result<()> {
+        let Some(duplicate_of_id) = file_in_zip.duplicate_of_id else {
+            return Ok(());
+        };
+        if get_placement_mode() != PlacementMode::Move {
+            return Ok(());
+        }
+        let Some(canonical) = fetch_file_in_zip_by_id(&self.db, duplicate_of_id).await? else {
+            return Ok(());
+        };
+        // The same entry in a later archive was extracted over the canonical.
+        if canonical.path == file_in_zip.path {
+            return Ok(());
+        }
+        let path = Path::new(&file_in_zip.path);
+        fs::remove_file(path).await?;
+        self.journal(OPERATION_REMOVE, Some(path), Some(Path::new(&canonical.path)), Some(file_in_zip.id))
+            .await
+    }
+
+    async fn add_to_album_if_exists(&self, file_in_zip: &FileInZipModel) -> Result<()> {
+        if file_in_zip.file_type != FILE_TYPE_MEDIA {
+            return Ok(());
+        }
+        let Some(folder) = Path::new(&file_in_zip.path).parent() else {
+            return Ok(());
+        };
+        if let Some(album) = fetch_album_by_path(&self.db, &folder.to_string_lossy()).await? {
+            add_media_to_album(&self.db, album.id, file_in_zip).await?;
+        }
+        Ok(())
+    }
+
+    /// Album metadata creates the album, account-level json is collected in one
+    /// folder of the library and json we know nothing about is left where it was
+    /// extracted. Sidecars go through matching.
+    async fn store_non_sidecar_json(&self, file_in_zip: FileInZipModel) -> Result<()> {
+        match file_in_zip.file_type.as_str() {
+            FILE_TYPE_ACCOUNT_METADATA => {
+                let account_folder = self.target_folder.join(ACCOUNT_METADATA_FOLDER);
+                fs::create_dir_all(&account_folder).await?;
+                let account_path = account_folder.join(&file_in_zip.name);
+                let (placement, mode) = self
+                    .place_file_as(
+                        Path::new(&file_in_zip.path),
+                        &account_path,
+                        get_placement_mode(),
+                        Some(file_in_zip.id),
+                    )
+                    .await?;
+                let log = placement_log(&file_in_zip.log, &placement);
+                let mut file_in_zip = file_in_zip.into_active_model();
+                file_in_zip.status = Set(MEDIA_STATUS_PROCESSED.to_owned());
+                file_in_zip.path = Set(placement.path().to_string_lossy().into_owned());
+                file_in_zip.log = Set(log);
+                file_in_zip.placement_mode = Set(Some(mode.as_str().to_owned()));
+                update_file_in_zip(&self.db, file_in_zip).await?;
+            }
+            FILE_TYPE_ALBUM_METADATA => {
+                let folder = Path::new(&file_in_zip.path).parent().context("Album metadata has no folder")?;
+                let folder_name = &folder.file_name().unwrap_or_default().to_string_lossy();
+                let status = if is_year_folder(folder_name) {
+                    MEDIA_STATUS_SKIPPED
+                } else {
+                    let file_content = fs::read_to_string(&file_in_zip.path).await?;
+                    let raw_json: Value = serde_json::from_str(&file_content)?;
+                    let title = raw_json
+                        .get("title")
+                        .and_then(Value::as_str)
+                        .filter(|title| !title.is_empty())
+                        .unwrap_or(folder_name);
+                    let album = store_album(&self.db, &file_in_zip.path_no_ext, title, &raw_json).await?;
+                    // Media extracted before the metadata joins the album now.
+                    for media_file in fetch_media_in_folder(&self.db, &file_in_zip.path_no_ext).await? {
+                        add_media_to_album(&self.db, album.id, &media_file).await?;
+                    }
+                    MEDIA_STATUS_PROCESSED
+                };
+                let mut file_in_zip = file_in_zip.into_active_model();
+                file_in_zip.status = Set(status.to_owned());
+                update_file_in_zip(&self.db, file_in_zip).await?;
+            }
+            FILE_TYPE_OTHER => {
+                let mut file_in_zip = file_in_zip.into_active_model();
+                file_in_zip.status = Set(MEDIA_STATUS_SKIPPED.to_owned());
+                update_file_in_zip(&self.db, file_in_zip).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     async fn examine_zip_with_progress(self, takeout_zip: TakeoutZipModel) -> anyhow::Result<()> {
         let file = TokioFile::open(&takeout_zip.local_path).await?;
         let buf_reader = BufReader::new(file);
@@ -416,7 +1176,7 @@ impl FileListWidget {
         let decoder = GzipDecoder::new(buf_reader);
         let mut archive = Archive::new(decoder);
         let mut entries = archive.entries()?;
-        let target_folder = get_target_folder();
+        let target_folder = self.target_folder.clone();
         let mut total = 0;
         // count all...
         while let Some(file) = entries.next().await {
@@ -449,21 +1209,33 @@ impl FileListWidget {
                 then we add the data for the file to the database
                 */
                 let mut output_file = fs::File::create(&full_path).await?;
-                tokio::io::copy(&mut entry, &mut output_file).await?;
-
-                let _file_in_zip = create_file_in_zip(
-                    takeout_zip.id,
-                    entry
-                        .path()?
-                        .file_name()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_owned(),
-                    full_path.to_str().unwrap().to_owned(),
+                let (content_hash, size) = copy_and_hash(&mut entry, &mut output_file).await?;
+
+                let name = entry
+                    .path()?
+                    .file_name()
+                    .context("Archive entry has no name")?
+                    .to_string_lossy()
+                    .into_owned();
+                let duplicate_of_id = self.find_content_duplicate(&name, &content_hash).await?;
+                let file_in_zip = create_file_in_zip(&self.db,
+                    NewFileInZip {
+                        takeout_zip_id: takeout_zip.id,
+                        name,
+                        path: full_path.to_string_lossy().into_owned(),
+                        archive_path: entry.path()?.to_str().map(str::to_owned),
+                        archive_mtime: entry.header().mtime().ok().map(|mtime| mtime as i64),
+                        content_hash: Some(content_hash),
+                        size: Some(size as i64),
+                        duplicate_of_id,
+                    },
                     true,
                 )
                 .await?;
+                self.add_to_album_if_exists(&file_in_zip).await?;
+                drop(output_file);
+                self.remove_duplicate_extract(&file_in_zip).await?;
+                self.store_non_sidecar_json(file_in_zip).await?;
                 let progress = if total > 0 {
                     (count as f64 / total as f64).clamp(0.0, 1.0)
                 } else {
@@ -476,14 +1248,14 @@ impl FileListWidget {
             fs::remove_file(&takeout_zip.local_path).await?;
             let mut takeout_zip = takeout_zip.into_active_model();
             takeout_zip.local_path = Set("".to_string());
-            update_takeout_zip(takeout_zip).await?;
+            update_takeout_zip(&self.db, takeout_zip).await?;
         }
         Ok(())
     }
 
     async fn download_to_disk_with_progress(self, file_item: DriveItem) -> anyhow::Result<String> {
         if let DriveItem::File(id, name) = file_item {
-            let local_path = get_file_path(&name);
+            let local_path = self.target_folder.join(&name);
             let mut response = download(id).await?;
             let size = response.content_length().unwrap_or_default();
             let mut written = usize::default();
@@ -497,9 +1269,570 @@ impl FileListWidget {
                 written += async_file.write(chunk.as_ref()).await?;
                 self.update_item_progress(&name, "downloading", written as f64 / size as f64);
             }
-            Ok(local_path.to_str().unwrap().to_string())
+            Ok(local_path.to_string_lossy().into_owned())
         } else {
             Err(anyhow::Error::msg("Not a file"))
         }
     }
 }
+
+/// Renders `template` for a media file once its album, camera and place are
+/// known.
+pub(super) fn render_media_template(
+    template: &PathTemplate,
+    name: &str,
+    taken: DateTime<FixedOffset>,
+    album: Option<&str>,
+    details: &MediaDetails,
+    place: Option<&Place>,
+) -> PathBuf {
+    let account = get_source_account();
+    let extension = Path::new(name).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    template.render(&TemplateValues {
+        taken,
+        name,
+        album,
+        camera_make: details.camera_make.as_deref(),
+        camera_model: details.camera_model.as_deref(),
+        media_type: media_type(extension),
+        account: account.as_deref(),
+        place,
+        locale: get_month_locale(),
+    })
+}
+
+/// Records on the row why a file was not placed under its own name.
+pub(super) fn placement_log(log: &Value, placement: &Placement) -> Value {
+    match placement {
+        Placement::Free(_) => log.clone(),
+        Placement::Renamed { path, taken_by } => append_log(
+            log,
+            json!({
+                "decision": "renamed",
+                "path": path,
+                "taken_by": taken_by,
+            }),
+        ),
+        Placement::Duplicate(existing) => append_log(
+            log,
+            json!({
+                "decision": "duplicate",
+                "of": existing,
+            }),
+        ),
+    }
+}
+
+/// `PLACEMENT_MODE` for a file that is placed and then written to when
+/// `writes`. A hard link shares its bytes and times with the extract, which
+/// has to stay as it was, so such a file and any file that gets its file times
+/// set is copied instead.
+fn placement_mode_for(writes: bool) -> PlacementMode {
+    match get_placement_mode() {
+        PlacementMode::Hardlink if writes || get_file_times_policy() != FileTimesPolicy::None => PlacementMode::Copy,
+        mode => mode,
+    }
+}
+
+/// Clears what an interrupted run left at `path`.
+async fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::list_takeouts;
+    use crate::db::test_db::{self, TestDb};
+    use crate::file_list_widget::journal::UndoScope;
+    use async_compression::tokio::write::GzipEncoder;
+    use sea_orm::EntityTrait;
+    use crate::metadata_writer::tests::{jpeg, movie};
+    use crate::settings::MetadataWrites;
+
+    const SIDECAR: &str = r#"{"photoTakenTime": {"timestamp": "1562275800", "formatted": "Jul 4, 2019"}}"#;
+
+    /// A sidecar that had no media when it was stored, and the media row it
+    /// finds now, in `media_status`.
+    async fn orphan_json(
+        db: &TestDb,
+        folder: &Path,
+        media_status: Option<&str>,
+    ) -> (FileInZipModel, Option<FileInZipModel>) {
+        orphan_json_with(db, folder, media_status, SIDECAR).await
+    }
+
+    async fn orphan_json_with(
+        db: &TestDb,
+        folder: &Path,
+        media_status: Option<&str>,
+        sidecar: &str,
+    ) -> (FileInZipModel, Option<FileInZipModel>) {
+        let takeout_zip_id = db.takeout().await;
+        let media = match media_status {
+            Some(status) => {
+                let media = db.extract(takeout_zip_id, folder.join("IMG_0001.jpg").to_str().unwrap(), false).await;
+                Some(db.set_status(media, status).await)
+            }
+            None => None,
+        };
+        let json_path = folder.join("IMG_0001.jpg.json");
+        fs::write(&json_path, sidecar).await.unwrap();
+        let json = db.extract(takeout_zip_id, json_path.to_str().unwrap(), false).await;
+        (db.set_status(json, MEDIA_STATUS_PROCESSING).await, media)
+    }
+
+    /// Points the media row at a file the media stage placed at `placed`.
+    async fn place(db: &TestDb, media: FileInZipModel, placed: &Path, mode: PlacementMode) -> FileInZipModel {
+        fs::create_dir_all(placed.parent().unwrap()).await.unwrap();
+        fs::write(placed, b"not really a jpeg").await.unwrap();
+        let mut media = media.into_active_model();
+        media.path = Set(placed.to_str().unwrap().to_owned());
+        media.placement_mode = Set(Some(mode.as_str().to_owned()));
+        update_file_in_zip(&db.conn, media).await.unwrap()
+    }
+
+    async fn json_status_for(db: &TestDb, media_status: Option<&str>) -> String {
+        let folder = tempfile::tempdir().unwrap();
+        let (json, _) = orphan_json(db, folder.path(), media_status).await;
+        db.widget().process_json_file(json.clone()).await.unwrap();
+        db.reload(&json).await.status
+    }
+
+    #[tokio::test]
+    async fn queues_media_that_is_not_placed_yet() {
+        for media_status in [MEDIA_STATUS_NO_RELATED, MEDIA_STATUS_HAS_RELATED, MEDIA_STATUS_PROCESSING] {
+            let db = test_db::fresh().await;
+            let folder = tempfile::tempdir().unwrap();
+            let (json, media) = orphan_json(&db, folder.path(), Some(media_status)).await;
+            db.widget().process_json_file(json.clone()).await.unwrap();
+            let (json, media) = (db.reload(&json).await, db.reload(&media.unwrap()).await);
+            assert_eq!(json.status, MEDIA_STATUS_HAS_RELATED);
+            assert_eq!(json.related_id, Some(media.id));
+            assert_eq!(media.related_id, Some(json.id));
+            let expected = match media_status {
+                MEDIA_STATUS_NO_RELATED => MEDIA_STATUS_HAS_RELATED,
+                status => status,
+            };
+            assert_eq!(media.status, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_media_that_was_not_placed() {
+        for (media_status, expected) in [
+            (MEDIA_STATUS_NO_DATE, MEDIA_STATUS_NO_DATE.to_owned()),
+            (MEDIA_STATUS_SKIPPED, MEDIA_STATUS_SKIPPED.to_owned()),
+            (MEDIA_STATUS_DUPLICATE, MEDIA_STATUS_DUPLICATE.to_owned()),
+            ("failed: disk full", format!("{}: media file already failed", MEDIA_STATUS_FAILED)),
+        ] {
+            let db = test_db::fresh().await;
+            assert_eq!(json_status_for(&db, Some(media_status)).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_media_in_an_unexpected_state() {
+        let db = test_db::fresh().await;
+        let status = json_status_for(&db, Some(MEDIA_STATUS_ORPHANED)).await;
+        assert!(status.starts_with(MEDIA_STATUS_FAILED), "{}", status);
+    }
+
+    #[tokio::test]
+    async fn waits_while_archives_are_examined_then_orphans() {
+        let db = test_db::fresh().await;
+        assert_eq!(json_status_for(&db, None).await, MEDIA_STATUS_WAITING);
+        drop(db);
+
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let (json, _) = orphan_json(&db, folder.path(), None).await;
+        db.set_takeouts_status(ZIP_STATUS_PROCESSED).await;
+        db.widget().process_json_file(json.clone()).await.unwrap();
+        assert_eq!(db.reload(&json).await.status, MEDIA_STATUS_ORPHANED);
+    }
+
+    #[tokio::test]
+    async fn moves_the_sidecar_to_media_already_placed() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let library = folder.path().join("library");
+        fs::create_dir_all(&library).await.unwrap();
+        let (json, media) = orphan_json(&db, folder.path(), Some(MEDIA_STATUS_PROCESSED)).await;
+        // The media stage left the row pointing at the placed file.
+        let placed = library.join("IMG_0001.jpg");
+        fs::write(&placed, b"not really a jpeg").await.unwrap();
+        let mut media = media.unwrap().into_active_model();
+        media.path = Set(placed.to_str().unwrap().to_owned());
+        let media = update_file_in_zip(&db.conn, media).await.unwrap();
+
+        db.widget().process_json_file(json.clone()).await.unwrap();
+
+        let json = db.reload(&json).await;
+        assert_eq!(Path::new(&json.path), library.join("IMG_0001.jpg.json"));
+        assert!(fs::try_exists(&json.path).await.unwrap());
+        let catalog = db.catalog().await;
+        assert_eq!(catalog.len(), 1);
+        assert_eq!(catalog[0].file_in_zip_id, Some(media.id));
+        assert_eq!(catalog[0].sidecar_file_in_zip_id, Some(json.id));
+    }
+
+    #[tokio::test]
+    async fn late_trashed_sidecar_quarantines_placed_media() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let sidecar = r#"{"photoTakenTime": {"timestamp": "1562275800", "formatted": "Jul 4, 2019"}, "trashed": true}"#;
+        let (json, media) = orphan_json_with(&db, folder.path(), Some(MEDIA_STATUS_PROCESSED), sidecar).await;
+        let placed = db.target_folder.join("2019/07/IMG_0001.jpg");
+        let media = place(&db, media.unwrap(), &placed, PlacementMode::Move).await;
+
+        db.widget().process_json_file(json.clone()).await.unwrap();
+
+        let quarantined = db.target_folder.join(TRASH_FOLDER).join("2019/07/IMG_0001.jpg");
+        assert_eq!(Path::new(&db.reload(&media).await.path), quarantined);
+        assert!(quarantined.is_file());
+        assert!(!placed.exists());
+        assert_eq!(Path::new(&db.reload(&json).await.path), quarantined.with_file_name("IMG_0001.jpg.json"));
+    }
+
+    #[tokio::test]
+    async fn late_trashed_sidecar_without_a_taken_time_quarantines_placed_media() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let (json, media) = orphan_json_with(&db, folder.path(), Some(MEDIA_STATUS_PROCESSED), r#"{"trashed": true}"#).await;
+        let placed = db.target_folder.join("2019/07/IMG_0001.jpg");
+        let media = place(&db, media.unwrap(), &placed, PlacementMode::Move).await;
+
+        db.widget().process_json_file(json).await.unwrap();
+
+        let quarantined = db.target_folder.join(TRASH_FOLDER).join("2019/07/IMG_0001.jpg");
+        assert_eq!(Path::new(&db.reload(&media).await.path), quarantined);
+        assert!(!placed.exists());
+    }
+
+    #[tokio::test]
+    async fn late_favorite_sidecar_rates_placed_media() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let sidecar = r#"{"photoTakenTime": {"timestamp": "1562275800", "formatted": "Jul 4, 2019"}, "favorited": true}"#;
+        let (json, media) = orphan_json_with(&db, folder.path(), Some(MEDIA_STATUS_PROCESSED), sidecar).await;
+        let placed = db.target_folder.join("2019/07/IMG_0001.jpg");
+        place(&db, media.unwrap(), &placed, PlacementMode::Move).await;
+
+        db.widget().process_json_file(json).await.unwrap();
+
+        let xmp = fs::read_to_string(xmp_path(&placed, get_xmp_keeps_extension())).await.unwrap();
+        assert!(xmp.contains("xmp:Rating=\"5\""), "{}", xmp);
+    }
+
+    #[tokio::test]
+    async fn applies_a_sidecar_without_a_taken_time() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let sidecar = r#"{"geoData": {"latitude": 48.8584, "longitude": 2.2945}, "favorited": true}"#;
+        let (json, media) = orphan_json_with(&db, folder.path(), Some(MEDIA_STATUS_PROCESSED), sidecar).await;
+        let placed = db.target_folder.join("2019/07/IMG_0001.jpg");
+        place(&db, media.unwrap(), &placed, PlacementMode::Move).await;
+
+        db.widget().process_json_file(json).await.unwrap();
+
+        let xmp = fs::read_to_string(xmp_path(&placed, get_xmp_keeps_extension())).await.unwrap();
+        assert!(xmp.contains("xmp:Rating=\"5\""), "{}", xmp);
+        assert!(xmp.contains("exif:GPSLatitude=\"48,51.504000N\""), "{}", xmp);
+    }
+
+    #[tokio::test]
+    async fn backs_up_an_xmp_that_was_already_there() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let sidecar = r#"{"photoTakenTime": {"timestamp": "1562275800", "formatted": "Jul 4, 2019"}, "favorited": true}"#;
+        let (json, media) = orphan_json_with(&db, folder.path(), Some(MEDIA_STATUS_PROCESSED), sidecar).await;
+        let placed = db.target_folder.join("2019/07/IMG_0001.jpg");
+        let media = place(&db, media.unwrap(), &placed, PlacementMode::Move).await;
+        let existing = xmp_path(&placed, get_xmp_keeps_extension());
+        fs::write(&existing, "edited in darktable").await.unwrap();
+        let widget = db.widget();
+        widget.begin_run();
+
+        widget.clone().process_json_file(json).await.unwrap();
+        let xmp = fs::read_to_string(&existing).await.unwrap();
+        assert!(xmp.contains("xmp:Rating=\"5\""), "{}", xmp);
+
+        widget.undo_operations(UndoScope::LastRun).await.unwrap();
+        assert_eq!(fs::read_to_string(&existing).await.unwrap(), "edited in darktable");
+        assert_eq!(Path::new(&db.reload(&media).await.path), placed);
+    }
+
+    #[tokio::test]
+    async fn edited_path_template_is_used_only_once_valid() {
+        let db = test_db::fresh().await;
+        let widget = db.widget();
+        let template = widget.path_template();
+
+        widget.edit_path_template();
+        assert!(widget.is_editing_path_template());
+        widget.type_path_template(Some('/'));
+        widget.type_path_template(Some('{'));
+        widget.apply_path_template();
+        assert!(widget.is_editing_path_template());
+        assert_eq!(widget.path_template(), template);
+
+        widget.type_path_template(None);
+        widget.type_path_template(None);
+        for c in "/{camera_make|unknown}".chars() {
+            widget.type_path_template(Some(c));
+        }
+        widget.apply_path_template();
+        assert!(!widget.is_editing_path_template());
+        assert_eq!(widget.path_template(), format!("{}/{{camera_make|unknown}}", template));
+        assert!(widget.get_read_state().status_line.ends_with("Google"), "{}", widget.get_read_state().status_line);
+
+        widget.edit_path_template();
+        widget.type_path_template(Some('x'));
+        widget.cancel_path_template();
+        assert!(!widget.is_editing_path_template());
+        assert_eq!(widget.path_template(), format!("{}/{{camera_make|unknown}}", template));
+    }
+
+    #[tokio::test]
+    async fn duplicate_extract_is_removed_with_a_way_back() {
+        let db = test_db::fresh().await;
+        let takeout_zip_id = db.takeout().await;
+        let folder = db.target_folder.join("Takeout");
+        fs::create_dir_all(folder.join("Album")).await.unwrap();
+        let widget = db.widget();
+        let mut rows = Vec::new();
+        for path in [folder.join("IMG_0001.jpg"), folder.join("Album/IMG_0001.jpg")] {
+            fs::write(&path, b"same bytes").await.unwrap();
+            let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+            let duplicate_of_id = widget.find_content_duplicate(&name, "abc").await.unwrap();
+            let new_file = NewFileInZip {
+                takeout_zip_id,
+                name,
+                path: path.to_str().unwrap().to_owned(),
+                content_hash: Some("abc".to_owned()),
+                duplicate_of_id,
+                ..Default::default()
+            };
+            let file_in_zip = create_file_in_zip(&db.conn, new_file, true).await.unwrap();
+            widget.remove_duplicate_extract(&file_in_zip).await.unwrap();
+            rows.push(file_in_zip);
+        }
+        let (canonical, duplicate) = (&rows[0], &rows[1]);
+        assert_eq!(canonical.duplicate_of_id, None);
+        assert_eq!(duplicate.duplicate_of_id, Some(canonical.id));
+        assert!(widget.find_content_duplicate("IMG_0001.jpg.json", "abc").await.unwrap().is_none());
+
+        assert!(fs::try_exists(&canonical.path).await.unwrap());
+        assert!(!fs::try_exists(&duplicate.path).await.unwrap());
+        let operations = fetch_operations_to_undo(&db.conn, &OperationFilter::File(duplicate.id)).await.unwrap();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].kind, OPERATION_REMOVE);
+        assert_eq!(operations[0].source.as_deref(), Some(duplicate.path.as_str()));
+        assert_eq!(operations[0].destination.as_deref(), Some(canonical.path.as_str()));
+    }
+
+    /// An extract with the content and archive path a plan knows it by.
+    async fn planned_extract(
+        db: &TestDb,
+        widget: &FileListWidget,
+        folder: &Path,
+        action: PlanAction,
+        destination: Option<PathBuf>,
+    ) -> FileInZipModel {
+        let path = folder.join("IMG_20190704_233000.jpg");
+        fs::write(&path, b"planned bytes").await.unwrap();
+        let new_file = NewFileInZip {
+            takeout_zip_id: db.takeout().await,
+            name: "IMG_20190704_233000.jpg".to_owned(),
+            path: path.to_str().unwrap().to_owned(),
+            archive_path: Some("Takeout/Google Photos/IMG_20190704_233000.jpg".to_owned()),
+            content_hash: Some("abc".to_owned()),
+            ..Default::default()
+        };
+        let media = create_file_in_zip(&db.conn, new_file, true).await.unwrap();
+        widget.follow_plan(PlannedEntries::from(vec![PlanEntry {
+            archive: "takeout-001.zip".to_owned(),
+            archive_path: media.archive_path.clone().unwrap(),
+            content_hash: "abc".to_owned(),
+            size: 13,
+            action,
+            destination,
+            duplicate_of: None,
+            taken_at: None,
+            date_source: None,
+            reason: Some("edited policy".to_owned()),
+        }]));
+        media
+    }
+
+    #[tokio::test]
+    async fn follows_what_the_plan_sets_aside() {
+        for (action, status) in [
+            (PlanAction::Skip, MEDIA_STATUS_SKIPPED),
+            (PlanAction::NoDate, MEDIA_STATUS_NO_DATE),
+            (PlanAction::Duplicate, MEDIA_STATUS_DUPLICATE),
+        ] {
+            let db = test_db::fresh().await;
+            let folder = tempfile::tempdir().unwrap();
+            // The file name has a date, a run of its own would place it.
+            let widget = db.widget();
+            let media = planned_extract(&db, &widget, folder.path(), action, None).await;
+            widget.process_media_file(media.clone()).await.unwrap();
+
+            let media_after = db.reload(&media).await;
+            assert_eq!(media_after.status, status);
+            assert_eq!(media_after.path, media.path);
+            assert!(fs::try_exists(&media.path).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn places_at_the_planned_destination() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let destination = db.target_folder.join("Planned/holiday.jpg");
+        let widget = db.widget();
+        let media = planned_extract(&db, &widget, folder.path(), PlanAction::Place, Some(destination.clone())).await;
+        widget.process_media_file(media).await.unwrap();
+
+        assert_eq!(fs::read(&destination).await.unwrap(), b"planned bytes");
+    }
+
+    #[tokio::test]
+    async fn reports_a_planned_destination_that_is_taken() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let destination = db.target_folder.join("Planned/holiday.jpg");
+        fs::create_dir_all(destination.parent().unwrap()).await.unwrap();
+        fs::write(&destination, b"other bytes").await.unwrap();
+        let widget = db.widget();
+        let media = planned_extract(&db, &widget, folder.path(), PlanAction::Place, Some(destination.clone())).await;
+        widget.process_media_file(media.clone()).await.unwrap();
+
+        let media_after = db.reload(&media).await;
+        assert!(media_after.status.starts_with(MEDIA_STATUS_FAILED), "{}", media_after.status);
+        assert!(media_after.status.contains("diverged from the plan"), "{}", media_after.status);
+        assert_eq!(media_after.path, media.path);
+        assert_eq!(fs::read(&media.path).await.unwrap(), b"planned bytes");
+        assert_eq!(fs::read(&destination).await.unwrap(), b"other bytes");
+        let mut placed = fs::read_dir(destination.parent().unwrap()).await.unwrap();
+        assert!(placed.next_entry().await.unwrap().is_some());
+        assert!(placed.next_entry().await.unwrap().is_none());
+    }
+
+    /// An extract of a real jpeg, with its sidecar when `sidecar` is given.
+    async fn extract_jpeg(db: &TestDb, folder: &Path, name: &str, sidecar: Option<&str>) -> FileInZipModel {
+        let takeout_zip_id = db.takeout().await;
+        if let Some(sidecar) = sidecar {
+            let json_path = folder.join(format!("{}.json", name));
+            fs::write(&json_path, sidecar).await.unwrap();
+            db.extract(takeout_zip_id, json_path.to_str().unwrap(), true).await;
+        }
+        let path = folder.join(name);
+        fs::write(&path, jpeg()).await.unwrap();
+        db.extract(takeout_zip_id, path.to_str().unwrap(), true).await
+    }
+
+    fn writing_everything(widget: &FileListWidget) {
+        widget.get_write_state().metadata_writes = MetadataWrites {
+            exif_dates: true,
+            video_dates: true,
+            exif_gps: true,
+        };
+    }
+
+    #[tokio::test]
+    async fn leaves_a_file_dated_by_its_name_unchanged() {
+        let db = test_db::fresh().await;
+        let widget = db.widget();
+        writing_everything(&widget);
+        let folder = tempfile::tempdir().unwrap();
+        let media = extract_jpeg(&db, folder.path(), "IMG_20190704_233000.jpg", None).await;
+        widget.process_media_file(media.clone()).await.unwrap();
+
+        let media_after = db.reload(&media).await;
+        assert_eq!(media_after.status, MEDIA_STATUS_PROCESSED);
+        assert_ne!(media_after.path, media.path);
+        assert_eq!(fs::read(&media_after.path).await.unwrap(), jpeg());
+    }
+
+    #[tokio::test]
+    async fn writes_the_sidecar_date_into_the_file() {
+        let db = test_db::fresh().await;
+        let widget = db.widget();
+        writing_everything(&widget);
+        let folder = tempfile::tempdir().unwrap();
+        let media = extract_jpeg(&db, folder.path(), "IMG_0001.jpg", Some(SIDECAR)).await;
+        widget.process_media_file(media.clone()).await.unwrap();
+
+        let media_after = db.reload(&media).await;
+        assert_eq!(media_after.status, MEDIA_STATUS_PROCESSED);
+        assert_ne!(fs::read(&media_after.path).await.unwrap(), jpeg());
+    }
+
+    #[tokio::test]
+    async fn leaves_a_video_dated_by_the_archive_unchanged() {
+        let db = test_db::fresh().await;
+        let widget = db.widget();
+        writing_everything(&widget);
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("clip.mp4");
+        fs::write(&path, movie()).await.unwrap();
+        let new_file = NewFileInZip {
+            takeout_zip_id: db.takeout().await,
+            name: "clip.mp4".to_owned(),
+            path: path.to_str().unwrap().to_owned(),
+            archive_mtime: Some(1_562_275_800),
+            ..Default::default()
+        };
+        let media = create_file_in_zip(&db.conn, new_file, true).await.unwrap();
+        widget.process_media_file(media.clone()).await.unwrap();
+
+        let media_after = db.reload(&media).await;
+        assert_eq!(media_after.status, MEDIA_STATUS_PROCESSED);
+        let catalog = db.catalog().await;
+        assert_eq!(catalog[0].date_source.as_deref(), Some(DateSource::ArchiveMtime.as_str()));
+        assert_eq!(fs::read(&media_after.path).await.unwrap(), movie());
+    }
+
+    /// A gzipped tar holding `entries`, named like a downloaded takeout.
+    async fn takeout_archive(folder: &Path, entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = folder.join("takeout-001.tgz");
+        let encoder = GzipEncoder::new(TokioFile::create(&path).await.unwrap());
+        let mut builder = tokio_tar::Builder::new(encoder);
+        for (name, data) in entries {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).await.unwrap();
+        }
+        let mut encoder = builder.into_inner().await.unwrap();
+        encoder.shutdown().await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn examines_entries_without_an_extension() {
+        let db = test_db::fresh().await;
+        let folder = tempfile::tempdir().unwrap();
+        let local_path = takeout_archive(folder.path(), &[
+            ("Takeout/README", b"Your Google data"),
+            ("Takeout/Google Photos/2019/IMG_0001.jpg", &jpeg()),
+        ]).await;
+        db.takeout().await;
+        let mut takeout_zip = list_takeouts(&db.conn).await.unwrap().remove(0);
+        takeout_zip.local_path = local_path.to_str().unwrap().to_owned();
+
+        db.widget().examine_zip_with_progress(takeout_zip).await.unwrap();
+
+        let files = entity::file_in_zip::Entity::find().all(&db.conn).await.unwrap();
+        let readme = files.iter().find(|file| file.name == "README").unwrap();
+        assert_eq!(readme.extension, "");
+        assert!(files.iter().any(|file| file.name == "IMG_0001.jpg"));
+    }
+}
@@ -1,6 +1,6 @@
 use ratatui::layout::{Alignment, Constraint, Layout, Rect};
 use ratatui::buffer::Buffer;
-use ratatui::widgets::{Block, Borders, HighlightSpacing, Padding, Paragraph, Table, Wrap};
+use ratatui::widgets::{Block, Borders, HighlightSpacing, Padding, Paragraph, Row, Table, Wrap};
 use ratatui::prelude::{Color, Line, Modifier, StatefulWidget, Style, Stylize, Widget};
 use ratatui::style::palette::tailwind::SLATE;
 use ratatui::style::palette::material::BLUE;
@@ -24,6 +24,9 @@ impl Widget for &mut FileListWidget {
             FileListWidgetViewState::Processing => {
                 self.render_processing_view(area, buf);
             }
+            FileListWidgetViewState::NearDuplicates => {
+                self.render_near_duplicates_view(area, buf);
+            }
         }
         let mut state = self.get_write_state();
         state.progress_count += 1;
@@ -42,8 +45,18 @@ pub fn render_file_footer(area: Rect, buf: &mut Buffer) {
     .render(area, buf);
 }
 
-fn render_processing_footer(area: Rect, buf: &mut Buffer) {
-    Paragraph::new("Use ↓↑ to move, Enter to select, s to store to db\n, f for files, q to quit")
+fn render_processing_footer(area: Rect, buf: &mut Buffer, template_input: Option<&str>) {
+    let footer = match template_input {
+        Some(template_input) => format!("Path template: {}▏ Enter to use it, Esc to keep the current one", template_input),
+        None => "Use ↓↑ to move, Enter to select, s to store to db\n, a to export albums, b to backfill the catalog, t to edit the path template, d to report duplicates, n to review near duplicates, e to export for a photo server, u to undo the selected takeout's last run, U the last run, f for files, q to quit".to_owned(),
+    };
+    Paragraph::new(footer)
+        .centered()
+        .render(area, buf);
+}
+
+fn render_near_duplicates_footer(area: Rect, buf: &mut Buffer) {
+    Paragraph::new("Use ↓↑ to move, Enter to keep the selected copy and put the rest of its cluster aside, u to undo the selected file, U the last run, v to go back, q to quit")
         .centered()
         .render(area, buf);
 }
@@ -103,7 +116,7 @@ impl FileListWidget {
         let info =
             entries
                 .iter()
-                .fold(String::new(), |mut acc, (key, (task, progress))| {
+                .fold(state.status_line.clone(), |mut acc, (key, (task, progress))| {
                     acc = format!("{}\n{}: {}, {:.2}%", acc, task, key, progress * 100.0);
                     acc
                 });
@@ -190,6 +203,36 @@ impl FileListWidget {
         StatefulWidget::render(table, area, buf, &mut state.table_state);
     }
 
+    fn render_near_duplicates_area(&mut self, area: Rect, buf: &mut Buffer) {
+        let mut state = self.get_write_state();
+
+        let block = Block::bordered()
+            .title("Near duplicates, pick the copy to keep")
+            .title_alignment(Alignment::Center);
+
+        let header = Row::new(vec!["Cluster", "File Name", "Dimensions", "Size", "Type", "Distance", "Place", "Path"])
+            .style(TODO_HEADER_STYLE);
+        let rows = state.near_duplicates.iter();
+        let widths = [
+            Constraint::Percentage(7),
+            Constraint::Percentage(18),
+            Constraint::Percentage(9),
+            Constraint::Percentage(8),
+            Constraint::Percentage(9),
+            Constraint::Percentage(7),
+            Constraint::Percentage(14),
+            Constraint::Percentage(28),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(block)
+            .highlight_spacing(HighlightSpacing::Always)
+            .highlight_symbol(">>")
+            .row_highlight_style(SELECTED_STYLE);
+
+        StatefulWidget::render(table, area, buf, &mut state.table_state);
+    }
+
     pub fn render_processing_view(&mut self, area: Rect, buf: &mut Buffer) {
         let [header_area, main_area, footer_area] = Layout::vertical([
             Constraint::Length(2),
@@ -202,11 +245,29 @@ impl FileListWidget {
             Layout::vertical([Constraint::Fill(1), Constraint::Length(20)]).areas(main_area);
 
         render_header(header_area, buf);
-        render_processing_footer(footer_area, buf);
+        let template_input = self.get_read_state().template_input.clone();
+        render_processing_footer(footer_area, buf, template_input.as_deref());
         self.render_processing_area(list_area, buf);
         self.render_status(status_area, buf);
     }
 
+    pub fn render_near_duplicates_view(&mut self, area: Rect, buf: &mut Buffer) {
+        let [header_area, main_area, footer_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+            .areas(area);
+
+        let [list_area, status_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(20)]).areas(main_area);
+
+        render_header(header_area, buf);
+        render_near_duplicates_footer(footer_area, buf);
+        self.render_near_duplicates_area(list_area, buf);
+        self.render_status(status_area, buf);
+    }
+
     pub fn render_file_view(&mut self, area: Rect, buf: &mut Buffer) {
         let [header_area, main_area, footer_area] = Layout::vertical([
             Constraint::Length(2),
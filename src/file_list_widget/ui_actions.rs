@@ -1,5 +1,6 @@
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use crate::app::{App, AppResult};
+use crate::file_list_widget::journal::UndoScope;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum UiActions {
@@ -9,11 +10,34 @@ pub enum UiActions {
     #[default]
     SelectItem,
     SwitchView,
+    ExportAlbums,
+    BackfillCatalog,
+    EditPathTemplate,
+    TypePathTemplate(char),
+    ErasePathTemplate,
+    ApplyPathTemplate,
+    CancelPathTemplate,
+    ReportDuplicates,
+    ReviewNearDuplicates,
+    ExportLibrary,
+    Undo(UndoScope),
     Quit,
 }
 
 /// Handles the key events and updates the state of [`App`].
 pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+    // While the path template is edited, keys go to it.
+    if app.file_list_widget.is_editing_path_template() {
+        let action = match key_event.code {
+            KeyCode::Esc => UiActions::CancelPathTemplate,
+            KeyCode::Enter => UiActions::ApplyPathTemplate,
+            KeyCode::Backspace => UiActions::ErasePathTemplate,
+            KeyCode::Char(c) => UiActions::TypePathTemplate(c),
+            _ => return Ok(()),
+        };
+        app.file_list_widget.handle_action(action);
+        return Ok(());
+    }
     match key_event.code {
         // Exit application on `ESC` or `q`
         KeyCode::Esc | KeyCode::Char('q') => {
@@ -39,6 +63,30 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
         KeyCode::Char('v') | KeyCode::Char('V') => {
             app.file_list_widget.handle_action(UiActions::SwitchView);
         }
+        KeyCode::Char('a') | KeyCode::Char('A') => {
+            app.file_list_widget.handle_action(UiActions::ExportAlbums);
+        }
+        KeyCode::Char('b') | KeyCode::Char('B') => {
+            app.file_list_widget.handle_action(UiActions::BackfillCatalog);
+        }
+        KeyCode::Char('t') | KeyCode::Char('T') => {
+            app.file_list_widget.handle_action(UiActions::EditPathTemplate);
+        }
+        KeyCode::Char('d') | KeyCode::Char('D') => {
+            app.file_list_widget.handle_action(UiActions::ReportDuplicates);
+        }
+        KeyCode::Char('n') | KeyCode::Char('N') => {
+            app.file_list_widget.handle_action(UiActions::ReviewNearDuplicates);
+        }
+        KeyCode::Char('e') | KeyCode::Char('E') => {
+            app.file_list_widget.handle_action(UiActions::ExportLibrary);
+        }
+        KeyCode::Char('u') => {
+            app.file_list_widget.handle_action(UiActions::Undo(UndoScope::Selection));
+        }
+        KeyCode::Char('U') => {
+            app.file_list_widget.handle_action(UiActions::Undo(UndoScope::LastRun));
+        }
         // Other handlers you could add here.
         _ => {}
     }
@@ -0,0 +1,89 @@
+use crate::file_list_widget::GeoData;
+use isocountry::CountryCode;
+use reverse_geocoder::ReverseGeocoder;
+use std::sync::LazyLock;
+
+/// The GeoNames places with more than 1000 inhabitants compiled into the
+/// binary, loaded on first use.
+static GEOCODER: LazyLock<ReverseGeocoder> = LazyLock::new(ReverseGeocoder::new);
+
+/// Locations further than this from any place, out at sea or deep in the
+/// wilderness, are not named after the nearest one.
+const MAX_PLACE_DISTANCE_KM: f64 = 100.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Where a photo was taken, named after the nearest place in the gazetteer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Place {
+    /// ISO 3166 alpha-2 code, `IT`.
+    pub country_code: String,
+    /// ISO 3166 short name, or the code for countries ISO does not list.
+    pub country: String,
+    /// First-level administrative division, a state or region.
+    pub region: Option<String>,
+    pub city: String,
+}
+
+/// Names the place a location lies in, without going online.
+pub fn place_at(location: GeoData) -> Option<Place> {
+    if !location.is_known() {
+        return None;
+    }
+    let nearest = GEOCODER.search((location.latitude, location.longitude));
+    // The search measures the straight line between points on the unit sphere.
+    let chord = nearest.distance.sqrt();
+    if 2.0 * EARTH_RADIUS_KM * (chord / 2.0).min(1.0).asin() > MAX_PLACE_DISTANCE_KM {
+        return None;
+    }
+    let record = nearest.record;
+    let country = CountryCode::for_alpha2(&record.cc)
+        .map(|country| country.name().to_owned())
+        .unwrap_or_else(|_| record.cc.clone());
+    Some(Place {
+        country_code: record.cc.clone(),
+        country,
+        region: Some(record.admin1.clone()).filter(|region| !region.is_empty()),
+        city: record.name.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(latitude: f64, longitude: f64) -> GeoData {
+        GeoData {
+            latitude,
+            longitude,
+            altitude: 0.0,
+        }
+    }
+
+    #[test]
+    fn names_the_nearest_place() {
+        assert_eq!(
+            place_at(at(43.7696, 11.2558)),
+            Some(Place {
+                country_code: "IT".to_owned(),
+                country: "Italy".to_owned(),
+                region: Some("Tuscany".to_owned()),
+                city: "Florence".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn names_no_place_out_at_sea() {
+        assert_eq!(place_at(at(-30.0, -120.0)), None);
+        assert_eq!(place_at(at(0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn keeps_the_code_of_a_country_iso_does_not_list() {
+        let place = place_at(at(42.6629, 21.1655)).unwrap();
+        assert_eq!(place.city, "Pristina");
+        assert_eq!(place.country_code, "XK");
+        assert_eq!(place.country, "XK");
+    }
+}
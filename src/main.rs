@@ -4,7 +4,17 @@ mod tui;
 pub(crate) mod drive;
 mod ui;
 pub(crate) mod db;
+mod date_resolver;
+mod geocode;
 mod media_utils;
+mod metadata_writer;
+mod path_template;
+mod perceptual_hash;
+mod placement;
+mod plan;
+mod settings;
+mod timezone;
+mod xmp;
 mod file_list_widget;
 
 use std::io;
@@ -15,13 +25,17 @@ use ratatui::Terminal;
 use crate::event::{Event, EventHandler};
 use file_list_widget::ui_actions::handle_key_events;
 use crate::tui::Tui;
+use crate::db::get_db_connection;
+use crate::drive::get_target_folder;
+use file_list_widget::FileListWidget;
 
 #[tokio::main]
 async fn main() -> AppResult<()> {
     // Create an application.
     dotenv().ok();
     
-    let mut app = App::new();
+    let db = get_db_connection().await?;
+    let mut app = App::new(FileListWidget::new(db, get_target_folder()));
 
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(io::stdout());
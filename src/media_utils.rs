@@ -1,22 +1,39 @@
 use anyhow::Result;
+use crate::db::{
+    FILE_TYPE_ACCOUNT_METADATA, FILE_TYPE_ALBUM_METADATA, FILE_TYPE_OTHER, FILE_TYPE_SIDECAR,
+};
+use crate::settings::FileTimesPolicy;
 use chrono::{DateTime, Utc};
-use nom_exif::{ExifIter, ExifTag, MediaParser, MediaSource, TrackInfo, TrackInfoTag};
+use filetime::FileTime;
+use nom_exif::{EntryValue, Exif, ExifIter, ExifTag, MediaParser, MediaSource, TrackInfo, TrackInfoTag};
+use sha2::{Digest, Sha256};
+use std::io::Read;
 use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-#[allow(dead_code)]
 pub async fn rexif_get_taken_date<P: AsRef<Path>>(path: P) -> Result<Option<DateTime<Utc>>> {
     if path.as_ref().is_file() {
-        if path.as_ref().extension().unwrap() == "png" {
+        if path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")) {
             return Ok(None);
         }
         let mut parser = MediaParser::new();
         let ms = MediaSource::file_path(path)?;
         let r = if ms.has_exif() {
             let iter: ExifIter = parser.parse(ms)?;
-            iter.into_iter()
-            .find(|x| x.tag_code() == ExifTag::CreateDate.code())
-                .and_then(|mut x| x.take_value()
-                    .and_then(|v| v.as_time_components().map(|(t,_)| t.and_utc())))
+            // When the shutter fired, rather than when the file was written.
+            let (mut date_time_original, mut create_date) = (None, None);
+            for mut entry in iter {
+                let date = if entry.tag_code() == ExifTag::DateTimeOriginal.code() {
+                    &mut date_time_original
+                } else if entry.tag_code() == ExifTag::CreateDate.code() {
+                    &mut create_date
+                } else {
+                    continue;
+                };
+                *date = entry.take_value()
+                    .and_then(|v| v.as_time_components().map(|(t,_)| t.and_utc()));
+            }
+            date_time_original.or(create_date)
         } else {
             let info: TrackInfo = parser.parse(ms)?;
             info.get(TrackInfoTag::CreateDate)
@@ -26,4 +43,385 @@ pub async fn rexif_get_taken_date<P: AsRef<Path>>(path: P) -> Result<Option<Date
     } else {
         Ok(None)
     }
-}
\ No newline at end of file
+}
+
+/// Suffixes Google Photos appends to edited copies, in the languages Takeout
+/// is known to export in.
+pub const EDIT_SUFFIXES: [&str; 10] = [
+    "-edited",
+    "-bearbeitet",
+    "-modifié",
+    "-editado",
+    "-modificato",
+    "-bewerkt",
+    "-redigerad",
+    "-redigert",
+    "-muokattu",
+    "-edytowane",
+];
+
+/// Strips a known edit suffix from a path without extension, returning the
+/// path of the original it was made from.
+pub fn strip_edit_suffix(path_no_ext: &str) -> Option<&str> {
+    EDIT_SUFFIXES
+        .iter()
+        .find_map(|suffix| path_no_ext.strip_suffix(suffix))
+}
+
+/// Extensions of the video halves of Live Photos and Motion Photos.
+pub const VIDEO_EXTENSIONS: [&str; 5] = ["mov", "mp4", "m4v", "mp", "3gp"];
+
+pub fn is_video_extension(extension: &str) -> bool {
+    VIDEO_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+/// Per-album descriptions, named after the export language.
+pub const ALBUM_METADATA_NAMES: [&str; 6] = [
+    "metadata.json",
+    "metadaten.json",
+    "métadonnées.json",
+    "metadatos.json",
+    "metadati.json",
+    "metadane.json",
+];
+
+/// Files describing the whole account rather than any single photo.
+pub const ACCOUNT_METADATA_NAMES: [&str; 4] = [
+    "print-subscriptions.json",
+    "shared_album_comments.json",
+    "user-generated-memory-titles.json",
+    "archive_browser.json",
+];
+
+/// Tells photo sidecars apart from the other json files found in a Takeout.
+pub fn classify_json(name: &str) -> &'static str {
+    let name = name.to_lowercase();
+    if ALBUM_METADATA_NAMES.contains(&name.as_str()) {
+        return FILE_TYPE_ALBUM_METADATA;
+    }
+    if ACCOUNT_METADATA_NAMES.contains(&name.as_str()) {
+        return FILE_TYPE_ACCOUNT_METADATA;
+    }
+    // Sidecars are named after the media file, extension included
+    // (`IMG_1234.jpg.json`, `IMG_1234.jpg.supplemental-metadata.json`). Long
+    // names are cut short, possibly before the extension, so only a short name
+    // without one cannot be a sidecar.
+    let is_unrelated = name
+        .strip_suffix(".json")
+        .is_some_and(|stem| !stem.contains('.') && !is_truncated_sidecar(&name));
+    if is_unrelated {
+        FILE_TYPE_OTHER
+    } else {
+        FILE_TYPE_SIDECAR
+    }
+}
+
+/// Sidecar names at least this long may have been cut short by Takeout.
+const TRUNCATED_SIDECAR_MIN_CHARS: usize = 46;
+
+/// Whether a sidecar name was cut short before the media extension
+/// (`Screenshot_20190101-123456_Some_long_app_na.json`), so that it only holds
+/// the start of the media name.
+pub fn is_truncated_sidecar(name: &str) -> bool {
+    let lowercase = name.to_lowercase();
+    match lowercase.strip_suffix(".json") {
+        Some(stem) => !stem.contains('.') && name.chars().count() >= TRUNCATED_SIDECAR_MIN_CHARS,
+        None => false,
+    }
+}
+
+/// Prefixes of the per-year folders (`Photos from 2019`) in the export languages.
+pub const YEAR_FOLDER_PREFIXES: [&str; 7] = [
+    "photos from",
+    "fotos von",
+    "fotos aus",
+    "photos de",
+    "fotos de",
+    "foto dal",
+    "foto's uit",
+];
+
+/// Whether a Takeout folder is one of the per-year folders rather than an album.
+pub fn is_year_folder(folder_name: &str) -> bool {
+    let folder_name = folder_name.to_lowercase();
+    match folder_name.rsplit_once(' ') {
+        Some((prefix, year)) => {
+            year.len() == 4
+                && year.chars().all(|c| c.is_ascii_digit())
+                && YEAR_FOLDER_PREFIXES.contains(&prefix)
+        }
+        None => false,
+    }
+}
+
+/// Sets the file times `policy` asks for, so that apps sorting by file date see
+/// when the photo was taken rather than when it was extracted.
+pub async fn set_file_times<P: AsRef<Path>>(
+    path: P,
+    taken: DateTime<Utc>,
+    policy: FileTimesPolicy,
+) -> Result<()> {
+    let path = path.as_ref().to_path_buf();
+    let time = FileTime::from_unix_time(taken.timestamp(), taken.timestamp_subsec_nanos());
+    tokio::task::spawn_blocking(move || match policy {
+        FileTimesPolicy::None => Ok(()),
+        FileTimesPolicy::Modified => filetime::set_file_mtime(&path, time),
+        FileTimesPolicy::ModifiedAndAccessed => filetime::set_file_times(&path, time, time),
+    })
+    .await??;
+    Ok(())
+}
+
+/// Camera and dimensions as the file itself records them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaDetails {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+pub async fn read_media_details<P: AsRef<Path>>(path: P) -> Result<MediaDetails> {
+    let extension = path.as_ref().extension().and_then(|extension| extension.to_str());
+    if !path.as_ref().is_file() || extension.is_some_and(|extension| extension.eq_ignore_ascii_case("png")) {
+        return Ok(MediaDetails::default());
+    }
+    let text = |value: Option<&EntryValue>| {
+        value
+            .and_then(|value| value.as_str())
+            .map(|value| value.trim_end_matches('\0').trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let dimension = |value: Option<&EntryValue>| {
+        value.and_then(|value| value.as_u32().or(value.as_u16().map(u32::from)))
+    };
+    let mut parser = MediaParser::new();
+    let ms = MediaSource::file_path(path)?;
+    let details = if ms.has_exif() {
+        let exif: Exif = parser.parse::<_, _, ExifIter>(ms)?.into();
+        MediaDetails {
+            camera_make: text(exif.get(ExifTag::Make)),
+            camera_model: text(exif.get(ExifTag::Model)),
+            width: dimension(exif.get(ExifTag::ExifImageWidth))
+                .or(dimension(exif.get(ExifTag::ImageWidth))),
+            height: dimension(exif.get(ExifTag::ExifImageHeight))
+                .or(dimension(exif.get(ExifTag::ImageHeight))),
+        }
+    } else {
+        let info: TrackInfo = parser.parse(ms)?;
+        MediaDetails {
+            camera_make: text(info.get(TrackInfoTag::Make)),
+            camera_model: text(info.get(TrackInfoTag::Model)),
+            width: dimension(info.get(TrackInfoTag::ImageWidth)),
+            height: dimension(info.get(TrackInfoTag::ImageHeight)),
+        }
+    };
+    Ok(details)
+}
+
+/// Mime types of the formats Google Photos accepts.
+pub fn mime_type(extension: &str) -> Option<&'static str> {
+    Some(match extension.to_lowercase().as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "dng" => "image/x-adobe-dng",
+        "cr2" => "image/x-canon-cr2",
+        "cr3" => "image/x-canon-cr3",
+        "nef" => "image/x-nikon-nef",
+        "arw" => "image/x-sony-arw",
+        "orf" => "image/x-olympus-orf",
+        "raf" => "image/x-fuji-raf",
+        "rw2" => "image/x-panasonic-rw2",
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "3gp" => "video/3gpp",
+        "avi" => "video/x-msvideo",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "mpg" | "mpeg" => "video/mpeg",
+        "wmv" => "video/x-ms-wmv",
+        "mts" | "m2ts" => "video/mp2t",
+        _ => return None,
+    })
+}
+
+/// `video` or `photo`, for paths and catalogs that keep the two apart.
+pub fn media_type(extension: &str) -> &'static str {
+    let is_video = is_video_extension(extension)
+        || mime_type(extension).is_some_and(|mime_type| mime_type.starts_with("video/"));
+    if is_video { "video" } else { "photo" }
+}
+
+/// SHA-256 of the file contents, hex encoded.
+pub async fn content_hash<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref().to_path_buf();
+    Ok(tokio::task::spawn_blocking(move || hash_file(&path)).await??)
+}
+
+/// The blocking half of [`content_hash`].
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Copies `reader` into `writer` and hashes the bytes on the way, so a file
+/// extracted from an archive is never read twice. Returns the hash in the same
+/// form as [`content_hash`] and the number of bytes copied.
+pub async fn copy_and_hash<R, W>(reader: &mut R, writer: &mut W) -> std::io::Result<(String, u64)>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read]).await?;
+        size += read as u64;
+    }
+    writer.flush().await?;
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// Album titles and other free text used in paths, kept from escaping the
+/// folder they are written to.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_json_files() {
+        let cases = [
+            ("IMG_1234.jpg.json", FILE_TYPE_SIDECAR),
+            ("IMG_1234.jpg.supplemental-metadata.json", FILE_TYPE_SIDECAR),
+            ("IMG_1234.jpg.supplemental-me.json", FILE_TYPE_SIDECAR),
+            ("Screenshot_20190101-123456_Some_long_app_na.json", FILE_TYPE_SIDECAR),
+            ("metadata.json", FILE_TYPE_ALBUM_METADATA),
+            ("Métadonnées.json", FILE_TYPE_ALBUM_METADATA),
+            ("print-subscriptions.json", FILE_TYPE_ACCOUNT_METADATA),
+            ("IMG_1234.j.json", FILE_TYPE_SIDECAR),
+            ("IMG_1234.jpg(1).json", FILE_TYPE_SIDECAR),
+            ("settings.json", FILE_TYPE_OTHER),
+            ("Labels.json", FILE_TYPE_OTHER),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(classify_json(name), expected, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn copies_and_hashes_in_one_pass() {
+        let folder = tempfile::tempdir().unwrap();
+        // Past one buffer, and nothing at all.
+        for bytes in [(0..200_000u32).map(|i| i as u8).collect::<Vec<_>>(), Vec::new()] {
+            let path = folder.path().join("copy.bin");
+            let mut output = tokio::fs::File::create(&path).await.unwrap();
+            let (hash, size) = copy_and_hash(&mut bytes.as_slice(), &mut output).await.unwrap();
+            assert_eq!(size, bytes.len() as u64);
+            assert_eq!(tokio::fs::read(&path).await.unwrap(), bytes);
+            assert_eq!(hash, content_hash(&path).await.unwrap());
+        }
+        let (hash, _) = copy_and_hash(&mut b"abc".as_slice(), &mut Vec::new()).await.unwrap();
+        assert_eq!(hash, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[tokio::test]
+    async fn sets_the_file_times_each_policy_asks_for() {
+        let folder = tempfile::tempdir().unwrap();
+        let taken = DateTime::parse_from_rfc3339("2019-07-01T12:30:00.25Z").unwrap().to_utc();
+        let taken_time = FileTime::from_unix_time(taken.timestamp(), taken.timestamp_subsec_nanos());
+        let extracted = FileTime::from_unix_time(1_700_000_000, 0);
+        let cases = [
+            (FileTimesPolicy::None, extracted, extracted),
+            (FileTimesPolicy::Modified, taken_time, extracted),
+            (FileTimesPolicy::ModifiedAndAccessed, taken_time, taken_time),
+        ];
+        for (policy, mtime, atime) in cases {
+            let path = folder.path().join(format!("{policy:?}.jpg"));
+            std::fs::write(&path, b"jpeg").unwrap();
+            filetime::set_file_times(&path, extracted, extracted).unwrap();
+
+            set_file_times(&path, taken, policy).await.unwrap();
+
+            let metadata = std::fs::metadata(&path).unwrap();
+            assert_eq!(FileTime::from_last_modification_time(&metadata), mtime, "{policy:?}");
+            assert_eq!(FileTime::from_last_access_time(&metadata), atime, "{policy:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn prefers_date_time_original_over_create_date() {
+        let folder = tempfile::tempdir().unwrap();
+        let written = folder.path().join("IMG_0001.jpg");
+        std::fs::write(&written, crate::metadata_writer::tests::jpeg()).unwrap();
+        let mut metadata = little_exif::metadata::Metadata::new();
+        metadata.set_tag(little_exif::exif_tag::ExifTag::CreateDate("2020:01:02 08:00:00".to_owned()));
+        metadata.set_tag(little_exif::exif_tag::ExifTag::DateTimeOriginal("2019:07:01 12:30:00".to_owned()));
+        metadata.write_to_file(&written).unwrap();
+        // Renamed by hand, it has no extension left.
+        let path = folder.path().join("IMG_0001");
+        std::fs::rename(&written, &path).unwrap();
+
+        let taken = rexif_get_taken_date(&path).await.unwrap();
+
+        assert_eq!(taken, DateTime::parse_from_rfc3339("2019-07-01T12:30:00Z").ok().map(|taken| taken.to_utc()));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_create_date() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("IMG_0001.jpg");
+        std::fs::write(&path, crate::metadata_writer::tests::jpeg()).unwrap();
+        let mut metadata = little_exif::metadata::Metadata::new();
+        metadata.set_tag(little_exif::exif_tag::ExifTag::CreateDate("2020:01:02 08:00:00".to_owned()));
+        metadata.write_to_file(&path).unwrap();
+
+        let taken = rexif_get_taken_date(&path).await.unwrap();
+
+        assert_eq!(taken, DateTime::parse_from_rfc3339("2020-01-02T08:00:00Z").ok().map(|taken| taken.to_utc()));
+    }
+
+    #[test]
+    fn tells_truncated_sidecar_names() {
+        assert!(is_truncated_sidecar("Screenshot_20190101-123456_Some_long_app_na.json"));
+        assert!(!is_truncated_sidecar("Screenshot_20190101-123456_Some_long_app.j.json"));
+        assert!(!is_truncated_sidecar("IMG_1234.json"));
+        assert!(!is_truncated_sidecar("Screenshot_20190101-123456_Some_long_app_name.jpg"));
+    }
+}
@@ -0,0 +1,498 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeDelta, Utc};
+use little_exif::exif_tag::ExifTag;
+use little_exif::metadata::Metadata;
+use little_exif::rational::uR64;
+use crate::file_list_widget::GeoData;
+use crate::media_utils::rexif_get_taken_date;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const EXIF_DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+/// Formats whose EXIF we rewrite in place. Only the metadata segment is
+/// replaced, the image data is never re-encoded.
+pub const EXIF_WRITABLE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "heic", "heif"];
+
+pub fn is_exif_writable(extension: &str) -> bool {
+    EXIF_WRITABLE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+/// The date tags a file already carries.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExifDates {
+    pub date_time_original: Option<NaiveDateTime>,
+    pub create_date: Option<NaiveDateTime>,
+    pub offset: Option<FixedOffset>,
+}
+
+impl ExifDates {
+    fn read(metadata: &Metadata) -> Self {
+        let string_tag = |tag: ExifTag| {
+            metadata.get_tag(&tag).find_map(|tag| match tag {
+                ExifTag::DateTimeOriginal(value)
+                | ExifTag::CreateDate(value)
+                | ExifTag::OffsetTimeOriginal(value) => {
+                    Some(value.trim_end_matches('\0').trim().to_string())
+                }
+                _ => None,
+            })
+        };
+        let date = |tag: ExifTag| {
+            string_tag(tag).and_then(|value| {
+                NaiveDateTime::parse_from_str(&value, EXIF_DATE_FORMAT).ok()
+            })
+        };
+        Self {
+            date_time_original: date(ExifTag::DateTimeOriginal(String::new())),
+            create_date: date(ExifTag::CreateDate(String::new())),
+            offset: string_tag(ExifTag::OffsetTimeOriginal(String::new()))
+                .and_then(|value| value.parse().ok()),
+        }
+    }
+}
+
+/// Reads `OffsetTimeOriginal`, the offset the camera clock was set to.
+pub async fn read_exif_offset<P: AsRef<Path>>(path: P) -> Result<Option<FixedOffset>> {
+    let path: PathBuf = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        Ok(Metadata::new_from_path(&path)
+            .ok()
+            .and_then(|metadata| ExifDates::read(&metadata).offset))
+    })
+    .await?
+}
+
+/// Works out the local time and offset the date tags should hold for a photo
+/// taken at `taken`, or `None` when the tags already agree with it.
+///
+/// An existing offset is kept. A present `DateTimeOriginal` without an offset
+/// is trusted as camera local time when it is a whole quarter hour and at most
+/// 14 hours away from `taken`, and the offset is derived from the difference.
+/// Otherwise the offset `taken` was resolved in is written.
+pub fn plan_exif_dates(
+    existing: &ExifDates,
+    taken: DateTime<FixedOffset>,
+) -> Option<(NaiveDateTime, FixedOffset)> {
+    let offset = existing
+        .offset
+        .or_else(|| {
+            existing
+                .date_time_original
+                .and_then(|local| infer_offset(local, taken.to_utc()))
+        })
+        .unwrap_or(*taken.offset());
+    let local = taken.with_timezone(&offset).naive_local();
+    let agrees = existing.date_time_original == Some(local)
+        && existing.create_date == Some(local)
+        && existing.offset == Some(offset);
+    if agrees { None } else { Some((local, offset)) }
+}
+
+fn infer_offset(local: NaiveDateTime, taken: DateTime<Utc>) -> Option<FixedOffset> {
+    let difference = local - taken.naive_utc();
+    if difference.abs() > TimeDelta::hours(14) || difference.num_seconds() % (15 * 60) != 0 {
+        return None;
+    }
+    FixedOffset::east_opt(difference.num_seconds() as i32)
+}
+
+/// Writes `DateTimeOriginal`, `CreateDate` and `OffsetTimeOriginal` from the
+/// taken time, in the offset it was resolved in, when they are missing or
+/// disagree with it. Returns whether the file was changed.
+pub async fn write_exif_taken_date<P: AsRef<Path>>(path: P, taken: DateTime<FixedOffset>) -> Result<bool> {
+    let path: PathBuf = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        // Takeout happily hands out jpegs without any EXIF at all.
+        let mut metadata = Metadata::new_from_path(&path).unwrap_or_else(|_| Metadata::new());
+        let existing = ExifDates::read(&metadata);
+        match plan_exif_dates(&existing, taken) {
+            Some((local, offset)) => {
+                let local = local.format(EXIF_DATE_FORMAT).to_string();
+                metadata.set_tag(ExifTag::DateTimeOriginal(local.clone()));
+                metadata.set_tag(ExifTag::CreateDate(local));
+                metadata.set_tag(ExifTag::OffsetTimeOriginal(offset.to_string()));
+                metadata.write_to_file(&path)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    })
+    .await?
+}
+
+/// Writes the GPS IFD from the sidecar location, unless the file already has a
+/// position of its own. Returns whether the file was changed.
+pub async fn write_exif_location<P: AsRef<Path>>(path: P, location: GeoData) -> Result<bool> {
+    let path: PathBuf = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut metadata = Metadata::new_from_path(&path).unwrap_or_else(|_| Metadata::new());
+        if metadata.get_tag(&ExifTag::GPSLatitude(Vec::new())).next().is_some() {
+            return Ok(false);
+        }
+        let latitude_ref = if location.latitude < 0.0 { "S" } else { "N" };
+        let longitude_ref = if location.longitude < 0.0 { "W" } else { "E" };
+        let altitude_ref = if location.altitude < 0.0 { 1 } else { 0 };
+        metadata.set_tag(ExifTag::GPSVersionID(vec![2, 3, 0, 0]));
+        metadata.set_tag(ExifTag::GPSLatitudeRef(latitude_ref.to_string()));
+        metadata.set_tag(ExifTag::GPSLatitude(to_degrees_minutes_seconds(location.latitude)));
+        metadata.set_tag(ExifTag::GPSLongitudeRef(longitude_ref.to_string()));
+        metadata.set_tag(ExifTag::GPSLongitude(to_degrees_minutes_seconds(location.longitude)));
+        metadata.set_tag(ExifTag::GPSAltitudeRef(vec![altitude_ref]));
+        metadata.set_tag(ExifTag::GPSAltitude(vec![to_rational(location.altitude.abs(), 100)]));
+        metadata.write_to_file(&path)?;
+        Ok(true)
+    })
+    .await?
+}
+
+fn to_rational(value: f64, denominator: u32) -> uR64 {
+    uR64 {
+        nominator: (value * denominator as f64).round() as u32,
+        denominator,
+    }
+}
+
+fn to_degrees_minutes_seconds(coordinate: f64) -> Vec<uR64> {
+    let coordinate = coordinate.abs();
+    let degrees = coordinate.trunc();
+    let minutes = ((coordinate - degrees) * 60.0).trunc();
+    let seconds = ((coordinate - degrees) * 60.0 - minutes) * 60.0;
+    vec![
+        to_rational(degrees, 1),
+        to_rational(minutes, 1),
+        to_rational(seconds, 10000),
+    ]
+}
+
+/// Containers built from QuickTime atoms, whose header dates we can patch.
+pub const QUICKTIME_EXTENSIONS: [&str; 4] = ["mov", "mp4", "m4v", "3gp"];
+
+pub fn is_quicktime(extension: &str) -> bool {
+    QUICKTIME_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+/// Seconds between the QuickTime epoch (1904-01-01) and the unix epoch.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// How far the container date may be from the sidecar before it is rewritten.
+const QUICKTIME_DATE_TOLERANCE_SECONDS: i64 = 60;
+
+/// Writes the sidecar's taken time into the creation and modification times of
+/// the `mvhd`, `tkhd` and `mdhd` atoms when the container date is missing (the
+/// 1904 epoch) or off. The atoms are overwritten in place, the file keeps its
+/// size and nothing is transcoded. Returns whether the file was changed.
+pub async fn write_quicktime_taken_date<P: AsRef<Path>>(
+    path: P,
+    taken: DateTime<Utc>,
+) -> Result<bool> {
+    let path: PathBuf = path.as_ref().to_path_buf();
+    let current = rexif_get_taken_date(&path).await.ok().flatten();
+    if let Some(current) = current
+        && (current - taken).num_seconds().abs() <= QUICKTIME_DATE_TOLERANCE_SECONDS
+    {
+        return Ok(false);
+    }
+    let seconds = (taken.timestamp() + QUICKTIME_EPOCH_OFFSET) as u64;
+    tokio::task::spawn_blocking(move || {
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let end = file.metadata()?.len();
+        let patched = patch_atoms(&mut file, 0, end, seconds)?;
+        file.sync_all()?;
+        Ok(patched > 0)
+    })
+    .await?
+}
+
+fn patch_atoms(file: &mut File, start: u64, end: u64, seconds: u64) -> Result<usize> {
+    let mut patched = 0;
+    let mut position = start;
+    while position + 8 <= end {
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into()?) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large_size = [0u8; 8];
+            file.read_exact(&mut large_size)?;
+            size = u64::from_be_bytes(large_size);
+            header_len = 16;
+        } else if size == 0 {
+            size = end - position;
+        }
+        if size < header_len || position + size > end {
+            break;
+        }
+        let body = position + header_len;
+        match &header[4..8] {
+            b"moov" | b"trak" | b"mdia" => {
+                patched += patch_atoms(file, body, position + size, seconds)?;
+            }
+            b"mvhd" | b"tkhd" | b"mdhd" => {
+                patch_header_times(file, body, seconds)?;
+                patched += 1;
+            }
+            _ => {}
+        }
+        position += size;
+    }
+    Ok(patched)
+}
+
+/// Full atoms start with a version byte and three flag bytes. Version 1 stores
+/// the creation and modification times as 64 bit values, version 0 as 32 bit.
+fn patch_header_times(file: &mut File, body: u64, seconds: u64) -> Result<()> {
+    file.seek(SeekFrom::Start(body))?;
+    let mut version = [0u8; 1];
+    file.read_exact(&mut version)?;
+    file.seek(SeekFrom::Start(body + 4))?;
+    if version[0] == 1 {
+        file.write_all(&seconds.to_be_bytes())?;
+        file.write_all(&seconds.to_be_bytes())?;
+    } else {
+        let seconds = u32::try_from(seconds)?;
+        file.write_all(&seconds.to_be_bytes())?;
+        file.write_all(&seconds.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A 1x1 grey baseline jpeg without any EXIF, built segment by segment.
+    pub(crate) fn jpeg() -> Vec<u8> {
+        let segment = |marker: u8, body: &[u8]| {
+            let mut segment = vec![0xff, marker];
+            segment.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+            segment.extend_from_slice(body);
+            segment
+        };
+        // One Huffman code of length one, for symbol zero.
+        let huffman_table = |class: u8| {
+            let mut body = vec![class, 1];
+            body.extend_from_slice(&[0; 16]);
+            body
+        };
+        let mut quantization_table = vec![0];
+        quantization_table.extend_from_slice(&[1; 64]);
+        [
+            vec![0xff, 0xd8],
+            segment(0xdb, &quantization_table),
+            segment(0xc0, &[8, 0, 1, 0, 1, 1, 1, 0x11, 0]),
+            segment(0xc4, &huffman_table(0x00)),
+            segment(0xc4, &huffman_table(0x10)),
+            segment(0xda, &[1, 1, 0, 0, 0x3f, 0]),
+            // A zero DC difference and the end of block, padded with ones.
+            vec![0x3f, 0xff, 0xd9],
+        ]
+        .concat()
+    }
+
+    fn write_jpeg(folder: &Path, name: &str) -> PathBuf {
+        let path = folder.join(name);
+        std::fs::write(&path, jpeg()).unwrap();
+        path
+    }
+
+    fn read_dates(path: &Path) -> ExifDates {
+        ExifDates::read(&Metadata::new_from_path(path).unwrap())
+    }
+
+    fn local(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, EXIF_DATE_FORMAT).unwrap()
+    }
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        [&(body.len() as u32 + 8).to_be_bytes()[..], kind, body].concat()
+    }
+
+    /// An atom whose size is given in the 64 bit field after the type.
+    fn large_atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        [&1u32.to_be_bytes()[..], kind, &(body.len() as u64 + 16).to_be_bytes(), body].concat()
+    }
+
+    /// A header atom with creation and modification time set to `seconds`.
+    fn header_atom(kind: &[u8; 4], version: u8, seconds: u64) -> Vec<u8> {
+        let mut body = vec![version, 0, 0, 0];
+        for _ in 0..2 {
+            match version {
+                1 => body.extend_from_slice(&seconds.to_be_bytes()),
+                _ => body.extend_from_slice(&(seconds as u32).to_be_bytes()),
+            }
+        }
+        body.extend_from_slice(&[0xaa; 20]);
+        atom(kind, &body)
+    }
+
+    /// A movie with nothing but a version 0 movie header, times unset.
+    pub(crate) fn movie() -> Vec<u8> {
+        [atom(b"ftyp", b"isom"), atom(b"moov", &header_atom(b"mvhd", 0, 0))].concat()
+    }
+
+    fn header_times(buffer: &[u8], kind: &[u8; 4]) -> (u8, u64, u64) {
+        let start = buffer.windows(4).position(|window| window == kind).unwrap() + 4;
+        let body = &buffer[start..];
+        match body[0] {
+            1 => (
+                1,
+                u64::from_be_bytes(body[4..12].try_into().unwrap()),
+                u64::from_be_bytes(body[12..20].try_into().unwrap()),
+            ),
+            version => (
+                version,
+                u32::from_be_bytes(body[4..8].try_into().unwrap()) as u64,
+                u32::from_be_bytes(body[8..12].try_into().unwrap()) as u64,
+            ),
+        }
+    }
+
+    fn patch(buffer: &[u8], seconds: u64) -> (Result<usize>, Vec<u8>) {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("clip.mov");
+        std::fs::write(&path, buffer).unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let patched = patch_atoms(&mut file, 0, buffer.len() as u64, seconds);
+        (patched, std::fs::read(&path).unwrap())
+    }
+
+    #[test]
+    fn patches_version_0_headers_down_the_tree() {
+        let mdia = atom(b"mdia", &header_atom(b"mdhd", 0, 0));
+        let trak = atom(b"trak", &[header_atom(b"tkhd", 0, 0), mdia].concat());
+        let moov = atom(b"moov", &[header_atom(b"mvhd", 0, 0), trak].concat());
+        let buffer = [atom(b"ftyp", b"qt  "), moov].concat();
+
+        let (patched, patched_buffer) = patch(&buffer, 3_644_829_000);
+
+        assert_eq!(patched.unwrap(), 3);
+        assert_eq!(patched_buffer.len(), buffer.len());
+        for kind in [b"mvhd", b"tkhd", b"mdhd"] {
+            assert_eq!(header_times(&patched_buffer, kind), (0, 3_644_829_000, 3_644_829_000));
+        }
+        // Nothing after the times is touched.
+        assert_eq!(patched_buffer.iter().filter(|byte| **byte == 0xaa).count(), 60);
+    }
+
+    #[test]
+    fn patches_version_1_headers_behind_extended_sizes() {
+        let mdat = large_atom(b"mdat", &[0; 32]);
+        let moov = large_atom(b"moov", &header_atom(b"mvhd", 1, 0));
+        let buffer = [atom(b"ftyp", b"qt  "), mdat, moov].concat();
+        let seconds = u32::MAX as u64 + 1;
+
+        let (patched, patched_buffer) = patch(&buffer, seconds);
+
+        assert_eq!(patched.unwrap(), 1);
+        assert_eq!(header_times(&patched_buffer, b"mvhd"), (1, seconds, seconds));
+    }
+
+    #[test]
+    fn refuses_times_version_0_cannot_hold() {
+        let buffer = atom(b"moov", &header_atom(b"mvhd", 0, 0));
+
+        let (patched, _) = patch(&buffer, u32::MAX as u64 + 1);
+
+        assert!(patched.is_err());
+    }
+
+    #[test]
+    fn stops_at_atoms_running_past_their_parent() {
+        let mut buffer = atom(b"moov", &header_atom(b"mvhd", 0, 0));
+        buffer[3] += 1;
+
+        let (patched, patched_buffer) = patch(&buffer, 3_644_829_000);
+
+        assert_eq!(patched.unwrap(), 0);
+        assert_eq!(patched_buffer, buffer);
+    }
+
+    #[tokio::test]
+    async fn writes_the_taken_date_into_a_video() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("VID_0001.mp4");
+        std::fs::write(&path, movie()).unwrap();
+        let taken = Utc.with_ymd_and_hms(2019, 7, 1, 12, 30, 0).unwrap();
+
+        assert!(write_quicktime_taken_date(&path, taken).await.unwrap());
+
+        let seconds = (taken.timestamp() + QUICKTIME_EPOCH_OFFSET) as u64;
+        assert_eq!(header_times(&std::fs::read(&path).unwrap(), b"mvhd"), (0, seconds, seconds));
+    }
+
+    #[test]
+    fn plans_dates_in_the_resolved_offset() {
+        let tokyo = FixedOffset::east_opt(9 * 3600).unwrap();
+        let taken = Utc.with_ymd_and_hms(2019, 7, 1, 12, 30, 0).unwrap().with_timezone(&tokyo);
+
+        assert_eq!(
+            plan_exif_dates(&ExifDates::default(), taken),
+            Some((local("2019:07:01 21:30:00"), tokyo))
+        );
+        // An offset the camera wrote is kept.
+        let existing = ExifDates {
+            offset: FixedOffset::east_opt(-4 * 3600),
+            ..Default::default()
+        };
+        assert_eq!(
+            plan_exif_dates(&existing, taken),
+            Some((local("2019:07:01 08:30:00"), FixedOffset::east_opt(-4 * 3600).unwrap()))
+        );
+        // Tags already holding the time in that offset are left alone.
+        let existing = ExifDates {
+            date_time_original: Some(local("2019:07:01 21:30:00")),
+            create_date: Some(local("2019:07:01 21:30:00")),
+            offset: Some(tokyo),
+        };
+        assert_eq!(plan_exif_dates(&existing, taken), None);
+    }
+
+    #[tokio::test]
+    async fn writes_dates_into_a_jpeg_without_exif() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = write_jpeg(folder.path(), "IMG_0001.jpg");
+        let taken = Utc.with_ymd_and_hms(2019, 7, 1, 12, 30, 0).unwrap();
+
+        assert!(write_exif_taken_date(&path, taken.fixed_offset()).await.unwrap());
+
+        assert_eq!(
+            read_dates(&path),
+            ExifDates {
+                date_time_original: Some(local("2019:07:01 12:30:00")),
+                create_date: Some(local("2019:07:01 12:30:00")),
+                offset: FixedOffset::east_opt(0),
+            }
+        );
+        // Other readers still take it for a jpeg with EXIF.
+        assert_eq!(rexif_get_taken_date(&path).await.unwrap(), Some(taken));
+        // The tags agree now, so a second run leaves the file alone.
+        assert!(!write_exif_taken_date(&path, taken.fixed_offset()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_camera_local_date_time_original() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = write_jpeg(folder.path(), "IMG_0002.jpg");
+        let mut metadata = Metadata::new();
+        metadata.set_tag(ExifTag::DateTimeOriginal("2019:07:01 14:30:00".to_owned()));
+        metadata.write_to_file(&path).unwrap();
+        let taken = Utc.with_ymd_and_hms(2019, 7, 1, 12, 30, 0).unwrap();
+
+        // The zone of where it was taken says otherwise, the camera wins.
+        let taken = taken.with_timezone(&FixedOffset::east_opt(3600).unwrap());
+        assert!(write_exif_taken_date(&path, taken).await.unwrap());
+
+        assert_eq!(
+            read_dates(&path),
+            ExifDates {
+                date_time_original: Some(local("2019:07:01 14:30:00")),
+                create_date: Some(local("2019:07:01 14:30:00")),
+                offset: FixedOffset::east_opt(2 * 3600),
+            }
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::geocode::Place;
+use crate::media_utils::sanitize_file_name;
+use anyhow::{Error, Result};
+use chrono::{DateTime, FixedOffset, Locale};
+use std::path::PathBuf;
+
+/// The layout used before templates existed, `2019/July/4/IMG_1234.jpg`.
+pub const DEFAULT_PATH_TEMPLATE: &str = "{year}/{month_name}/{day_num}/{name}";
+
+/// Tokens a template can use. Tokens without a value for a file render their
+/// fallback, written as `{album|No album}`, or `Unknown`. Literal braces are
+/// doubled, `{{` and `}}`.
+pub const TEMPLATE_TOKENS: [&str; 22] = [
+    "year",
+    "month",
+    "month_num",
+    "month_name",
+    "month_short",
+    "day",
+    "day_num",
+    "hour",
+    "minute",
+    "second",
+    "album",
+    "camera_make",
+    "camera_model",
+    "media_type",
+    "account",
+    "country",
+    "country_code",
+    "region",
+    "city",
+    "name",
+    "stem",
+    "ext",
+];
+
+/// Tokens filled from where a photo was taken.
+pub const PLACE_TOKENS: [&str; 4] = ["country", "country_code", "region", "city"];
+
+const MISSING_VALUE: &str = "Unknown";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Token {
+        name: String,
+        fallback: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+/// What a file's path is rendered from.
+#[derive(Debug, Clone)]
+pub struct TemplateValues<'a> {
+    pub taken: DateTime<FixedOffset>,
+    pub name: &'a str,
+    pub album: Option<&'a str>,
+    pub camera_make: Option<&'a str>,
+    pub camera_model: Option<&'a str>,
+    pub media_type: &'a str,
+    pub account: Option<&'a str>,
+    /// Where the photo was taken, see [`crate::geocode::place_at`].
+    pub place: Option<&'a Place>,
+    pub locale: Locale,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        let mut literal = String::new();
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(position) if rest[position..].starts_with("{{") || rest[position..].starts_with("}}") => {
+                    literal.push_str(&rest[..=position]);
+                    rest = &rest[position + 2..];
+                }
+                Some(position) if rest[position..].starts_with('}') => {
+                    return Err(Error::msg(format!("Unmatched '}}' in path template `{}`", template)));
+                }
+                Some(position) => {
+                    literal.push_str(&rest[..position]);
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    let token = &rest[position + 1..];
+                    let end = token.find('}').ok_or_else(|| {
+                        Error::msg(format!("Unclosed '{{' in path template `{}`", template))
+                    })?;
+                    let (name, fallback) = match token[..end].split_once('|') {
+                        Some((name, fallback)) => (name.trim(), Some(fallback.to_owned())),
+                        None => (token[..end].trim(), None),
+                    };
+                    if !TEMPLATE_TOKENS.contains(&name) {
+                        return Err(Error::msg(format!(
+                            "Unknown token `{{{}}}` in path template, known tokens are {}",
+                            name,
+                            TEMPLATE_TOKENS.join(", ")
+                        )));
+                    }
+                    segments.push(Segment::Token {
+                        name: name.to_owned(),
+                        fallback,
+                    });
+                    rest = &token[end + 1..];
+                }
+                None => {
+                    literal.push_str(rest);
+                    rest = "";
+                }
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        let template = Self { segments };
+        template.validate()?;
+        Ok(template)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.uses("name") && !self.uses("stem") {
+            return Err(Error::msg("Path template needs `{name}` or `{stem}` to keep files apart"));
+        }
+        for segment in &self.segments {
+            if let Segment::Literal(literal) = segment {
+                if literal.split('/').any(|component| component == "..") {
+                    return Err(Error::msg("Path template cannot leave the target folder with `..`"));
+                }
+                if literal.contains('\\') {
+                    return Err(Error::msg("Path template separates folders with `/`"));
+                }
+            }
+        }
+        if matches!(self.segments.first(), Some(Segment::Literal(literal)) if literal.starts_with('/')) {
+            return Err(Error::msg("Path template is relative to the target folder"));
+        }
+        Ok(())
+    }
+
+    pub fn uses(&self, token: &str) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Token { name, .. } if name == token))
+    }
+
+    /// The path of a file relative to the library root. Every token is
+    /// sanitized, so a value can never add a folder level.
+    pub fn render(&self, values: &TemplateValues) -> PathBuf {
+        let rendered: String = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Token { name, fallback } => {
+                    let value = values
+                        .token(name)
+                        .filter(|value| !value.is_empty())
+                        .or_else(|| fallback.clone())
+                        .unwrap_or_else(|| MISSING_VALUE.to_owned());
+                    sanitize_file_name(&value)
+                }
+            })
+            .collect();
+        rendered
+            .split('/')
+            .map(str::trim)
+            .filter(|component| !component.is_empty() && *component != ".")
+            .collect()
+    }
+}
+
+impl TemplateValues<'_> {
+    fn token(&self, name: &str) -> Option<String> {
+        let format = |format: &str| Some(self.taken.format(format).to_string());
+        let (stem, extension) = match self.name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, extension),
+            _ => (self.name, ""),
+        };
+        match name {
+            "year" => format("%Y"),
+            "month" => format("%m"),
+            "month_num" => format("%-m"),
+            "month_name" => Some(self.taken.format_localized("%B", self.locale).to_string()),
+            "month_short" => Some(self.taken.format_localized("%b", self.locale).to_string()),
+            "day" => format("%d"),
+            "day_num" => format("%-d"),
+            "hour" => format("%H"),
+            "minute" => format("%M"),
+            "second" => format("%S"),
+            "album" => self.album.map(str::to_owned),
+            "camera_make" => self.camera_make.map(str::to_owned),
+            "camera_model" => self.camera_model.map(str::to_owned),
+            "media_type" => Some(self.media_type.to_owned()),
+            "account" => self.account.map(str::to_owned),
+            "country" => self.place.map(|place| place.country.clone()),
+            "country_code" => self.place.map(|place| place.country_code.clone()),
+            "region" => self.place.and_then(|place| place.region.clone()),
+            "city" => self.place.map(|place| place.city.clone()),
+            "name" => Some(self.name.to_owned()),
+            "stem" => Some(stem.to_owned()),
+            "ext" => Some(extension.to_owned()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(place: Option<&Place>) -> TemplateValues<'_> {
+        TemplateValues {
+            taken: DateTime::parse_from_rfc3339("2019-07-04T23:30:00+02:00").unwrap(),
+            name: "IMG_20190704_233000.jpg",
+            album: None,
+            camera_make: Some("Google"),
+            camera_model: Some("Pixel 7"),
+            media_type: "photo",
+            account: None,
+            place,
+            locale: Locale::de_DE,
+        }
+    }
+
+    fn render(template: &str) -> String {
+        let template = PathTemplate::parse(template).unwrap();
+        template.render(&values(None)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn renders_tokens_in_the_offset_taken() {
+        assert_eq!(render(DEFAULT_PATH_TEMPLATE), "2019/Juli/4/IMG_20190704_233000.jpg");
+        assert_eq!(
+            render("{year}-{month}-{day} {hour}.{minute}.{second}/{stem}.{ext}"),
+            "2019-07-04 23.30.00/IMG_20190704_233000.jpg"
+        );
+        assert_eq!(render("{camera_make} {camera_model}/{name}"), "Google Pixel 7/IMG_20190704_233000.jpg");
+    }
+
+    #[test]
+    fn renders_fallbacks_for_missing_values() {
+        assert_eq!(render("{album|No album}/{name}"), "No album/IMG_20190704_233000.jpg");
+        assert_eq!(render("{city}/{name}"), "Unknown/IMG_20190704_233000.jpg");
+        let florence = Place {
+            country_code: "IT".to_owned(),
+            country: "Italy".to_owned(),
+            region: None,
+            city: "Florence".to_owned(),
+        };
+        let template = PathTemplate::parse("{country}/{region|No region}/{city|Somewhere}/{name}").unwrap();
+        assert_eq!(
+            template.render(&values(Some(&florence))),
+            PathBuf::from("Italy/No region/Florence/IMG_20190704_233000.jpg")
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{year}}}/{name}"), "{2019}/IMG_20190704_233000.jpg");
+        assert_eq!(render("{{year}}/{name}"), "{year}/IMG_20190704_233000.jpg");
+    }
+
+    #[test]
+    fn values_never_add_folder_levels() {
+        let mut values = values(None);
+        values.camera_model = Some("../etc/passwd");
+        let template = PathTemplate::parse("{camera_model}/{name}").unwrap();
+        let rendered = template.render(&values);
+        assert_eq!(rendered.components().count(), 2);
+    }
+
+    #[test]
+    fn rejects_broken_templates() {
+        for template in [
+            "{year}/{nmae}",
+            "{year/{name}",
+            "{year}}/{name}",
+            "{year}",
+            "../{name}",
+            "/{year}/{name}",
+            "{year}\\{name}",
+        ] {
+            assert!(PathTemplate::parse(template).is_err(), "{template}");
+        }
+        let err = PathTemplate::parse("{nmae}").unwrap_err().to_string();
+        assert!(err.contains("Unknown token `{nmae}`"), "{err}");
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Formats we can decode without native libraries. Raw files have no pure Rust
+/// decoder and are left without a hash.
+const HASHABLE_EXTENSIONS: [&str; 8] = ["jpg", "jpeg", "png", "webp", "gif", "bmp", "tif", "tiff"];
+
+/// HEVC has no pure Rust decoder either, these are hashed by the JPEG preview
+/// some cameras embed in them. Files with only HEVC thumbnails, as iPhones
+/// write them, stay without a hash.
+const PREVIEW_EXTENSIONS: [&str; 2] = ["heic", "heif"];
+
+pub fn is_perceptual_hashable(extension: &str) -> bool {
+    let extension = extension.to_lowercase();
+    HASHABLE_EXTENSIONS.contains(&extension.as_str()) || PREVIEW_EXTENSIONS.contains(&extension.as_str())
+}
+
+/// Difference hash of the image at `path`, `None` for formats we cannot decode.
+pub async fn perceptual_hash<P: AsRef<Path>>(path: P) -> Result<Option<u64>> {
+    let path = path.as_ref().to_path_buf();
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    if !is_perceptual_hashable(extension) {
+        return Ok(None);
+    }
+    let preview = PREVIEW_EXTENSIONS.contains(&extension.to_lowercase().as_str());
+    let hash = tokio::task::spawn_blocking(move || -> Result<Option<u64>> {
+        if preview {
+            return Ok(embedded_jpeg(&std::fs::read(&path)?).map(|image| dhash(&image)));
+        }
+        let mut decoder = ImageReader::open(&path)?.with_guessed_format()?.into_decoder()?;
+        // A copy that was rotated on export should still match its original.
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        Ok(Some(dhash(&image)))
+    })
+    .await??;
+    Ok(hash)
+}
+
+/// The largest JPEG found inside `bytes`, looked for by its start marker.
+fn embedded_jpeg(bytes: &[u8]) -> Option<DynamicImage> {
+    let jpeg_at = |start: usize| ImageReader::with_format(Cursor::new(&bytes[start..]), ImageFormat::Jpeg);
+    let start = bytes
+        .windows(3)
+        .enumerate()
+        .filter(|(_, marker)| *marker == [0xff, 0xd8, 0xff])
+        .filter_map(|(start, _)| {
+            let (width, height) = jpeg_at(start).into_dimensions().ok()?;
+            Some((width as u64 * height as u64, start))
+        })
+        .max()?
+        .1;
+    jpeg_at(start).decode().ok()
+}
+
+/// Shrinks the image to 9x8 grey pixels and records for each row whether the
+/// brightness rises from one pixel to the next. Re-encoding, resizing and mild
+/// compression leave most of the 64 bits alone.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups hashes that are at most `max_distance` bits apart, directly or
+/// through another member. Returns each group of two or more ids with the
+/// distance of every member to the first one.
+///
+/// Hashes within `max_distance` agree on at least one of `max_distance + 1`
+/// slices, so only hashes sharing a slice are compared.
+pub fn cluster_hashes(hashes: &[(i32, u64)], max_distance: u32) -> Vec<Vec<(i32, u32)>> {
+    let slices = (max_distance + 1).min(64);
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    for slice in 0..slices {
+        let shift = slice * 64 / slices;
+        let bits = (slice + 1) * 64 / slices - shift;
+        let mask = if bits == 64 { u64::MAX } else { (1u64 << bits) - 1 };
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (index, (_, hash)) in hashes.iter().enumerate() {
+            buckets.entry((hash >> shift) & mask).or_default().push(index);
+        }
+        for bucket in buckets.values() {
+            for (i, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[i + 1..] {
+                    if hamming_distance(hashes[a].1, hashes[b].1) <= max_distance {
+                        let (root_a, root_b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                        parents[root_a.max(root_b)] = root_a.min(root_b);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..hashes.len() {
+        let root = find_root(&mut parents, index);
+        groups.entry(root).or_default().push(index);
+    }
+    let mut clusters: Vec<Vec<(i32, u32)>> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let first = hashes[members[0]].1;
+            members
+                .into_iter()
+                .map(|index| (hashes[index].0, hamming_distance(first, hashes[index].1)))
+                .collect()
+        })
+        .collect();
+    clusters.sort_by_key(|members| members[0].0);
+    clusters
+}
+
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{GrayImage, Luma};
+
+    /// Brightness rising to the right in every row but the last two columns.
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, _| {
+            Luma([if x * 9 / width < 7 { (x * 255 / width) as u8 } else { 0 }])
+        }))
+    }
+
+    fn encode_jpeg(image: &DynamicImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        JpegEncoder::new_with_quality(&mut bytes, 90).encode_image(image).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn dhash_records_where_brightness_rises() {
+        let hash = dhash(&gradient(90, 80));
+        // Each row rises over its first six steps only.
+        assert_eq!(hash, 0xfcfc_fcfc_fcfc_fcfc);
+        assert_eq!(dhash(&gradient(900, 800)), hash);
+        assert_eq!(dhash(&DynamicImage::ImageLuma8(GrayImage::new(90, 80))), 0);
+    }
+
+    #[test]
+    fn counts_the_bits_that_differ() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    fn clusters_only_identical_hashes_at_distance_zero() {
+        let hashes = [(1, 0b1111), (2, 0b1110), (3, 0b1111), (4, u64::MAX)];
+        assert_eq!(cluster_hashes(&hashes, 0), vec![vec![(1, 0), (3, 0)]]);
+    }
+
+    #[test]
+    fn chains_hashes_through_their_neighbours() {
+        // 1 and 3 are four bits apart, each two bits from 2.
+        let hashes = [(3, 0b1111), (1, 0), (2, 0b0011), (4, u64::MAX << 8)];
+        assert_eq!(cluster_hashes(&hashes, 2), vec![vec![(3, 0), (1, 4), (2, 2)]]);
+        assert_eq!(cluster_hashes(&hashes, 1), Vec::<Vec<(i32, u32)>>::new());
+        assert_eq!(cluster_hashes(&hashes, 64).len(), 1);
+        assert_eq!(cluster_hashes(&hashes, 64)[0].len(), 4);
+    }
+
+    #[tokio::test]
+    async fn hashes_heic_by_its_largest_embedded_jpeg() {
+        let folder = tempfile::tempdir().unwrap();
+        let image = gradient(90, 80);
+        let jpeg = folder.path().join("IMG_0001.jpg");
+        std::fs::write(&jpeg, encode_jpeg(&image)).unwrap();
+
+        let heic = folder.path().join("IMG_0001.HEIC");
+        let mut bytes = b"\0\0\0\x18ftypheic".to_vec();
+        bytes.extend(encode_jpeg(&DynamicImage::ImageLuma8(GrayImage::new(9, 8))));
+        bytes.extend([0xff, 0xd8, 0xff, 0x00]);
+        bytes.extend(encode_jpeg(&image));
+        std::fs::write(&heic, bytes).unwrap();
+        assert_eq!(perceptual_hash(&heic).await.unwrap(), perceptual_hash(&jpeg).await.unwrap());
+
+        let hevc_only = folder.path().join("IMG_0002.HEIC");
+        std::fs::write(&hevc_only, b"\0\0\0\x18ftypheic").unwrap();
+        assert_eq!(perceptual_hash(&hevc_only).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::media_utils::{content_hash, hash_file};
+use crate::settings::PlacementMode;
+use anyhow::Result;
+use filetime::FileTime;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Where a file ends up when its target name may already be taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    /// Nothing was at the target.
+    Free(PathBuf),
+    /// A different file had the name, this one gets a suffix.
+    Renamed { path: PathBuf, taken_by: PathBuf },
+    /// The same content is already there.
+    Duplicate(PathBuf),
+}
+
+impl Placement {
+    pub fn path(&self) -> &Path {
+        match self {
+            Placement::Free(path) | Placement::Renamed { path, .. } | Placement::Duplicate(path) => path,
+        }
+    }
+}
+
+/// Picks the path `source` is placed at instead of `target`. A file with the
+/// same name and different content makes this one `IMG_0001-1a2b3c4d.JPG`,
+/// from the start of its content hash, so the name does not depend on which
+/// phone's photo was processed first.
+pub async fn plan_placement(source: &Path, target: &Path) -> Result<Placement> {
+    if !exists(target).await? {
+        return Ok(Placement::Free(target.to_path_buf()));
+    }
+    plan_placement_of(&content_hash(source).await?, target, &HashMap::new()).await
+}
+
+/// [`plan_placement`] for a file known by its content hash, seeing `planned`,
+/// the files a plan puts in place before this one, as if they were on disk.
+pub async fn plan_placement_of(
+    hash: &str,
+    target: &Path,
+    planned: &HashMap<PathBuf, String>,
+) -> Result<Placement> {
+    let existing_hash = async |path: &Path| -> Result<Option<String>> {
+        if let Some(planned_hash) = planned.get(path) {
+            return Ok(Some(planned_hash.clone()));
+        }
+        if !exists(path).await? {
+            return Ok(None);
+        }
+        Ok(Some(content_hash(path).await?))
+    };
+    match existing_hash(target).await? {
+        None => return Ok(Placement::Free(target.to_path_buf())),
+        Some(existing) if existing == hash => return Ok(Placement::Duplicate(target.to_path_buf())),
+        Some(_) => {}
+    }
+    let stem = target.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let extension = target
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| format!(".{}", extension))
+        .unwrap_or_default();
+    for attempt in 0.. {
+        let suffix = match attempt {
+            0 => hash[..8].to_owned(),
+            attempt => format!("{}-{}", &hash[..8], attempt),
+        };
+        let candidate = target.with_file_name(format!("{}-{}{}", stem, suffix, extension));
+        match existing_hash(&candidate).await? {
+            None => {
+                return Ok(Placement::Renamed {
+                    path: candidate,
+                    taken_by: target.to_path_buf(),
+                });
+            }
+            Some(existing) if existing == hash => return Ok(Placement::Duplicate(candidate)),
+            Some(_) => {}
+        }
+    }
+    unreachable!()
+}
+
+/// Moves `source` to `target` unless something got there first, which shows up
+/// as an `AlreadyExists` error. On Linux this is one `renameat2` that refuses
+/// to replace the target. Elsewhere, or where the filesystem lacks that flag,
+/// the file is hard linked and the source unlinked, which never replaces a
+/// file either. Both only touch directory entries. Across mounts, or onto a
+/// filesystem without hard links, the file is copied instead, which writes it
+/// once, flushes it and reads both copies again to compare their hashes.
+pub async fn move_without_overwrite(source: &Path, target: &Path) -> std::io::Result<()> {
+    let (source, target) = (source.to_path_buf(), target.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        move_by(&source, &target, rename_no_replace, |source, target| std::fs::hard_link(source, target))
+    })
+    .await?
+}
+
+/// [`move_without_overwrite`] with the renaming and linking handed in.
+fn move_by(
+    source: &Path,
+    target: &Path,
+    rename: impl Fn(&Path, &Path) -> std::io::Result<()>,
+    link: impl Fn(&Path, &Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let must_copy = |err: &std::io::Error| matches!(err.kind(), ErrorKind::CrossesDevices | ErrorKind::Unsupported);
+    match rename(source, target) {
+        Ok(_) => return Ok(()),
+        Err(err) if err.kind() == ErrorKind::Unsupported => match link(source, target) {
+            Ok(_) => {}
+            Err(err) if must_copy(&err) => copy_verified(source, target)?,
+            Err(err) => return Err(err),
+        },
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => copy_verified(source, target)?,
+        Err(err) => return Err(err),
+    }
+    std::fs::remove_file(source)
+}
+
+#[cfg(target_os = "linux")]
+fn rename_no_replace(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let target = CString::new(target.as_os_str().as_bytes())?;
+    // SAFETY: both paths are nul-terminated and outlive the call.
+    let renamed = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            libc::AT_FDCWD,
+            target.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if renamed == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        // Filesystems that do not know the flag turn it down as invalid.
+        Some(libc::EINVAL) => Err(ErrorKind::Unsupported.into()),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_no_replace(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Puts `source` at `target`, which must not exist yet, the way `mode` asks.
+/// A hard link across mounts or a reflink on a filesystem without them falls
+/// back to a verified copy. Returns the mode that was used.
+pub async fn transfer(source: &Path, target: &Path, mode: PlacementMode) -> std::io::Result<PlacementMode> {
+    let linked = match mode {
+        PlacementMode::Move => return move_without_overwrite(source, target).await.map(|_| mode),
+        PlacementMode::Copy => Err(std::io::Error::from(ErrorKind::Unsupported)),
+        PlacementMode::Hardlink => fs::hard_link(source, target).await,
+        PlacementMode::Reflink => {
+            let (source, target) = (source.to_path_buf(), target.to_path_buf());
+            tokio::task::spawn_blocking(move || reflink_copy::reflink(&source, &target)).await?
+        }
+    };
+    match linked {
+        Ok(_) => Ok(mode),
+        Err(err) if matches!(err.kind(), ErrorKind::AlreadyExists | ErrorKind::NotFound) => Err(err),
+        Err(_) => {
+            let (source, target) = (source.to_path_buf(), target.to_path_buf());
+            tokio::task::spawn_blocking(move || copy_verified(&source, &target)).await??;
+            Ok(PlacementMode::Copy)
+        }
+    }
+}
+
+/// Copies to a target that must not exist yet, flushes it to disk and checks
+/// size and hash against the source before the source may be removed. A copy
+/// that does not match is removed again.
+fn copy_verified(source: &Path, target: &Path) -> std::io::Result<()> {
+    let mut input = File::open(source)?;
+    let mut output = OpenOptions::new().write(true).create_new(true).open(target)?;
+    let copied = (|| {
+        std::io::copy(&mut input, &mut output)?;
+        output.sync_all()?;
+        let source_metadata = input.metadata()?;
+        if source_metadata.len() != output.metadata()?.len() || hash_file(source)? != hash_file(target)? {
+            return Err(std::io::Error::other(format!(
+                "Copy of {} does not match the original",
+                source.display()
+            )));
+        }
+        filetime::set_file_mtime(target, FileTime::from_last_modification_time(&source_metadata))?;
+        // The directory entry has to be on disk too before the source goes.
+        #[cfg(unix)]
+        if let Some(folder) = target.parent() {
+            File::open(folder)?.sync_all()?;
+        }
+        Ok(())
+    })();
+    if copied.is_err() {
+        let _ = std::fs::remove_file(target);
+    }
+    copied
+}
+
+async fn exists(path: &Path) -> Result<bool> {
+    match fs::symlink_metadata(path).await {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::MetadataExt;
+
+    async fn source_in(folder: &Path) -> PathBuf {
+        let source = folder.join("IMG_0001.jpg");
+        fs::write(&source, b"photo bytes").await.unwrap();
+        source
+    }
+
+    #[tokio::test]
+    async fn places_files_the_way_each_mode_asks() {
+        let folder = tempfile::tempdir().unwrap();
+        let source = source_in(folder.path()).await;
+
+        let copy = folder.path().join("copy.jpg");
+        assert_eq!(transfer(&source, &copy, PlacementMode::Copy).await.unwrap(), PlacementMode::Copy);
+        assert_eq!(fs::read(&copy).await.unwrap(), b"photo bytes");
+
+        let link = folder.path().join("link.jpg");
+        assert_eq!(transfer(&source, &link, PlacementMode::Hardlink).await.unwrap(), PlacementMode::Hardlink);
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&link).await.unwrap().ino(),
+            fs::metadata(&source).await.unwrap().ino()
+        );
+
+        // Without reflink support the file is copied.
+        let reflink = folder.path().join("reflink.jpg");
+        let used = transfer(&source, &reflink, PlacementMode::Reflink).await.unwrap();
+        assert!(matches!(used, PlacementMode::Reflink | PlacementMode::Copy), "{:?}", used);
+        assert_eq!(fs::read(&reflink).await.unwrap(), b"photo bytes");
+
+        #[cfg(unix)]
+        let inode = fs::metadata(&source).await.unwrap().ino();
+        let moved = folder.path().join("moved.jpg");
+        assert_eq!(transfer(&source, &moved, PlacementMode::Move).await.unwrap(), PlacementMode::Move);
+        assert!(!exists(&source).await.unwrap());
+        assert_eq!(fs::read(&moved).await.unwrap(), b"photo bytes");
+        // Renamed, not copied.
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&moved).await.unwrap().ino(), inode);
+    }
+
+    #[tokio::test]
+    async fn links_and_unlinks_where_renaming_cannot_refuse_to_overwrite() {
+        let folder = tempfile::tempdir().unwrap();
+        let source = source_in(folder.path()).await;
+        let target = folder.path().join("moved.jpg");
+        let unsupported = |_: &Path, _: &Path| Err(ErrorKind::Unsupported.into());
+        let denied = |_: &Path, _: &Path| Err(ErrorKind::PermissionDenied.into());
+
+        let err = move_by(&source, &target, unsupported, denied).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(!exists(&target).await.unwrap());
+        assert!(exists(&source).await.unwrap());
+
+        move_by(&source, &target, unsupported, |source, target| std::fs::hard_link(source, target)).unwrap();
+        assert!(!exists(&source).await.unwrap());
+        assert_eq!(fs::read(&target).await.unwrap(), b"photo bytes");
+    }
+
+    /// A file cannot be renamed or linked across mounts, it is copied there
+    /// instead.
+    #[tokio::test]
+    async fn copies_what_cannot_be_linked_across_mounts() {
+        let folder = tempfile::tempdir().unwrap();
+        let cross_device: fn(&Path, &Path) -> std::io::Result<()> = |_, _| Err(ErrorKind::CrossesDevices.into());
+        let unsupported: fn(&Path, &Path) -> std::io::Result<()> = |_, _| Err(ErrorKind::Unsupported.into());
+
+        for (rename, link) in [(cross_device, cross_device), (unsupported, cross_device)] {
+            let source = source_in(folder.path()).await;
+            let moved = folder.path().join("moved.jpg");
+            move_by(&source, &moved, rename, link).unwrap();
+            assert_eq!(fs::read(&moved).await.unwrap(), b"photo bytes");
+            assert!(!exists(&source).await.unwrap());
+            fs::remove_file(&moved).await.unwrap();
+        }
+    }
+
+    /// [`copies_what_cannot_be_linked_across_mounts`] on real mounts.
+    #[cfg(unix)]
+    #[tokio::test]
+    #[ignore = "needs /dev/shm on another mount than the temporary folder"]
+    async fn copies_across_real_mounts() {
+        let folder = tempfile::tempdir().unwrap();
+        let other_mount = tempfile::tempdir_in("/dev/shm").expect("/dev/shm is writable");
+        assert_ne!(
+            fs::metadata(folder.path()).await.unwrap().dev(),
+            fs::metadata(other_mount.path()).await.unwrap().dev(),
+            "/dev/shm is on another mount"
+        );
+        let source = source_in(folder.path()).await;
+
+        let link = other_mount.path().join("link.jpg");
+        assert_eq!(transfer(&source, &link, PlacementMode::Hardlink).await.unwrap(), PlacementMode::Copy);
+        assert_eq!(fs::read(&link).await.unwrap(), b"photo bytes");
+        assert!(exists(&source).await.unwrap());
+
+        let moved = other_mount.path().join("moved.jpg");
+        assert_eq!(transfer(&source, &moved, PlacementMode::Move).await.unwrap(), PlacementMode::Move);
+        assert_eq!(fs::read(&moved).await.unwrap(), b"photo bytes");
+        assert!(!exists(&source).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn never_falls_back_over_a_taken_target_or_a_missing_source() {
+        let folder = tempfile::tempdir().unwrap();
+        let source = source_in(folder.path()).await;
+        let taken = folder.path().join("taken.jpg");
+        fs::write(&taken, b"other bytes").await.unwrap();
+        let missing = folder.path().join("missing.jpg");
+        let target = folder.path().join("target.jpg");
+
+        for mode in [PlacementMode::Move, PlacementMode::Copy, PlacementMode::Hardlink, PlacementMode::Reflink] {
+            let err = transfer(&source, &taken, mode).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::AlreadyExists, "{:?}", mode);
+            assert_eq!(fs::read(&taken).await.unwrap(), b"other bytes");
+            assert!(exists(&source).await.unwrap());
+
+            let err = transfer(&missing, &target, mode).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::NotFound, "{:?}", mode);
+            assert!(!exists(&target).await.unwrap());
+        }
+    }
+}
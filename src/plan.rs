@@ -0,0 +1,179 @@
+use crate::settings::get_plan_file;
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const PLAN_JSON_FILE: &str = "plan.json";
+pub const PLAN_CSV_FILE: &str = "plan.csv";
+
+/// What a run would do with a media file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanAction {
+    Place,
+    /// Placed with a suffix, another file has its name.
+    Rename,
+    Duplicate,
+    NoDate,
+    Skip,
+}
+
+impl PlanAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlanAction::Place => "place",
+            PlanAction::Rename => "rename",
+            PlanAction::Duplicate => "duplicate",
+            PlanAction::NoDate => "no_date",
+            PlanAction::Skip => "skip",
+        }
+    }
+}
+
+/// One media file of the archives, found by its path in the archive and its
+/// content hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanEntry {
+    pub archive: String,
+    pub archive_path: String,
+    pub content_hash: String,
+    pub size: u64,
+    pub action: PlanAction,
+    pub destination: Option<PathBuf>,
+    /// The archive entry or library file with the same content.
+    pub duplicate_of: Option<String>,
+    pub taken_at: Option<DateTime<FixedOffset>>,
+    pub date_source: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub created_at: DateTime<Utc>,
+    pub target_folder: PathBuf,
+    pub path_template: String,
+    pub entries: Vec<PlanEntry>,
+}
+
+impl Plan {
+    pub fn count(&self, action: PlanAction) -> usize {
+        self.entries.iter().filter(|entry| entry.action == action).count()
+    }
+
+    /// The same entries as a spreadsheet, one row per media file.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "archive,archive_path,content_hash,size,action,destination,duplicate_of,taken_at,date_source,reason\n",
+        );
+        for entry in &self.entries {
+            let fields = [
+                entry.archive.clone(),
+                entry.archive_path.clone(),
+                entry.content_hash.clone(),
+                entry.size.to_string(),
+                entry.action.as_str().to_owned(),
+                entry
+                    .destination
+                    .as_ref()
+                    .map(|destination| destination.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                entry.duplicate_of.clone().unwrap_or_default(),
+                entry.taken_at.map(|taken_at| taken_at.to_rfc3339()).unwrap_or_default(),
+                entry.date_source.clone().unwrap_or_default(),
+                entry.reason.clone().unwrap_or_default(),
+            ];
+            let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// The entries of a plan a run follows, by archive path and content hash.
+#[derive(Debug, Default)]
+pub struct PlannedEntries(HashMap<(String, String), PlanEntry>);
+
+impl From<Vec<PlanEntry>> for PlannedEntries {
+    fn from(entries: Vec<PlanEntry>) -> Self {
+        Self(
+            entries
+                .into_iter()
+                .map(|entry| ((entry.archive_path.clone(), entry.content_hash.clone()), entry))
+                .collect(),
+        )
+    }
+}
+
+impl PlannedEntries {
+    /// Reads the plan a dry run wrote to `plan_file`.
+    pub fn load(plan_file: &Path) -> Result<Self> {
+        let read = || -> Result<Plan> { Ok(serde_json::from_str(&std::fs::read_to_string(plan_file)?)?) };
+        let plan = read().with_context(|| format!("Cannot read plan {}", plan_file.display()))?;
+        Ok(plan.entries.into())
+    }
+
+    /// The plan in `PLAN_FILE`, or no entries when no plan is followed.
+    pub fn from_settings() -> Result<Self> {
+        match get_plan_file() {
+            Some(plan_file) => Self::load(&plan_file),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// What the plan decided for the file extracted from `archive_path`, if the
+    /// file has the content it was planned with.
+    pub fn get(&self, archive_path: &str, content_hash: &str) -> Option<&PlanEntry> {
+        self.0.get(&(archive_path.to_owned(), content_hash.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(archive_path: &str, content_hash: &str) -> PlanEntry {
+        PlanEntry {
+            archive: "takeout-001.zip".to_owned(),
+            archive_path: archive_path.to_owned(),
+            content_hash: content_hash.to_owned(),
+            size: 13,
+            action: PlanAction::Place,
+            destination: Some(PathBuf::from("2019/07/IMG_0001.jpg")),
+            duplicate_of: None,
+            taken_at: None,
+            date_source: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn finds_entries_of_a_written_plan_by_path_and_content() {
+        let folder = tempfile::tempdir().unwrap();
+        let plan = Plan {
+            created_at: Utc::now(),
+            target_folder: folder.path().to_path_buf(),
+            path_template: "{year}/{month}/{name}".to_owned(),
+            entries: vec![entry("Takeout/IMG_0001.jpg", "abc")],
+        };
+        let plan_file = folder.path().join(PLAN_JSON_FILE);
+        std::fs::write(&plan_file, serde_json::to_string_pretty(&plan).unwrap()).unwrap();
+
+        let entries = PlannedEntries::load(&plan_file).unwrap();
+        let found = entries.get("Takeout/IMG_0001.jpg", "abc").unwrap();
+        assert_eq!(found.destination, Some(PathBuf::from("2019/07/IMG_0001.jpg")));
+        // Changed content is not what was planned.
+        assert!(entries.get("Takeout/IMG_0001.jpg", "def").is_none());
+        assert!(PlannedEntries::load(&folder.path().join("missing.json")).is_err());
+    }
+}
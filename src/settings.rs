@@ -0,0 +1,292 @@
+use crate::date_resolver::DateSource;
+use crate::path_template::DEFAULT_PATH_TEMPLATE;
+use chrono::Locale;
+use chrono_tz::Tz;
+use std::path::PathBuf;
+
+/// What to keep when Takeout exports both an original and an edited copy
+/// (`IMG_1234.jpg` and `IMG_1234-edited.jpg`) of the same photo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EditedPolicy {
+    #[default]
+    KeepBoth,
+    EditedOnly,
+    OriginalOnly,
+}
+
+/// Reads `EDITED_POLICY` (`both`, `edited_only` or `original_only`).
+pub fn get_edited_policy() -> EditedPolicy {
+    match dotenv::var("EDITED_POLICY").unwrap_or_default().as_str() {
+        "edited_only" => EditedPolicy::EditedOnly,
+        "original_only" => EditedPolicy::OriginalOnly,
+        _ => EditedPolicy::KeepBoth,
+    }
+}
+
+/// How albums are written out by the album export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlbumExportMode {
+    #[default]
+    Symlink,
+    Hardlink,
+    M3u,
+    Json,
+}
+
+/// Reads `ALBUM_EXPORT` (`symlink`, `hardlink`, `m3u` or `json`).
+pub fn get_album_export_mode() -> AlbumExportMode {
+    match dotenv::var("ALBUM_EXPORT").unwrap_or_default().as_str() {
+        "hardlink" => AlbumExportMode::Hardlink,
+        "m3u" => AlbumExportMode::M3u,
+        "json" => AlbumExportMode::Json,
+        _ => AlbumExportMode::Symlink,
+    }
+}
+
+/// Reads `WRITE_EXIF_DATES`, whether the sidecar's taken time is written back
+/// into the EXIF of files we can edit in place.
+pub fn get_write_exif_dates() -> bool {
+    dotenv::var("WRITE_EXIF_DATES").is_ok_and(|value| value == "true")
+}
+
+/// Reads `WRITE_EXIF_GPS`, whether the sidecar's location is written into
+/// files that lost their GPS tags.
+pub fn get_write_exif_gps() -> bool {
+    dotenv::var("WRITE_EXIF_GPS").is_ok_and(|value| value == "true")
+}
+
+/// Reads `WRITE_XMP_SIDECARS`, whether raw files and videos get an `.xmp` next
+/// to them. On unless set to `false`.
+pub fn get_write_xmp_sidecars() -> bool {
+    dotenv::var("WRITE_XMP_SIDECARS").map_or(true, |value| value != "false")
+}
+
+/// Reads `XMP_SIDECAR_NAMING`, `extension` for `IMG_1.CR2.xmp` (darktable,
+/// digiKam) or `basename` for `IMG_1.xmp` (Lightroom).
+pub fn get_xmp_keeps_extension() -> bool {
+    dotenv::var("XMP_SIDECAR_NAMING").map_or(true, |value| value != "basename")
+}
+
+/// Which file times are set to the taken date once a file is placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileTimesPolicy {
+    None,
+    #[default]
+    Modified,
+    ModifiedAndAccessed,
+}
+
+/// Reads `SET_FILE_TIMES` (`none`, `mtime` or `mtime_atime`).
+pub fn get_file_times_policy() -> FileTimesPolicy {
+    match dotenv::var("SET_FILE_TIMES").unwrap_or_default().as_str() {
+        "none" => FileTimesPolicy::None,
+        "mtime_atime" => FileTimesPolicy::ModifiedAndAccessed,
+        _ => FileTimesPolicy::Modified,
+    }
+}
+
+/// Reads `WRITE_VIDEO_DATES`, whether the sidecar's taken time is patched into
+/// the QuickTime headers of videos.
+pub fn get_write_video_dates() -> bool {
+    dotenv::var("WRITE_VIDEO_DATES").is_ok_and(|value| value == "true")
+}
+
+/// The metadata a run writes into the files it places.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetadataWrites {
+    pub exif_dates: bool,
+    pub video_dates: bool,
+    pub exif_gps: bool,
+}
+
+/// Reads `WRITE_EXIF_DATES`, `WRITE_VIDEO_DATES` and `WRITE_EXIF_GPS`.
+pub fn get_metadata_writes() -> MetadataWrites {
+    MetadataWrites {
+        exif_dates: get_write_exif_dates(),
+        video_dates: get_write_video_dates(),
+        exif_gps: get_write_exif_gps(),
+    }
+}
+
+/// Reads `DATE_SOURCES`, the comma separated order in which taken dates are
+/// looked up (`json`, `exif`, `filename`, `archive_mtime`). Sources left out
+/// are not consulted.
+pub fn get_date_source_priority() -> Vec<DateSource> {
+    let priority: Vec<DateSource> = dotenv::var("DATE_SOURCES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(DateSource::parse)
+        .collect();
+    if priority.is_empty() {
+        DateSource::DEFAULT_PRIORITY.to_vec()
+    } else {
+        priority
+    }
+}
+
+/// Reads `DEFAULT_TIMEZONE`, the IANA zone (`Europe/Amsterdam`) used to place
+/// photos that carry neither an offset nor a location.
+pub fn get_default_timezone() -> Option<Tz> {
+    dotenv::var("DEFAULT_TIMEZONE").ok()?.parse().ok()
+}
+
+/// What happens to photos the sidecar marks as trashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrashedPolicy {
+    Skip,
+    #[default]
+    Quarantine,
+    Keep,
+}
+
+/// Reads `TRASHED_POLICY` (`skip`, `quarantine` or `keep`).
+pub fn get_trashed_policy() -> TrashedPolicy {
+    match dotenv::var("TRASHED_POLICY").unwrap_or_default().as_str() {
+        "skip" => TrashedPolicy::Skip,
+        "keep" => TrashedPolicy::Keep,
+        _ => TrashedPolicy::Quarantine,
+    }
+}
+
+/// Reads `ARCHIVED_POLICY`, whether archived and locked folder photos get a
+/// tree of their own (`separate`) or stay with the rest (`keep`).
+pub fn get_separate_archived() -> bool {
+    dotenv::var("ARCHIVED_POLICY").is_ok_and(|value| value == "separate")
+}
+
+/// How favorites stand out in the library.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FavoritePolicy {
+    None,
+    #[default]
+    Rating,
+    Folder,
+}
+
+/// Reads `FAVORITE_POLICY` (`none`, `rating` or `folder`).
+pub fn get_favorite_policy() -> FavoritePolicy {
+    match dotenv::var("FAVORITE_POLICY").unwrap_or_default().as_str() {
+        "none" => FavoritePolicy::None,
+        "folder" => FavoritePolicy::Folder,
+        _ => FavoritePolicy::Rating,
+    }
+}
+
+/// Reads `PATH_TEMPLATE`, where files are placed below the target folder.
+pub fn get_path_template() -> String {
+    dotenv::var("PATH_TEMPLATE")
+        .ok()
+        .filter(|template| !template.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PATH_TEMPLATE.to_owned())
+}
+
+/// Reads `MONTH_LOCALE` (`de_DE`, `fr_FR`, ...), the language of month names
+/// in paths. English when unset.
+pub fn get_month_locale() -> Locale {
+    dotenv::var("MONTH_LOCALE")
+        .ok()
+        .and_then(|locale| locale.parse().ok())
+        .unwrap_or(Locale::en_US)
+}
+
+/// Reads `SOURCE_ACCOUNT`, the name of the Google account the archives come
+/// from, for the `{account}` path token.
+pub fn get_source_account() -> Option<String> {
+    dotenv::var("SOURCE_ACCOUNT").ok().filter(|account| !account.is_empty())
+}
+
+/// How files get from the extraction area into the library.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlacementMode {
+    #[default]
+    Move,
+    Copy,
+    Hardlink,
+    Reflink,
+}
+
+impl PlacementMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlacementMode::Move => "move",
+            PlacementMode::Copy => "copy",
+            PlacementMode::Hardlink => "hardlink",
+            PlacementMode::Reflink => "reflink",
+        }
+    }
+}
+
+/// Reads `PLACEMENT_MODE` (`move`, `copy`, `hardlink` or `reflink`). All but
+/// `move` leave the extracted files as they were, so a run can be repeated.
+/// Hard links are only made for files that are not written to and only while
+/// `SET_FILE_TIMES` is `none`, other files are copied.
+pub fn get_placement_mode() -> PlacementMode {
+    match dotenv::var("PLACEMENT_MODE").unwrap_or_default().as_str() {
+        "copy" => PlacementMode::Copy,
+        "hardlink" => PlacementMode::Hardlink,
+        "reflink" => PlacementMode::Reflink,
+        _ => PlacementMode::Move,
+    }
+}
+
+/// Reads `NEAR_DUPLICATE_DISTANCE`, how many of the 64 bits of a perceptual
+/// hash may differ for two images to count as the same shot. 6 when unset.
+pub fn get_near_duplicate_distance() -> u32 {
+    dotenv::var("NEAR_DUPLICATE_DISTANCE")
+        .ok()
+        .and_then(|distance| distance.parse().ok())
+        .unwrap_or(6)
+}
+
+/// Reads `DRY_RUN`, whether starting the pipeline only plans what it would do
+/// with the archives already on disk.
+pub fn get_dry_run() -> bool {
+    dotenv::var("DRY_RUN").is_ok_and(|value| value == "true")
+}
+
+/// Reads `PLAN_FILE`, a plan written by a dry run whose destinations a real run
+/// follows.
+pub fn get_plan_file() -> Option<PathBuf> {
+    dotenv::var("PLAN_FILE").ok().filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+/// Which photo server an export is arranged for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportProfile {
+    #[default]
+    Immich,
+    PhotoPrism,
+}
+
+impl ExportProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportProfile::Immich => "immich",
+            ExportProfile::PhotoPrism => "photoprism",
+        }
+    }
+}
+
+/// Reads `EXPORT_PROFILE` (`immich` or `photoprism`).
+pub fn get_export_profile() -> ExportProfile {
+    match dotenv::var("EXPORT_PROFILE").unwrap_or_default().as_str() {
+        "photoprism" => ExportProfile::PhotoPrism,
+        _ => ExportProfile::Immich,
+    }
+}
+
+/// Reads `EXPORT_FOLDER`, where exports are written. A folder per profile in
+/// `<target folder>-export`, next to the target folder, when unset.
+pub fn get_export_folder() -> Option<PathBuf> {
+    dotenv::var("EXPORT_FOLDER").ok().filter(|path| !path.is_empty()).map(PathBuf::from)
+}
+
+/// Reads `EXPORT_MODE` (`hardlink`, `reflink` or `copy`), how exported files
+/// share their bytes with the library.
+pub fn get_export_mode() -> PlacementMode {
+    match dotenv::var("EXPORT_MODE").unwrap_or_default().as_str() {
+        "copy" => PlacementMode::Copy,
+        "reflink" => PlacementMode::Reflink,
+        _ => PlacementMode::Hardlink,
+    }
+}
@@ -0,0 +1,134 @@
+use crate::date_resolver::ResolvedDate;
+use crate::file_list_widget::GeoData;
+use crate::metadata_writer::{is_exif_writable, read_exif_offset};
+use crate::settings::get_default_timezone;
+use chrono::{FixedOffset, Offset, TimeZone};
+use chrono_tz::Tz;
+use std::sync::LazyLock;
+use tzf_rs::DefaultFinder;
+
+/// Timezone boundaries compiled into the binary, loaded on first use.
+static TIMEZONE_FINDER: LazyLock<DefaultFinder> = LazyLock::new(DefaultFinder::new);
+
+/// The zone a location lies in.
+pub fn timezone_at(location: GeoData) -> Option<Tz> {
+    if !location.is_known() {
+        return None;
+    }
+    TIMEZONE_FINDER
+        .get_tz_name(location.longitude, location.latitude)
+        .parse()
+        .ok()
+}
+
+/// The offset `zone` had when the photo was taken, so daylight saving time is
+/// accounted for.
+pub fn offset_in(zone: Tz, taken: &ResolvedDate) -> FixedOffset {
+    let naive = taken.taken.naive_utc();
+    let from_utc = || zone.offset_from_utc_datetime(&naive).fix();
+    if taken.wall_clock {
+        zone.offset_from_local_datetime(&naive)
+            .earliest()
+            .map(|offset| offset.fix())
+            .unwrap_or_else(from_utc)
+    } else {
+        from_utc()
+    }
+}
+
+/// Works out the offset a photo was taken in, from the offset the camera wrote,
+/// the zone of where it was taken or the configured default zone, in that
+/// order. Falls back to UTC.
+pub async fn resolve_offset(
+    path: &str,
+    extension: &str,
+    location: Option<GeoData>,
+    taken: &ResolvedDate,
+) -> FixedOffset {
+    let exif_offset = if is_exif_writable(extension) {
+        read_exif_offset(path).await.ok().flatten()
+    } else {
+        None
+    };
+    resolve_known_offset(exif_offset, location, taken)
+}
+
+/// Like [`resolve_offset`] for a file that was already read, with
+/// `exif_offset` holding the offset the camera wrote.
+pub fn resolve_known_offset(
+    exif_offset: Option<FixedOffset>,
+    location: Option<GeoData>,
+    taken: &ResolvedDate,
+) -> FixedOffset {
+    exif_offset.unwrap_or_else(|| {
+        location
+            .and_then(timezone_at)
+            .or_else(get_default_timezone)
+            .map(|zone| offset_in(zone, taken))
+            .unwrap_or(FixedOffset::east_opt(0).unwrap())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date_resolver::{DateConfidence, DateSource};
+    use crate::metadata_writer::tests::jpeg;
+    use crate::metadata_writer::write_exif_taken_date;
+    use chrono::{DateTime, Utc};
+
+    const PARIS: GeoData = GeoData {
+        latitude: 48.8566,
+        longitude: 2.3522,
+        altitude: 0.0,
+    };
+
+    fn hours(hours: i32) -> FixedOffset {
+        FixedOffset::east_opt(hours * 3600).unwrap()
+    }
+
+    fn taken_at(taken: &str, wall_clock: bool) -> ResolvedDate {
+        ResolvedDate {
+            taken: taken.parse::<DateTime<Utc>>().unwrap(),
+            source: DateSource::Json,
+            confidence: DateConfidence::High,
+            wall_clock,
+            offset: None,
+        }
+    }
+
+    #[test]
+    fn prefers_the_camera_offset_over_the_location() {
+        let taken = taken_at("2019-07-04T12:30:00Z", false);
+        let offset = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+        assert_eq!(resolve_known_offset(Some(offset), Some(PARIS), &taken), offset);
+    }
+
+    #[test]
+    fn takes_daylight_saving_time_at_the_location_into_account() {
+        assert_eq!(resolve_known_offset(None, Some(PARIS), &taken_at("2019-07-04T12:30:00Z", false)), hours(2));
+        assert_eq!(resolve_known_offset(None, Some(PARIS), &taken_at("2019-01-04T12:30:00Z", false)), hours(1));
+    }
+
+    #[test]
+    fn reads_a_wall_clock_time_as_local_time() {
+        // Clocks went back at 03:00 that night, 02:30 happened in both offsets.
+        assert_eq!(resolve_known_offset(None, Some(PARIS), &taken_at("2019-10-27T02:30:00Z", true)), hours(2));
+        assert_eq!(resolve_known_offset(None, Some(PARIS), &taken_at("2019-10-27T02:30:00Z", false)), hours(1));
+        // Clocks went forward at 02:00, 02:30 never happened there.
+        assert_eq!(resolve_known_offset(None, Some(PARIS), &taken_at("2019-03-31T02:30:00Z", true)), hours(2));
+    }
+
+    #[tokio::test]
+    async fn reads_the_offset_from_files_that_carry_exif() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("IMG_0001.jpg");
+        std::fs::write(&path, jpeg()).unwrap();
+        let taken = taken_at("2019-07-04T12:30:00Z", false);
+        write_exif_taken_date(&path, taken.taken.with_timezone(&hours(9))).await.unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(resolve_offset(path, "jpg", Some(PARIS), &taken).await, hours(9));
+        assert_eq!(resolve_offset(path, "mp4", Some(PARIS), &taken).await, hours(2));
+    }
+}
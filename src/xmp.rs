@@ -0,0 +1,206 @@
+use crate::file_list_widget::GeoData;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Camera raw formats, never modified in place.
+pub const RAW_EXTENSIONS: [&str; 11] = [
+    "cr2", "cr3", "crw", "nef", "nrw", "arw", "dng", "orf", "rw2", "raf", "pef",
+];
+
+/// Videos whose containers we do not rewrite.
+pub const XMP_VIDEO_EXTENSIONS: [&str; 9] =
+    ["mp4", "mov", "m4v", "3gp", "avi", "mkv", "mts", "m2ts", "wmv"];
+
+/// Whether the metadata for a file goes into an `.xmp` next to it rather than into the file.
+pub fn needs_xmp_sidecar(extension: &str) -> bool {
+    let extension = extension.to_lowercase();
+    RAW_EXTENSIONS.contains(&extension.as_str())
+        || XMP_VIDEO_EXTENSIONS.contains(&extension.as_str())
+}
+
+/// What we know about a photo from its Takeout json.
+#[derive(Debug, Clone, Default)]
+pub struct XmpData {
+    /// In the offset the photo was taken in, which XMP dates carry along.
+    pub taken: Option<DateTime<FixedOffset>>,
+    pub location: Option<GeoData>,
+    pub description: String,
+    pub people: Vec<String>,
+    pub favorite: bool,
+}
+
+/// darktable and digiKam look for `IMG_1.CR2.xmp`, Lightroom for `IMG_1.xmp`.
+pub fn xmp_path(media_path: &Path, keep_extension: bool) -> PathBuf {
+    if keep_extension {
+        let mut name = media_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".xmp");
+        media_path.with_file_name(name)
+    } else {
+        media_path.with_extension("xmp")
+    }
+}
+
+pub async fn write_xmp_sidecar(media_path: &Path, keep_extension: bool, data: &XmpData) -> Result<PathBuf> {
+    let path = xmp_path(media_path, keep_extension);
+    fs::write(&path, to_xmp(data)).await?;
+    Ok(path)
+}
+
+/// Renders an XMP packet using the namespaces digiKam, darktable and Lightroom
+/// all read: `exif`/`xmp`/`photoshop` for dates, `exif` GPS, `dc:description`,
+/// `Iptc4xmpExt:PersonInImage` plus keywords for people and `xmp:Rating`.
+pub fn to_xmp(data: &XmpData) -> String {
+    let mut properties = String::new();
+    if let Some(taken) = data.taken {
+        let taken = taken.to_rfc3339_opts(SecondsFormat::Secs, false);
+        properties.push_str(&format!(
+            "   exif:DateTimeOriginal=\"{taken}\"\n   xmp:CreateDate=\"{taken}\"\n   photoshop:DateCreated=\"{taken}\"\n"
+        ));
+    }
+    if let Some(location) = data.location.filter(GeoData::is_known) {
+        properties.push_str(&format!(
+            "   exif:GPSVersionID=\"2.3.0.0\"\n   exif:GPSLatitude=\"{}\"\n   exif:GPSLongitude=\"{}\"\n   exif:GPSAltitudeRef=\"{}\"\n   exif:GPSAltitude=\"{}/100\"\n",
+            to_xmp_coordinate(location.latitude, 'N', 'S'),
+            to_xmp_coordinate(location.longitude, 'E', 'W'),
+            if location.altitude < 0.0 { 1 } else { 0 },
+            (location.altitude.abs() * 100.0).round() as u64,
+        ));
+    }
+    if data.favorite {
+        properties.push_str("   xmp:Rating=\"5\"\n");
+    }
+
+    let mut elements = String::new();
+    if !data.description.is_empty() {
+        elements.push_str(&format!(
+            "   <dc:description>\n    <rdf:Alt>\n     <rdf:li xml:lang=\"x-default\">{}</rdf:li>\n    </rdf:Alt>\n   </dc:description>\n",
+            escape(&data.description)
+        ));
+    }
+    if !data.people.is_empty() {
+        let bag = |prefix: &str, separator: &str| {
+            data.people.iter().fold(String::new(), |acc, name| {
+                format!("{}     <rdf:li>{}{}{}</rdf:li>\n", acc, prefix, separator, escape(name))
+            })
+        };
+        elements.push_str(&format!(
+            "   <Iptc4xmpExt:PersonInImage>\n    <rdf:Bag>\n{}    </rdf:Bag>\n   </Iptc4xmpExt:PersonInImage>\n",
+            bag("", "")
+        ));
+        elements.push_str(&format!(
+            "   <dc:subject>\n    <rdf:Bag>\n{}    </rdf:Bag>\n   </dc:subject>\n",
+            bag("", "")
+        ));
+        elements.push_str(&format!(
+            "   <lr:hierarchicalSubject>\n    <rdf:Bag>\n{}    </rdf:Bag>\n   </lr:hierarchicalSubject>\n",
+            bag("People", "|")
+        ));
+        elements.push_str(&format!(
+            "   <digiKam:TagsList>\n    <rdf:Seq>\n{}    </rdf:Seq>\n   </digiKam:TagsList>\n",
+            bag("People", "/")
+        ));
+    }
+
+    format!(
+        r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="takeout-fixer">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+   xmlns:exif="http://ns.adobe.com/exif/1.0/"
+   xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+   xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/"
+   xmlns:dc="http://purl.org/dc/elements/1.1/"
+   xmlns:Iptc4xmpExt="http://iptc.org/std/Iptc4xmpExt/2008-02-29/"
+   xmlns:lr="http://ns.adobe.com/lightroom/1.0/"
+   xmlns:digiKam="http://www.digikam.org/ns/1.0/"
+{}  >
+{}  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#,
+        properties, elements
+    )
+}
+
+/// XMP writes GPS coordinates as `DDD,MM.mmmmmmK`.
+fn to_xmp_coordinate(coordinate: f64, positive: char, negative: char) -> String {
+    let reference = if coordinate < 0.0 { negative } else { positive };
+    let coordinate = coordinate.abs();
+    let degrees = coordinate.trunc();
+    let minutes = (coordinate - degrees) * 60.0;
+    format!("{},{:.6}{}", degrees as u32, minutes, reference)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn names_the_sidecar_after_the_file() {
+        let media_path = Path::new("/photos/2019/IMG_0001.CR2");
+        assert_eq!(xmp_path(media_path, true), Path::new("/photos/2019/IMG_0001.CR2.xmp"));
+        assert_eq!(xmp_path(media_path, false), Path::new("/photos/2019/IMG_0001.xmp"));
+    }
+
+    #[test]
+    fn renders_what_the_json_knows() {
+        let data = XmpData {
+            taken: Some(
+                Utc.with_ymd_and_hms(2019, 7, 4, 21, 30, 0)
+                    .unwrap()
+                    .with_timezone(&FixedOffset::east_opt(2 * 3600).unwrap()),
+            ),
+            location: Some(GeoData {
+                latitude: 48.8584,
+                longitude: -2.2945,
+                altitude: -12.5,
+            }),
+            description: "Fish & <chips>".to_owned(),
+            people: vec!["Ann \"Annie\" Lee".to_owned()],
+            favorite: true,
+        };
+        let xmp = to_xmp(&data);
+        let namespaces = ["exif", "xmp", "photoshop", "dc", "Iptc4xmpExt", "lr", "digiKam"];
+        for namespace in namespaces.map(|prefix| format!("xmlns:{prefix}=")) {
+            assert!(xmp.contains(&namespace), "{namespace}");
+        }
+        for property in [
+            "exif:DateTimeOriginal=\"2019-07-04T23:30:00+02:00\"",
+            "xmp:CreateDate=\"2019-07-04T23:30:00+02:00\"",
+            "photoshop:DateCreated=\"2019-07-04T23:30:00+02:00\"",
+            "exif:GPSLatitude=\"48,51.504000N\"",
+            "exif:GPSLongitude=\"2,17.670000W\"",
+            "exif:GPSAltitudeRef=\"1\"",
+            "exif:GPSAltitude=\"1250/100\"",
+            "xmp:Rating=\"5\"",
+            "<rdf:li xml:lang=\"x-default\">Fish &amp; &lt;chips&gt;</rdf:li>",
+            "<rdf:li>Ann &quot;Annie&quot; Lee</rdf:li>",
+            "<rdf:li>People|Ann &quot;Annie&quot; Lee</rdf:li>",
+            "<rdf:li>People/Ann &quot;Annie&quot; Lee</rdf:li>",
+        ] {
+            assert!(xmp.contains(property), "{property} in {xmp}");
+        }
+    }
+
+    #[test]
+    fn leaves_out_what_the_json_does_not_know() {
+        let xmp = to_xmp(&XmpData {
+            location: Some(GeoData::default()),
+            ..Default::default()
+        });
+        for property in ["exif:DateTimeOriginal", "exif:GPSLatitude", "xmp:Rating", "<dc:description>", "<dc:subject>"] {
+            assert!(!xmp.contains(property), "{property} in {xmp}");
+        }
+    }
+}